@@ -0,0 +1,9 @@
+// Generates the gRPC service in `src/grpc.rs` from `proto/`. Uses the `protoc` shipped by
+// `protoc-bin-vendored`, so none needs installing, unless `PROTOC` names another.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::compile_protos("proto/questions.proto")?;
+    Ok(())
+}
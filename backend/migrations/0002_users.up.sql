@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS users (
+  id serial PRIMARY KEY,
+  email VARCHAR (255) NOT NULL,
+  full_name VARCHAR (255) NOT NULL,
+  oidc_issuer TEXT,
+  oidc_subject TEXT,
+  created_on TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (oidc_issuer, oidc_subject)
+);
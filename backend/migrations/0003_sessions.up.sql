@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS sessions (
+  id TEXT PRIMARY KEY,
+  data JSONB NOT NULL,
+  expiry_date TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_expiry_date ON sessions (expiry_date);
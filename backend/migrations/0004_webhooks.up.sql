@@ -0,0 +1,37 @@
+CREATE TABLE IF NOT EXISTS webhooks (
+  id serial PRIMARY KEY,
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  events TEXT [] NOT NULL DEFAULT '{}',
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+  id bigserial PRIMARY KEY,
+  webhook_id integer NOT NULL REFERENCES webhooks ON DELETE CASCADE,
+  event_kind TEXT NOT NULL,
+  payload JSONB NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts integer NOT NULL DEFAULT 0,
+  next_attempt_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  delivered_on TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due
+  ON webhook_deliveries (next_attempt_on) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook
+  ON webhook_deliveries (webhook_id, id DESC);
+
+CREATE TABLE IF NOT EXISTS webhook_attempts (
+  id bigserial PRIMARY KEY,
+  delivery_id bigint NOT NULL REFERENCES webhook_deliveries ON DELETE CASCADE,
+  attempted_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  response_status integer,
+  error TEXT,
+  duration_ms integer NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS webhook_attempts_delivery ON webhook_attempts (delivery_id);
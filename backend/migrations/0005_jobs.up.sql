@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS jobs (
+  id bigserial PRIMARY KEY,
+  kind TEXT NOT NULL,
+  payload JSONB NOT NULL DEFAULT '{}',
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'running', 'done', 'failed')),
+  attempts integer NOT NULL DEFAULT 0,
+  max_attempts integer NOT NULL DEFAULT 5,
+  run_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  locked_until TIMESTAMPTZ,
+  every_secs integer,
+  last_error TEXT,
+  created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  finished_on TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS jobs_due ON jobs (run_at) WHERE status IN ('pending', 'running');
+CREATE INDEX IF NOT EXISTS jobs_status ON jobs (status, id DESC);
+
+-- At most one queued run of each recurring job.
+CREATE UNIQUE INDEX IF NOT EXISTS jobs_recurring ON jobs (kind)
+  WHERE every_secs IS NOT NULL AND status IN ('pending', 'running');
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS question_watchers (
+  question_id TEXT NOT NULL,
+  email TEXT NOT NULL,
+  full_name TEXT NOT NULL,
+  author BOOLEAN NOT NULL DEFAULT FALSE,
+  created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (question_id, email)
+);
+
+CREATE TABLE IF NOT EXISTS notification_preferences (
+  email TEXT PRIMARY KEY,
+  email_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+  digest BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+  id bigserial PRIMARY KEY,
+  email TEXT NOT NULL,
+  full_name TEXT NOT NULL,
+  question_id TEXT NOT NULL,
+  answer_id TEXT NOT NULL,
+  answer_content TEXT NOT NULL,
+  digest BOOLEAN NOT NULL,
+  created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS notifications_digest
+  ON notifications (email) WHERE digest;
@@ -0,0 +1,3 @@
+-- The table may predate 0007 and still holds the tags, so only what 0007 added goes.
+DROP INDEX IF EXISTS tags_tag;
+ALTER TABLE tags DROP CONSTRAINT IF EXISTS tags_id_fkey;
@@ -0,0 +1,34 @@
+-- The service wrote to a tags table before any migration created one, so it may already exist
+-- without the keys below. Its rows are kept.
+CREATE TABLE IF NOT EXISTS tags (
+  id integer NOT NULL,
+  tag TEXT NOT NULL
+);
+
+-- Rows the keys would reject: incomplete ones, tags of questions that are gone, and repeats.
+DELETE FROM tags WHERE id IS NULL OR tag IS NULL;
+DELETE FROM tags t WHERE NOT EXISTS (SELECT 1 FROM questions q WHERE q.id = t.id);
+DELETE FROM tags a USING tags b WHERE a.id = b.id AND a.tag = b.tag AND a.ctid > b.ctid;
+
+ALTER TABLE tags ALTER COLUMN id SET NOT NULL, ALTER COLUMN tag SET NOT NULL;
+
+DO $$
+BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_constraint
+      WHERE conrelid = 'tags'::regclass AND contype = 'p') THEN
+    ALTER TABLE tags ADD CONSTRAINT tags_pkey PRIMARY KEY (id, tag);
+  END IF;
+  IF NOT EXISTS (SELECT 1 FROM pg_constraint
+      WHERE conrelid = 'tags'::regclass AND contype = 'f'
+        AND confrelid = 'questions'::regclass) THEN
+    ALTER TABLE tags ADD CONSTRAINT tags_id_fkey
+      FOREIGN KEY (id) REFERENCES questions ON DELETE CASCADE;
+  END IF;
+END $$;
+
+CREATE INDEX IF NOT EXISTS tags_tag ON tags (tag);
+
+-- Tags written to the original column before questions had a table of them.
+INSERT INTO tags (id, tag)
+  SELECT id, unnest(tags) FROM questions WHERE tags IS NOT NULL
+  ON CONFLICT DO NOTHING;
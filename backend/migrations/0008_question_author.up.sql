@@ -0,0 +1,6 @@
+ALTER TABLE questions ADD COLUMN IF NOT EXISTS author TEXT;
+
+-- Authors recorded only as watchers before questions had a column for them.
+UPDATE questions q SET author = w.full_name || ' <' || w.email || '>'
+  FROM question_watchers w
+  WHERE w.author AND w.question_id = q.id::TEXT AND q.author IS NULL;
@@ -6,11 +6,16 @@ use crate::appstate::HandlerAppState;
 use crate::auth::make_jwt_token;
 use crate::auth::Claims;
 use crate::auth::Registration;
-use crate::error::StoreError;
-use crate::types::question::Question;
+use crate::auth::{AuthBody, API_KEY_HEADER};
+use crate::error::Problem;
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::question::{Question, QuestionId};
+use crate::validation::{FieldError, ValidJson};
 use axum_core::response::IntoResponse;
 use error::StoreErr;
-use utoipa::OpenApi;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{Modify, OpenApi};
 
 // Implementing Axum 'IntoResponse' from shuttle.rs but with the Serialized Question
 pub enum ApiResponse {
@@ -55,18 +60,14 @@ impl IntoResponse for ApiError {
     /// Returns:
     ///
     /// A `Response` object is being returned based on the variant of the enum `self`. The
-    /// `into_response` method is being called on the tuple `(StatusCode, &str)` to convert it into a
-    /// `Response` object.
+    /// variant is mapped to a status-only `Problem`, which renders the response.
     fn into_response(self) -> Response {
-        match self {
-            Self::NotFound => (StatusCode::NOT_FOUND, "404 Not Found").into_response(),
-            Self::NotImplemented => {
-                (StatusCode::NOT_IMPLEMENTED, "501 Not Implemented").into_response()
-            }
-            Self::Failed => {
-                (StatusCode::EXPECTATION_FAILED, "417 Expectation Failed").into_response()
-            }
-        }
+        let status: StatusCode = match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::Failed => StatusCode::EXPECTATION_FAILED,
+        };
+        Problem::from_status(status).into_response()
     }
 }
 
@@ -79,19 +80,116 @@ impl IntoResponse for ApiError {
         post_question,
         delete_question,
         update_question,
+        post_answer,
+        register,
+        register_get,
+        crate::oidc::oidc_login,
+        crate::oidc::oidc_callback,
+        crate::health::healthz,
+        crate::health::readyz,
+        crate::metrics::metrics,
+        crate::events::events,
+        crate::ws::ws,
+        crate::webhooks::list_webhooks,
+        crate::webhooks::create_webhook,
+        crate::webhooks::update_webhook,
+        crate::webhooks::delete_webhook,
+        crate::webhooks::list_deliveries,
+        crate::webhooks::get_delivery,
+        crate::webhooks::redeliver,
+        crate::jobs::list_jobs,
+        crate::notifications::watch_question,
+        crate::notifications::unwatch_question,
+        crate::notifications::get_preferences,
+        crate::notifications::put_preferences,
+        crate::notifications::unsubscribe_form,
+        crate::notifications::unsubscribe,
+        crate::graphql::graphql,
+        crate::graphql::graphiql,
+        crate::v2::list_questions,
+        crate::v2::get_question,
+        crate::v2::create_question,
+        crate::v2::update_question,
+        crate::v2::delete_question,
+        crate::v2::list_answers,
+        crate::v2::create_answer,
+        crate::v2::get_answer,
     ),
     components(
-        schemas(StoreError)
+        schemas(Problem, FieldError, crate::events::Event, crate::events::EventKind,
+            crate::ws::ClientMessage, crate::ws::ServerMessage,
+            crate::webhooks::Webhook, crate::webhooks::WebhookSpec,
+            crate::webhooks::CreatedWebhook, crate::webhooks::Delivery,
+            crate::webhooks::DeliveryLog, crate::webhooks::Attempt, crate::jobs::Job,
+            Question, Answer, NewAnswer, AuthBody, Registration,
+            crate::notifications::Preferences, crate::health::HealthReport,
+            crate::health::ComponentStatus, crate::health::Status,
+            question_api::QuestionResource, question_api::AnswerResource,
+            question_api::QuestionInput, question_api::page::QuestionData,
+            question_api::page::AnswerData, question_api::page::QuestionPage,
+            question_api::page::AnswerPage, question_api::Pagination, question_api::Links)
     ),
+    modifiers(&SecuritySchemes, &DeprecateV1),
     tags(
-        (name = "question", description = "Question API")
+        (name = "question", description = "Question API"),
+        (name = "v2", description = "Question API, version 2"),
+        (name = "auth", description = "Getting a token"),
+        (name = "events", description = "Live question and answer events"),
+        (name = "notifications", description = "Email notification settings"),
+        (name = "webhooks", description = "Outbound webhooks, for administrators"),
+        (name = "jobs", description = "Background jobs, for administrators"),
+        (name = "graphql", description = "GraphQL API"),
+        (name = "operations", description = "Health checks and metrics")
     )
 )]
 pub struct ApiDoc;
 
+/// Declares how callers send the token from `register` or the OIDC login: as a bearer token, or
+/// in the API key header.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("`Authorization: Bearer <access_token>`"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                API_KEY_HEADER,
+                "The same `access_token`, for clients that can't set `Authorization`",
+            ))),
+        );
+    }
+}
+
+/// Marks the `/api/v1` operations deprecated, as their `Deprecation` header does.
+struct DeprecateV1;
+
+impl Modify for DeprecateV1 {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            if path.starts_with("/api/v1/") {
+                for operation in item.operations.values_mut() {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+        }
+    }
+}
+
 #[utoipa::path(
     get,
     path = "/api/v1/questions",
+    tag = "question",
     responses(
         (status = 200, description = "List questions", body = [Question])
     )
@@ -101,32 +199,36 @@ pub async fn questions(State(appstate): HandlerAppState) -> Response {
         appstate.read().await.store.get_questions().await;
     match questions {
         Ok(questions) => Json(questions).into_response(),
-        Err(e) => StoreError::response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     get,
     path = "/api/v1/question",
+    tag = "question",
     responses(
         (status = 200, description = "Return random question", body = Question),
-        (status = 204, description = "Store is empty", body = StoreError)
+        (status = 404, description = "Store is empty", body = Problem,
+            content_type = "application/problem+json")
     )
 )]
 
 pub async fn question(State(appstate): HandlerAppState) -> Response {
     match appstate.read().await.store.get_random().await {
-        Ok(question) => question.into_response(),
-        Err(e) => StoreError::response(StatusCode::NO_CONTENT, e),
+        Ok(question) => Json(question).into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     get,
     path = "/api/v1/question/{id}",
+    tag = "question",
     responses(
         (status = 200, description = "Return specified question", body = Question),
-        (status = 204, description = "No question with this id", body = StoreError),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
     )
 )]
 pub async fn get_question(
@@ -134,40 +236,99 @@ pub async fn get_question(
     Path(question_id): Path<String>,
 ) -> Response {
     match appstate.read().await.store.get(&question_id).await {
-        Ok(question) => question.into_response(),
-        Err(e) => StoreError::response(StatusCode::NO_CONTENT, e),
+        Ok(question) => Json(question).into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     post,
     path = "/api/v1/question/add",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
     request_body(
-        content = inline(StoreError),
-        description = "Question to add"
+        content = Question,
+        description = "Question to add; the server assigns its id"
     ),
     responses(
-        (status = 201, description = "Added question", body = ()),
-        (status = 400, description = "Bad request", body = StoreError)
+        (status = 201, description = "Added question, with the id it was given", body = Question),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 409, description = "Question conflicts with an existing one", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid question", body = Problem,
+            content_type = "application/problem+json"),
     )
 )]
 pub async fn post_question(
+    claims: Claims,
+    State(appstate): HandlerAppState,
+    ValidJson(question): ValidJson<Question>,
+) -> Response {
+    let appstate = appstate.write().await;
+    match appstate
+        .store
+        .add_question(question, Some(&claims.sub))
+        .await
+    {
+        Ok(question) => (StatusCode::CREATED, Json(question)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/question/{id}/answer",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(
+        content = NewAnswer,
+        description = "Answer to add"
+    ),
+    responses(
+        (status = 201, description = "Added answer", body = Answer),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid answer", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn post_answer(
     _claims: Claims,
     State(appstate): HandlerAppState,
-    Json(question): Json<Question>,
+    Path(question_id): Path<String>,
+    ValidJson(answer): ValidJson<NewAnswer>,
 ) -> Response {
-    match appstate.write().await.store.add_question(question, 1).await {
-        Ok(()) => StatusCode::CREATED.into_response(),
-        Err(e) => StoreError::response(StatusCode::BAD_REQUEST, e),
+    let question_id: QuestionId = QuestionId(question_id);
+    match appstate
+        .read()
+        .await
+        .store
+        .add_answer(&question_id, &answer.content)
+        .await
+    {
+        Ok(answer) => (StatusCode::CREATED, Json(answer)).into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     delete,
     path = "/api/v1/question/{id}",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
     responses(
         (status = 200, description = "Deleted question", body = ()),
-        (status = 400, description = "Bad request", body = StoreError),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "Question not found", body = Problem,
+            content_type = "application/problem+json"),
     )
 )]
 pub async fn delete_question(
@@ -183,69 +344,94 @@ pub async fn delete_question(
         .await
     {
         Ok(()) => StatusCode::OK.into_response(),
-        Err(e) => StoreError::response(StatusCode::BAD_REQUEST, e),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     put,
     path = "/api/v1/question/{id}",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
     request_body(
-        content = inline(StoreError),
+        content = Question,
         description = "Question to update"
     ),
     responses(
         (status = 200, description = "Updated question", body = ()),
-        (status = 400, description = "Bad request", body = StoreError),
-        (status = 404, description = "Question not found", body = StoreError),
-        (status = 422, description = "Unprocessable entity", body = StoreError),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "Question not found", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid question", body = Problem,
+            content_type = "application/problem+json"),
     )
 )]
 pub async fn update_question(
     _claims: Claims,
     State(appstate): HandlerAppState,
     Path(question_id): Path<String>,
-    Json(question): Json<Question>,
+    ValidJson(question): ValidJson<Question>,
 ) -> Response {
     match appstate
         .write()
         .await
         .store
-        .update_question(&question_id, question, 1)
+        .update_question(&question_id, question)
         .await
     {
         Ok(_) => StatusCode::OK.into_response(),
-        Err(StoreErr::DatabaseQueryError(e)) => StoreError::response(
-            StatusCode::UNPROCESSABLE_ENTITY,
-            StoreErr::DatabaseQueryError(e),
-        ),
-        Err(StoreErr::QuestionNotFound(_)) => StoreError::response(
-            StatusCode::NOT_FOUND,
-            StoreErr::QuestionNotFound(question_id.clone()),
-        ),
-        Err(e) => StoreError::response(StatusCode::BAD_REQUEST, e),
+        Err(e) => e.into_response(),
     }
 }
 
 #[utoipa::path(
     post,
     path = "/api/v1/register",
+    tag = "auth",
     request_body(
-        content = inline(Registration),
+        content = Registration,
         description = "Get an API key"
     ),
     responses(
         (status = 200, description = "JSON Web Token", body = AuthBody),
-        (status = 401, description = "Registration failed", body = AuthError),
+        (status = 401, description = "Registration failed", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid registration", body = Problem,
+            content_type = "application/problem+json"),
     )
 )]
 pub async fn register(
     State(appstate): HandlerAppState,
-    Json(registration): Json<Registration>,
+    ValidJson(registration): ValidJson<Registration>,
 ) -> Response {
     let appstate = appstate.read().await;
     match make_jwt_token(&appstate, &registration) {
         Err(e) => e.into_response(),
-        Ok(token) => (StatusCode::OK, token).into_response(),
+        Ok(token) => (StatusCode::OK, Json(token)).into_response(),
     }
 }
+
+/// `GET /api/v1/register` is also routed to [`register`], with the same JSON body, for clients
+/// written before it took `POST`. This only documents it.
+#[utoipa::path(
+    get,
+    path = "/api/v1/register",
+    tag = "auth",
+    operation_id = "register_get",
+    request_body(
+        content = Registration,
+        description = "Get an API key; use `POST` instead"
+    ),
+    responses(
+        (status = 200, description = "JSON Web Token", body = AuthBody),
+        (status = 401, description = "Registration failed", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid registration", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+#[deprecated = "use POST /api/v1/register"]
+fn register_get() {}
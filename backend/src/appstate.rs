@@ -1,10 +1,21 @@
 use crate::auth::JwtKeys;
+use crate::graphql::{self, QuestionSchema};
+use crate::oidc::OidcClient;
+use crate::shutdown::Shutdown;
+use crate::ws::Relay;
 use crate::*;
 
 pub struct AppState {
     pub store: Store,
     pub jwt_keys: JwtKeys,
     pub reg_key: String,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub relay: Arc<Relay>,
+    /// Lowercased emails of the users allowed to use the admin endpoints.
+    pub admins: HashSet<String>,
+    pub graphql: QuestionSchema,
+    /// Cancelled on a shutdown signal, ending event streams and websockets.
+    pub shutdown: Shutdown,
 }
 
 pub type SharedAppState = Arc<RwLock<AppState>>;
@@ -12,11 +23,23 @@ pub type SharedAppState = Arc<RwLock<AppState>>;
 pub type HandlerAppState = State<SharedAppState>;
 
 impl AppState {
-    pub fn new(store: Store, jwt_keys: JwtKeys, reg_key: String) -> Self {
+    pub fn new(
+        store: Store,
+        jwt_keys: JwtKeys,
+        reg_key: String,
+        oidc: Option<Arc<OidcClient>>,
+        admins: HashSet<String>,
+        shutdown: Shutdown,
+    ) -> Self {
         Self {
             store,
             jwt_keys,
             reg_key,
+            oidc,
+            relay: Arc::default(),
+            admins,
+            graphql: graphql::schema(),
+            shutdown,
         }
     }
 }
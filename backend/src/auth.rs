@@ -1,7 +1,8 @@
 // From knock-knock/src/authjwt.rs.
 // From https://github.com/shuttle-hq/shuttle-examples/axum/jwt-authentication
 
-use crate::error::error_schema;
+use crate::error::Problem;
+use crate::metrics::METRICS;
 use crate::*;
 use chrono::Utc;
 
@@ -11,15 +12,19 @@ use axum_extra::TypedHeader;
 use chrono::TimeDelta;
 use headers::authorization::Bearer;
 use headers::Authorization;
+use hmac::{Hmac, Mac};
 use jsonwebtoken::{DecodingKey, EncodingKey};
+use sha2::Sha256;
 use std::error::Error;
 use utoipa::openapi::schema::Schema;
 use utoipa::openapi::RefOr;
 use utoipa::ToSchema;
 
+#[derive(Clone)]
 pub struct JwtKeys {
     encoding: EncodingKey,
     decoding: DecodingKey,
+    secret: Arc<[u8]>,
 }
 
 impl JwtKeys {
@@ -27,8 +32,40 @@ impl JwtKeys {
         Self {
             encoding: EncodingKey::from_secret(secret),
             decoding: DecodingKey::from_secret(secret),
+            secret: Arc::from(secret),
         }
     }
+
+    fn mac(&self, purpose: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac: Hmac<Sha256> =
+            Hmac::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(purpose.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    /// Hex signature of `value` for `purpose`, for links that mustn't be forgeable, such as
+    /// unsubscribe links. Signatures for one purpose aren't valid for another.
+    pub fn sign(&self, purpose: &str, value: &str) -> String {
+        hex::encode(self.mac(purpose, value).finalize().into_bytes())
+    }
+
+    /// Check a signature made by [`JwtKeys::sign`], in constant time.
+    pub fn verify(&self, purpose: &str, value: &str, signature: &str) -> bool {
+        hex::decode(signature)
+            .is_ok_and(|signature| self.mac(purpose, value).verify_slice(&signature).is_ok())
+    }
+
+    /// Validate one of our own access tokens and return its claims.
+    pub fn decode_claims(&self, token: &str) -> Result<Claims, AuthError> {
+        use jsonwebtoken::{decode, Algorithm, Validation};
+
+        let validation: Validation = Validation::new(Algorithm::HS512);
+        decode::<Claims>(token, &self.decoding, &validation)
+            .map(|token_data| token_data.claims)
+            .map_err(|_| AuthError::Registration)
+    }
 }
 
 pub async fn read_secret(env_var: &str) -> Result<String, Box<dyn Error>> {
@@ -50,37 +87,18 @@ pub enum AuthError {
     TokenCreation,
     #[error("registration error")]
     Registration,
+    #[error("invalid login state")]
+    LoginState,
+    #[error("identity provider error")]
+    IdentityProvider,
+    #[error("login method not configured")]
+    NotConfigured,
+    #[error("not an administrator")]
+    Forbidden,
 }
 
-impl<'s> ToSchema<'s> for AuthError {
-    fn schema() -> (&'s str, RefOr<Schema>) {
-        let example: serde_json::Value = serde_json::json!({
-            "status":"401","error":"wrong credentials"
-        });
-        error_schema("AuthError", example)
-    }
-}
-
-#[derive(Debug, Serialize, ToSchema)]
-pub struct AuthBody {
-    access_token: String,
-    token_type: String,
-}
-
-impl AuthBody {
-    fn new(access_token: String) -> Self {
-        Self {
-            access_token,
-            token_type: "Bearer".to_string(),
-        }
-    }
-}
-
-impl IntoResponse for AuthBody {
-    fn into_response(self) -> Response {
-        Json(serde_json::json!(self)).into_response()
-    }
-}
+// Registration and the token it returns are shared with the frontend.
+pub use question_api::auth::{AuthBody, Registration, API_KEY_HEADER};
 
 /*
 #[derive(Debug, Deserialize, ToSchema)]
@@ -98,77 +116,256 @@ impl FromRequestParts<SharedAppState> for Claims {
         parts: &mut Parts,
         state: &SharedAppState,
     ) -> Result<Self, Self::Rejection> {
-        use jsonwebtoken::{decode, Algorithm, Validation};
-
-        // Extract the token from the authorization header
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| AuthError::InvalidToken)?;
+        // Extract the token from the authorization header, or else the API key header
+        let token: String = match parts.extract::<TypedHeader<Authorization<Bearer>>>().await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+            Err(_) => parts
+                .headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .ok_or(AuthError::InvalidToken)?
+                .to_string(),
+        };
         // Decode the user data
         let appstate: tokio::sync::RwLockReadGuard<AppState> = state.read().await;
-        let decoding_key: &DecodingKey = &appstate.jwt_keys.decoding;
-        let validation: Validation = Validation::new(Algorithm::HS512);
-        let result: Result<jsonwebtoken::TokenData<Claims>, jsonwebtoken::errors::Error> =
-            decode::<Claims>(bearer.token(), decoding_key, &validation);
-        let token_data: jsonwebtoken::TokenData<Claims> =
-            result.map_err(|_| AuthError::Registration)?;
-        Ok(token_data.claims)
+        appstate.jwt_keys.decode_claims(&token)
     }
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthError::Registration => (StatusCode::UNAUTHORIZED, "Invalid registration"),
-            AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
-        };
-        let body = Json(serde_json::json!({
-            "status": status.as_u16(),
-            "error": error_message,
-        }));
-        (status, body).into_response()
+/// Claims of a caller whose email is listed in `--admin-emails` and was verified by the
+/// identity provider. Anyone with the registration password can register under any address,
+/// so self-registered tokens are never admins.
+#[derive(Debug, Clone)]
+pub struct Admin(pub Claims);
+
+#[async_trait]
+impl FromRequestParts<SharedAppState> for Admin {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedAppState,
+    ) -> Result<Self, Self::Rejection> {
+        let claims: Claims = Claims::from_request_parts(parts, state).await?;
+        let email: String = subject_email(&claims.sub).to_lowercase();
+        if claims.email_verified && state.read().await.admins.contains(&email) {
+            Ok(Admin(claims))
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+/// The name in a `Full Name <email>` subject, or the whole subject without an address.
+pub fn subject_name(sub: &str) -> &str {
+    sub.split_once(" <").map_or(sub, |(name, _)| name).trim()
+}
+
+/// The address in a `Full Name <email>` subject, or the whole subject without one.
+pub fn subject_email(sub: &str) -> &str {
+    sub.rsplit_once('<')
+        .and_then(|(_, rest)| rest.strip_suffix('>'))
+        .unwrap_or(sub)
+        .trim()
+}
+
+impl AuthError {
+    /// Stable label for the variant, used in metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::TokenCreation => "token_creation",
+            AuthError::Registration => "registration",
+            AuthError::LoginState => "login_state",
+            AuthError::IdentityProvider => "identity_provider",
+            AuthError::NotConfigured => "not_configured",
+            AuthError::Forbidden => "forbidden",
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, ToSchema)]
-pub struct Registration {
-    #[schema(example = "Jane Doux")]
-    full_name: String,
-    #[schema(example = "janedoux@example.org")]
-    email: String,
-    #[schema(example = "password123")]
-    password: String,
+impl From<AuthError> for Problem {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Registration => Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "registration-failed",
+                "Invalid registration",
+            ),
+            AuthError::TokenCreation => Problem::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "token-creation",
+                "Token creation error",
+            ),
+            AuthError::InvalidToken => {
+                Problem::new(StatusCode::UNAUTHORIZED, "invalid-token", "Invalid token")
+            }
+            AuthError::LoginState => Problem::new(
+                StatusCode::BAD_REQUEST,
+                "login-state",
+                "Invalid or expired login state",
+            ),
+            AuthError::IdentityProvider => Problem::new(
+                StatusCode::BAD_GATEWAY,
+                "identity-provider",
+                "Identity provider error",
+            ),
+            AuthError::NotConfigured => Problem::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "not-configured",
+                "Login method not configured",
+            ),
+            AuthError::Forbidden => Problem::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "Administrator access required",
+            ),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        METRICS.auth_failure(&self);
+        Problem::from(self).into_response()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Claims {
     #[schema(example = "question.po8.org")]
-    iss: String,
+    pub iss: String,
     #[schema(example = "Jane Doux <janedoux@example.org>")]
-    sub: String,
+    pub sub: String,
     #[schema(example = "1717630066")]
-    exp: u64,
+    pub exp: u64,
+    /// Whether an identity provider verified the subject's email. Never set for tokens from
+    /// registration, which takes the email on trust.
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
-pub fn make_jwt_token(
+/// Check `registration` against the registration password and return the subject it
+/// identifies. Shared by token registration and the HTML pages' session login.
+pub fn check_registration(
     appstate: &AppState,
     registration: &Registration,
-) -> Result<AuthBody, AuthError> {
-    use jsonwebtoken::{encode, Algorithm, Header};
-
+) -> Result<String, AuthError> {
     if registration.password != appstate.reg_key {
         return Err(AuthError::Registration);
     }
+    Ok(format!(
+        "{} <{}>",
+        registration.full_name, registration.email
+    ))
+}
+
+pub fn make_jwt_token(
+    appstate: &AppState,
+    registration: &Registration,
+) -> Result<AuthBody, AuthError> {
+    let sub: String = check_registration(appstate, registration)?;
+    issue_jwt_token(appstate, sub, false)
+}
+
+/// Sign one of our own access tokens for `sub`, valid for a day. Used both by
+/// registration and by logins through an external identity provider, which alone set
+/// `email_verified`.
+pub fn issue_jwt_token(
+    appstate: &AppState,
+    sub: String,
+    email_verified: bool,
+) -> Result<AuthBody, AuthError> {
+    use jsonwebtoken::{encode, Algorithm, Header};
 
     let iss: String = "question.po8.org".to_string();
-    let sub: String = format!("{} <{}>", registration.full_name, registration.email);
     let exp: i64 = (Utc::now() + TimeDelta::days(1)).timestamp();
     let exp: u64 = u64::try_from(exp).unwrap();
-    let claims: Claims = Claims { iss, sub, exp };
+    let claims: Claims = Claims {
+        iss,
+        sub,
+        exp,
+        email_verified,
+    };
     let header: Header = Header::new(Algorithm::HS512);
     let token: String = encode(&header, &claims, &appstate.jwt_keys.encoding)
         .map_err(|_| AuthError::TokenCreation)?;
     Ok(AuthBody::new(token))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a token for `sub` gets through the admin check, given how it was issued.
+    async fn admits(pool: PgPool, sub: &str, email_verified: bool) -> bool {
+        let store: Store = Store {
+            connection: pool,
+            events: Arc::default(),
+            notify: false,
+        };
+        let admins: HashSet<String> = HashSet::from(["root@example.org".to_string()]);
+        let appstate: AppState = AppState::new(
+            store,
+            JwtKeys::new(b"test"),
+            "password".to_string(),
+            None,
+            admins,
+            crate::shutdown::Shutdown::default(),
+        );
+        let token: AuthBody = issue_jwt_token(&appstate, sub.to_string(), email_verified).unwrap();
+        let state: SharedAppState = Arc::new(RwLock::new(appstate));
+        let request = http::Request::builder()
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", token.access_token),
+            )
+            .body(())
+            .unwrap();
+        let (mut parts, ()) = request.into_parts();
+        Admin::from_request_parts(&mut parts, &state).await.is_ok()
+    }
+
+    #[sqlx::test]
+    async fn admins_need_a_verified_email(pool: PgPool) {
+        let cases: [(&str, bool, bool); 4] = [
+            ("Root <root@example.org>", true, true),
+            ("Root <ROOT@example.org>", true, true),
+            // Anyone with the registration password can claim the address.
+            ("Root <root@example.org>", false, false),
+            ("Jane <jane@example.org>", true, false),
+        ];
+        for (sub, email_verified, admitted) in cases {
+            let got: bool = admits(pool.clone(), sub, email_verified).await;
+            assert_eq!(got, admitted, "{} {}", sub, email_verified);
+        }
+    }
+
+    #[sqlx::test]
+    async fn registration_never_verifies_the_email(pool: PgPool) {
+        let store: Store = Store {
+            connection: pool,
+            events: Arc::default(),
+            notify: false,
+        };
+        let appstate: AppState = AppState::new(
+            store,
+            JwtKeys::new(b"test"),
+            "password".to_string(),
+            None,
+            HashSet::new(),
+            crate::shutdown::Shutdown::default(),
+        );
+        let registration: Registration = Registration {
+            full_name: "Root".to_string(),
+            email: "root@example.org".to_string(),
+            password: "password".to_string(),
+        };
+        let token: AuthBody = make_jwt_token(&appstate, &registration).unwrap();
+        let claims: Claims = appstate
+            .jwt_keys
+            .decode_claims(&token.access_token)
+            .unwrap();
+        assert!(!claims.email_verified);
+    }
+}
@@ -0,0 +1,189 @@
+//! # CORS
+//!
+//! Cross-origin policy for the API, set separately for each route group:
+//!
+//! * `read` (`GET /questions`, `/question`, `/question/{id}`) is open to any origin by default.
+//! * `write` (adding, changing and deleting questions) and `auth` (registration, OIDC) allow no
+//!   cross-origin requests until origins are listed for them.
+//!
+//! Each group takes allowed origins, methods, request headers, whether credentials may be
+//! sent, and how long browsers may cache a preflight answer. Responses expose the request id and
+//! rate limit headers to scripts.
+
+use crate::requestid::X_REQUEST_ID;
+use crate::*;
+
+use http::{HeaderName, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Response headers scripts on allowed origins may read.
+const EXPOSED_HEADERS: [HeaderName; 6] = [
+    X_REQUEST_ID,
+    HeaderName::from_static("ratelimit-limit"),
+    HeaderName::from_static("ratelimit-remaining"),
+    HeaderName::from_static("ratelimit-reset"),
+    HeaderName::from_static("ratelimit-policy"),
+    http::header::RETRY_AFTER,
+];
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CorsArgs {
+    /// Origins allowed to read questions, or `*` for any.
+    #[clap(
+        long,
+        env = "CORS_READ_ORIGINS",
+        value_delimiter = ',',
+        default_value = "*"
+    )]
+    pub cors_read_origins: Vec<String>,
+    #[clap(
+        long,
+        env = "CORS_READ_METHODS",
+        value_delimiter = ',',
+        default_value = "GET,HEAD"
+    )]
+    pub cors_read_methods: Vec<String>,
+    #[clap(long, env = "CORS_READ_HEADERS", value_delimiter = ',')]
+    pub cors_read_headers: Vec<String>,
+    #[clap(long, env = "CORS_READ_CREDENTIALS")]
+    pub cors_read_credentials: bool,
+    #[clap(long, env = "CORS_READ_MAX_AGE", default_value_t = 3600)]
+    pub cors_read_max_age: u64,
+
+    /// Origins allowed to add, change and delete questions. None by default.
+    #[clap(long, env = "CORS_WRITE_ORIGINS", value_delimiter = ',')]
+    pub cors_write_origins: Vec<String>,
+    #[clap(
+        long,
+        env = "CORS_WRITE_METHODS",
+        value_delimiter = ',',
+        default_value = "POST,PUT,DELETE"
+    )]
+    pub cors_write_methods: Vec<String>,
+    #[clap(
+        long,
+        env = "CORS_WRITE_HEADERS",
+        value_delimiter = ',',
+        default_value = "authorization,content-type"
+    )]
+    pub cors_write_headers: Vec<String>,
+    #[clap(long, env = "CORS_WRITE_CREDENTIALS")]
+    pub cors_write_credentials: bool,
+    #[clap(long, env = "CORS_WRITE_MAX_AGE", default_value_t = 600)]
+    pub cors_write_max_age: u64,
+
+    /// Origins allowed to register and log in. None by default.
+    #[clap(long, env = "CORS_AUTH_ORIGINS", value_delimiter = ',')]
+    pub cors_auth_origins: Vec<String>,
+    #[clap(
+        long,
+        env = "CORS_AUTH_METHODS",
+        value_delimiter = ',',
+        default_value = "GET,POST"
+    )]
+    pub cors_auth_methods: Vec<String>,
+    #[clap(
+        long,
+        env = "CORS_AUTH_HEADERS",
+        value_delimiter = ',',
+        default_value = "content-type"
+    )]
+    pub cors_auth_headers: Vec<String>,
+    #[clap(long, env = "CORS_AUTH_CREDENTIALS")]
+    pub cors_auth_credentials: bool,
+    #[clap(long, env = "CORS_AUTH_MAX_AGE", default_value_t = 600)]
+    pub cors_auth_max_age: u64,
+}
+
+/// The CORS settings of one route group.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy<'a> {
+    pub group: &'static str,
+    pub origins: &'a [String],
+    pub methods: &'a [String],
+    pub headers: &'a [String],
+    pub credentials: bool,
+    pub max_age: u64,
+}
+
+impl CorsArgs {
+    pub fn read(&self) -> CorsPolicy<'_> {
+        CorsPolicy {
+            group: "read",
+            origins: &self.cors_read_origins,
+            methods: &self.cors_read_methods,
+            headers: &self.cors_read_headers,
+            credentials: self.cors_read_credentials,
+            max_age: self.cors_read_max_age,
+        }
+    }
+
+    pub fn write(&self) -> CorsPolicy<'_> {
+        CorsPolicy {
+            group: "write",
+            origins: &self.cors_write_origins,
+            methods: &self.cors_write_methods,
+            headers: &self.cors_write_headers,
+            credentials: self.cors_write_credentials,
+            max_age: self.cors_write_max_age,
+        }
+    }
+
+    pub fn auth(&self) -> CorsPolicy<'_> {
+        CorsPolicy {
+            group: "auth",
+            origins: &self.cors_auth_origins,
+            methods: &self.cors_auth_methods,
+            headers: &self.cors_auth_headers,
+            credentials: self.cors_auth_credentials,
+            max_age: self.cors_auth_max_age,
+        }
+    }
+}
+
+/// Drop the empty entries an unset or trailing-comma list leaves behind.
+fn entries(values: &[String]) -> impl Iterator<Item = &str> {
+    values.iter().map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+impl CorsPolicy<'_> {
+    /// Build the layer, refusing settings browsers would reject, such as credentials with a
+    /// wildcard origin.
+    pub fn layer(&self) -> Result<CorsLayer, String> {
+        let fail =
+            |what: &str, value: &str| format!("cors {}: bad {} {:?}", self.group, what, value);
+
+        let any_origin: bool = entries(self.origins).any(|o| o == "*");
+        if any_origin && self.credentials {
+            return Err(format!(
+                "cors {}: credentials can't be allowed for any origin",
+                self.group
+            ));
+        }
+        let origin: AllowOrigin = if any_origin {
+            AllowOrigin::any()
+        } else {
+            let origins: Vec<HeaderValue> = entries(self.origins)
+                .map(|o| HeaderValue::from_str(o).map_err(|_| fail("origin", o)))
+                .collect::<Result<_, _>>()?;
+            AllowOrigin::list(origins)
+        };
+        let methods: Vec<Method> = entries(self.methods)
+            .map(|m| {
+                Method::from_bytes(m.to_ascii_uppercase().as_bytes()).map_err(|_| fail("method", m))
+            })
+            .collect::<Result<_, _>>()?;
+        let headers: Vec<HeaderName> = entries(self.headers)
+            .map(|h| HeaderName::from_bytes(h.as_bytes()).map_err(|_| fail("header", h)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(CorsLayer::new()
+            .allow_origin(origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.credentials)
+            .expose_headers(EXPOSED_HEADERS)
+            .max_age(Duration::from_secs(self.max_age)))
+    }
+}
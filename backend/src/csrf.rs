@@ -0,0 +1,257 @@
+//! # CSRF Protection
+//!
+//! The server-rendered pages authenticate with the session cookie, which the browser attaches
+//! to requests started by any site. State-changing requests to them therefore have to pass two
+//! checks in [`csrf_protect`]:
+//!
+//! * `Origin` (or, without it, `Referer`) names this site or one of `--csrf-trusted-origins`.
+//! * The request carries the session's synchronizer token, in the `csrf_token` form field or
+//!   the `X-CSRF-Token` header. Templates embed it with [`csrf_token`].
+//!
+//! The JSON API authenticates with bearer tokens, which browsers never attach on their own, and
+//! isn't covered.
+
+use crate::error::Problem;
+use crate::*;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::middleware::Next;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use http::HeaderName;
+
+/// Session key holding the synchronizer token.
+pub const CSRF_SESSION_KEY: &str = "csrf_token";
+
+/// Form field carrying the token.
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// Header carrying the token, for requests that aren't form posts.
+pub const CSRF_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// Largest form body read while looking for the token.
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CsrfArgs {
+    /// Further origins, such as `https://question.example.org`, allowed to submit the pages'
+    /// forms. Needed when a proxy changes the `Host` header.
+    #[clap(long, env = "CSRF_TRUSTED_ORIGINS", value_delimiter = ',')]
+    pub csrf_trusted_origins: Vec<String>,
+}
+
+/// The session's synchronizer token, created on first use.
+pub async fn csrf_token(session: &Session) -> Result<String, tower_sessions::session::Error> {
+    if let Some(token) = session.get::<String>(CSRF_SESSION_KEY).await? {
+        return Ok(token);
+    }
+    let bytes: [u8; 32] = rand::random();
+    let token: String = URL_SAFE_NO_PAD.encode(bytes);
+    session.insert(CSRF_SESSION_KEY, &token).await?;
+    Ok(token)
+}
+
+/// Compare without returning early, so timing doesn't reveal how much of a guess was right.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+fn forbidden(detail: &str) -> Response {
+    Problem::new(
+        StatusCode::FORBIDDEN,
+        "csrf-failed",
+        "Cross-site request refused",
+    )
+    .with_detail(detail)
+    .into_response()
+}
+
+/// `scheme://authority` of an `Origin` or `Referer` value.
+fn origin_of(url: &str) -> Option<&str> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority: &str = rest.split(['/', '?', '#']).next()?;
+    Some(&url[..scheme.len() + 3 + authority.len()])
+}
+
+/// Whether the request was started by a page of this site.
+fn same_origin(request: &Request, trusted: &[String]) -> bool {
+    let header = |name: http::HeaderName| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let Some(source) = header(http::header::ORIGIN).or_else(|| header(http::header::REFERER))
+    else {
+        return false;
+    };
+    let Some(origin) = origin_of(&source) else {
+        return false;
+    };
+    if trusted.iter().any(|t| t.eq_ignore_ascii_case(origin)) {
+        return true;
+    }
+    // HTTP/2 requests name the host in the URI rather than a `Host` header.
+    let host: Option<String> = header(http::header::HOST)
+        .or_else(|| request.uri().authority().map(|a| a.as_str().to_string()));
+    let authority: &str = origin.split_once("://").map_or("", |(_, a)| a);
+    host.is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
+
+/// Middleware refusing state-changing requests that fail the origin or token check.
+pub async fn csrf_protect(
+    State(args): State<Arc<CsrfArgs>>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method().is_safe() {
+        return next.run(request).await;
+    }
+    if !same_origin(&request, &args.csrf_trusted_origins) {
+        return forbidden("request did not come from this site");
+    }
+    let expected: String = match session.get::<String>(CSRF_SESSION_KEY).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return forbidden("no form was issued to this session"),
+        Err(e) => {
+            tracing::error!("session: {}", e);
+            return Problem::from_status(StatusCode::INTERNAL_SERVER_ERROR).into_response();
+        }
+    };
+
+    let header_token: Option<String> = request
+        .headers()
+        .get(&CSRF_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let (request, submitted) = match header_token {
+        Some(token) => (request, Some(token)),
+        None => {
+            // The token is in the form body, which then has to be put back for the handler.
+            let (parts, body) = request.into_parts();
+            let bytes = match axum::body::to_bytes(body, MAX_FORM_BYTES).await {
+                Ok(bytes) => bytes,
+                Err(_) => return forbidden("form too large to check"),
+            };
+            let token: Option<String> =
+                serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+                    .ok()
+                    .and_then(|fields| {
+                        fields
+                            .into_iter()
+                            .find(|(name, _)| name == CSRF_FIELD)
+                            .map(|(_, value)| value)
+                    });
+            (Request::from_parts(parts, Body::from(bytes)), token)
+        }
+    };
+
+    match submitted {
+        Some(token) if tokens_match(&token, &expected) => next.run(request).await,
+        _ => forbidden("missing or stale form token; reload the page and try again"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_of() {
+        let cases: [(&str, Option<&str>); 7] = [
+            ("https://a.b", Some("https://a.b")),
+            ("https://a.b/site/ask", Some("https://a.b")),
+            ("https://a.b?x", Some("https://a.b")),
+            ("https://a.b#top", Some("https://a.b")),
+            ("http://a.b:3000/x?y#z", Some("http://a.b:3000")),
+            ("null", None),
+            ("", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(super::origin_of(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn tokens_match() {
+        let cases: [(&str, &str, bool); 5] = [
+            ("abc", "abc", true),
+            ("", "", true),
+            ("abc", "abd", false),
+            ("abc", "ab", false),
+            ("abc", "abcd", false),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(super::tokens_match(a, b), expected, "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn same_origin() {
+        let trusted: Vec<String> = vec!["https://question.example.org".to_string()];
+        // Origin, Referer and Host headers, and whether the request is from this site.
+        let cases = [
+            (Some("http://a.b"), None, Some("a.b"), true),
+            (Some("http://A.B"), None, Some("a.b"), true),
+            (Some("http://evil.example"), None, Some("a.b"), false),
+            (Some("http://a.b:8080"), None, Some("a.b"), false),
+            (Some("null"), None, Some("a.b"), false),
+            (
+                Some("https://question.example.org"),
+                None,
+                Some("a.b"),
+                true,
+            ),
+            // Browsers that leave out `Origin` still send `Referer`.
+            (None, Some("http://a.b/site/ask?x=1"), Some("a.b"), true),
+            (None, Some("http://evil.example/a.b"), Some("a.b"), false),
+            // `Origin` wins over `Referer`.
+            (
+                Some("http://evil.example"),
+                Some("http://a.b/"),
+                Some("a.b"),
+                false,
+            ),
+            (None, None, Some("a.b"), false),
+        ];
+        for (origin, referer, host, expected) in cases {
+            let mut request = Request::builder().method("POST").uri("/site/ask");
+            for (name, value) in [
+                (http::header::ORIGIN, origin),
+                (http::header::REFERER, referer),
+                (http::header::HOST, host),
+            ] {
+                if let Some(value) = value {
+                    request = request.header(name, value);
+                }
+            }
+            let request: Request = request.body(Body::empty()).unwrap();
+            assert_eq!(
+                super::same_origin(&request, &trusted),
+                expected,
+                "{:?} {:?} {:?}",
+                origin,
+                referer,
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn same_origin_without_host_uses_the_uri() {
+        let request: Request = Request::builder()
+            .method("POST")
+            .uri("https://a.b/site/ask")
+            .header(http::header::ORIGIN, "https://a.b")
+            .body(Body::empty())
+            .unwrap();
+        assert!(super::same_origin(&request, &[]));
+    }
+}
@@ -1,19 +1,36 @@
-use crate::store;
+//! # Errors
+//!
+//! Every error the service returns is rendered as an RFC 7807 problem document with content
+//! type `application/problem+json`. Module specific errors (`StoreErr`, `AuthError`,
+//! validation rejections) convert into [`Problem`], which is the single place that decides
+//! the status code and wording a client sees.
+//!
+//! The [`problem_details`] middleware fills in the `instance` member from the request path and
+//! turns any other plain text or empty error response (axum's own rejections, `405`, `413`, ...)
+//! into a problem document as well.
+
+use crate::requestid;
+use crate::validation::FieldError;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::middleware::Next;
 use axum::{
-    http::{HeaderValue, Method, StatusCode},
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    routing::{any, get, post},
-    Json, Router,
 };
-use serde::ser::{Error, SerializeStruct, Serializer};
 use serde::Serialize;
-use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
-use sqlx::{error::Error as SqlxError, Postgres};
-use std::collections::HashSet;
-use tower_http::cors::CorsLayer;
-use utoipa::openapi::{ObjectBuilder, RefOr, Schema, SchemaType};
+use sqlx::error::Error as SqlxError;
 use utoipa::ToSchema;
 
+/// Base of the `type` URIs identifying each kind of problem.
+pub const PROBLEM_TYPE_BASE: &str = "https://question.po8.org/problems/";
+
+/// Media type of problem documents.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Largest error body the middleware will read when converting a foreign error response.
+const MAX_FOREIGN_ERROR_BODY: usize = 64 * 1024;
+
 // Reference from jokebase class repo
 #[derive(Debug, thiserror::Error, ToSchema, Serialize)]
 pub enum StoreErr {
@@ -25,6 +42,12 @@ pub enum StoreErr {
     DatabaseQueryError(String),
     #[error("Question doesn't exist")]
     QuestionNotFound(String),
+    #[error("Record doesn't exist")]
+    NotFound(String),
+    #[error("Record conflicts with existing data")]
+    Conflict(String),
+    #[error("Record rejected by the database")]
+    InvalidData(String),
 }
 
 impl From<std::num::ParseIntError> for StoreErr {
@@ -33,56 +56,359 @@ impl From<std::num::ParseIntError> for StoreErr {
     }
 }
 
+/// The error for data rejected with SQLSTATE `code`, if that is a client's fault. The
+/// database's own message names tables and constraints, so clients get a fixed one instead.
+fn rejected(code: &str) -> Option<StoreErr> {
+    let conflict = |message: &str| Some(StoreErr::Conflict(message.to_string()));
+    let invalid = |message: &str| Some(StoreErr::InvalidData(message.to_string()));
+    match code {
+        // unique_violation
+        "23505" => conflict("a record with the same key already exists"),
+        // foreign_key_violation
+        "23503" => conflict("the record refers to or is referred to by another"),
+        // not_null_violation
+        "23502" => invalid("a required value is missing"),
+        // check_violation
+        "23514" => invalid("a value is out of range"),
+        // string_data_right_truncation
+        "22001" => invalid("a value is too long"),
+        // invalid_text_representation
+        "22P02" => invalid("a value is malformed"),
+        _ => None,
+    }
+}
+
 impl From<SqlxError> for StoreErr {
     fn from(e: SqlxError) -> Self {
-        StoreErr::DatabaseQueryError(e.to_string())
+        match &e {
+            SqlxError::RowNotFound => StoreErr::NotFound("no matching record".to_string()),
+            SqlxError::Database(db) => match db.code().as_deref().and_then(rejected) {
+                Some(rejected) => {
+                    tracing::warn!("database rejected data: {}", db.message());
+                    rejected
+                }
+                None => StoreErr::DatabaseQueryError(e.to_string()),
+            },
+            _ => StoreErr::DatabaseQueryError(e.to_string()),
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct StoreError {
-    pub status: StatusCode,
-    pub error: StoreErr,
+/// An RFC 7807 problem document.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Problem {
+    /// URI identifying the kind of problem, or `about:blank` when the status says it all.
+    #[serde(rename = "type")]
+    #[schema(example = "https://question.po8.org/problems/not-found")]
+    pub kind: String,
+    #[schema(example = "Not Found")]
+    pub title: String,
+    #[schema(example = 404)]
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "no question with id 7")]
+    pub detail: Option<String>,
+    /// Path of the request that failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "/api/v1/question/7")]
+    pub instance: Option<String>,
+    /// Identifier to quote when reporting the failure: the request's `X-Request-Id`, which
+    /// also appears in the server logs.
+    #[schema(example = "9b2f4c1d7e3a4b8c9d0e1f2a3b4c5d6e")]
+    pub trace_id: String,
+    /// Per-field errors of a rejected payload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+    /// What went wrong underneath, for the logs only.
+    #[serde(skip)]
+    pub cause: Option<String>,
 }
 
-pub fn error_schema(name: &str, example: serde_json::Value) -> (&str, RefOr<Schema>) {
-    let sch: RefOr<Schema> = ObjectBuilder::new()
-        .property(
-            "status",
-            ObjectBuilder::new().schema_type(SchemaType::String),
-        )
-        .property(
-            "error",
-            ObjectBuilder::new().schema_type(SchemaType::String),
+/// Identifier tying a problem document to the logs: the id of the request being handled, or a
+/// fresh one outside of a request.
+fn new_trace_id() -> String {
+    requestid::current().unwrap_or_else(requestid::generate)
+}
+
+impl Problem {
+    /// A problem of kind `PROBLEM_TYPE_BASE` + `slug`.
+    pub fn new(status: StatusCode, slug: &str, title: &str) -> Self {
+        Self {
+            kind: format!("{}{}", PROBLEM_TYPE_BASE, slug),
+            title: title.to_string(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            trace_id: new_trace_id(),
+            errors: None,
+            cause: None,
+        }
+    }
+
+    /// A problem described by its status code alone.
+    pub fn from_status(status: StatusCode) -> Self {
+        Self {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            ..Self::new(status, "", "")
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = Some(errors);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: impl Into<String>) -> Self {
+        self.cause = Some(cause.into());
+        self
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn body(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        if self.status_code().is_server_error() {
+            tracing::error!(
+                trace_id = %self.trace_id,
+                "{}: {:?}, cause: {:?}",
+                self.title,
+                self.detail,
+                self.cause
+            );
+        }
+        let mut response: Response = (
+            self.status_code(),
+            [(header::CONTENT_TYPE, PROBLEM_JSON)],
+            self.body(),
         )
-        .example(Some(example))
-        .into();
-    (name, sch)
+            .into_response();
+        // Left for `problem_details` to add request context.
+        response.extensions_mut().insert(self);
+        response
+    }
 }
 
-impl<'s> ToSchema<'s> for StoreError {
-    fn schema() -> (&'s str, RefOr<Schema>) {
-        let example: serde_json::Value = serde_json::json!({
-            "status":"404","error":"no question"
-        });
-        error_schema("StoreError", example)
+impl From<StoreErr> for Problem {
+    fn from(e: StoreErr) -> Self {
+        match e {
+            StoreErr::ParseError(msg) => Problem::new(
+                StatusCode::BAD_REQUEST,
+                "bad-parameter",
+                "Cannot parse parameter",
+            )
+            .with_detail(msg),
+            StoreErr::MissingParameters(msg) => Problem::new(
+                StatusCode::BAD_REQUEST,
+                "missing-parameter",
+                "Missing parameter",
+            )
+            .with_detail(msg),
+            StoreErr::QuestionNotFound(id) => {
+                Problem::new(StatusCode::NOT_FOUND, "not-found", "Not Found")
+                    .with_detail(format!("no question with id {}", id))
+            }
+            StoreErr::NotFound(msg) => {
+                Problem::new(StatusCode::NOT_FOUND, "not-found", "Not Found").with_detail(msg)
+            }
+            StoreErr::Conflict(msg) => {
+                Problem::new(StatusCode::CONFLICT, "conflict", "Conflict").with_detail(msg)
+            }
+            StoreErr::InvalidData(msg) => Problem::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-data",
+                "Data rejected",
+            )
+            .with_detail(msg),
+            // The driver's message stays in the logs rather than going to the client.
+            StoreErr::DatabaseQueryError(msg) => Problem::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database-error",
+                "Database error",
+            )
+            .with_detail("the query could not be executed")
+            .with_cause(msg),
+        }
     }
 }
 
-impl Serialize for StoreError {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let status: String = self.status.to_string();
-        let mut state: <S as Serializer>::SerializeStruct =
-            serializer.serialize_struct("StoreError", 2)?;
-        state.serialize_field("status", &status)?;
-        state.serialize_field("error", &self.error)?;
-        state.end()
+impl IntoResponse for StoreErr {
+    fn into_response(self) -> Response {
+        Problem::from(self).into_response()
     }
 }
 
-impl StoreError {
-    pub fn response(status: StatusCode, error: StoreErr) -> Response {
-        let error: StoreError = StoreError { status, error };
-        (status, Json(error)).into_response()
+/// Middleware completing problem documents with the request path, and converting error
+/// responses produced outside our handlers into problem documents.
+pub async fn problem_details(request: Request, next: Next) -> Response {
+    let instance: String = request.uri().path().to_string();
+    let mut response: Response = next.run(request).await;
+
+    let problem: Option<Problem> = response.extensions_mut().remove::<Problem>();
+    let mut problem: Problem = match problem {
+        Some(problem) => problem,
+        None => {
+            let status: StatusCode = response.status();
+            if !(status.is_client_error() || status.is_server_error()) {
+                return response;
+            }
+            let content_type: Option<&str> = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok());
+            let is_text: bool = match content_type {
+                Some(content_type) => content_type.starts_with("text/plain"),
+                None => true,
+            };
+            // Structured error bodies, such as the readiness report, are left alone.
+            if !is_text {
+                return response;
+            }
+            let body: Body = std::mem::take(response.body_mut());
+            let text: String = match axum::body::to_bytes(body, MAX_FOREIGN_ERROR_BODY).await {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).trim().to_string(),
+                Err(_) => String::new(),
+            };
+            let problem: Problem = Problem::from_status(status);
+            if text.is_empty() {
+                problem
+            } else {
+                problem.with_detail(text)
+            }
+        }
+    };
+
+    problem.instance.get_or_insert(instance);
+    response.headers_mut().remove(header::CONTENT_LENGTH);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    *response.body_mut() = Body::from(problem.body());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_data_gets_a_fixed_detail() {
+        let cases: [(&str, Option<(StatusCode, &str)>); 7] = [
+            (
+                "23505",
+                Some((
+                    StatusCode::CONFLICT,
+                    "a record with the same key already exists",
+                )),
+            ),
+            (
+                "23503",
+                Some((
+                    StatusCode::CONFLICT,
+                    "the record refers to or is referred to by another",
+                )),
+            ),
+            (
+                "23502",
+                Some((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "a required value is missing",
+                )),
+            ),
+            (
+                "23514",
+                Some((StatusCode::UNPROCESSABLE_ENTITY, "a value is out of range")),
+            ),
+            (
+                "22001",
+                Some((StatusCode::UNPROCESSABLE_ENTITY, "a value is too long")),
+            ),
+            (
+                "22P02",
+                Some((StatusCode::UNPROCESSABLE_ENTITY, "a value is malformed")),
+            ),
+            ("42P01", None),
+        ];
+        for (code, expected) in cases {
+            let problem: Option<Problem> = rejected(code).map(Problem::from);
+            let got = problem
+                .as_ref()
+                .map(|problem| (problem.status_code(), problem.detail.as_deref().unwrap()));
+            assert_eq!(got, expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn store_errors_become_problems() {
+        let cases: [(StoreErr, StatusCode, &str, &str); 7] = [
+            (
+                StoreErr::ParseError("bad id".to_string()),
+                StatusCode::BAD_REQUEST,
+                "bad-parameter",
+                "bad id",
+            ),
+            (
+                StoreErr::MissingParameters("no id".to_string()),
+                StatusCode::BAD_REQUEST,
+                "missing-parameter",
+                "no id",
+            ),
+            (
+                StoreErr::QuestionNotFound("7".to_string()),
+                StatusCode::NOT_FOUND,
+                "not-found",
+                "no question with id 7",
+            ),
+            (
+                StoreErr::NotFound("no answer".to_string()),
+                StatusCode::NOT_FOUND,
+                "not-found",
+                "no answer",
+            ),
+            (
+                StoreErr::Conflict("taken".to_string()),
+                StatusCode::CONFLICT,
+                "conflict",
+                "taken",
+            ),
+            (
+                StoreErr::InvalidData("too long".to_string()),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-data",
+                "too long",
+            ),
+            (
+                StoreErr::DatabaseQueryError("relation \"secrets\" does not exist".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database-error",
+                "the query could not be executed",
+            ),
+        ];
+        for (err, status, slug, detail) in cases {
+            let problem: Problem = Problem::from(err);
+            assert_eq!(problem.status_code(), status, "{}", slug);
+            assert_eq!(problem.kind, format!("{}{}", PROBLEM_TYPE_BASE, slug));
+            assert_eq!(problem.detail.as_deref(), Some(detail));
+        }
+    }
+
+    #[test]
+    fn causes_stay_out_of_the_body() {
+        let problem: Problem = StoreErr::DatabaseQueryError("secret".to_string()).into();
+        assert_eq!(problem.cause.as_deref(), Some("secret"));
+        let body: String = String::from_utf8(problem.body()).unwrap();
+        assert!(!body.contains("secret"), "{}", body);
     }
 }
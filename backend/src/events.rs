@@ -0,0 +1,394 @@
+//! # Events
+//!
+//! Changes to questions and answers, published by the `Store` write paths after their
+//! transaction commits and streamed to clients as Server-Sent Events at `GET /api/v1/events`.
+//!
+//! The last [`EVENT_LOG_CAPACITY`] events are kept in memory. A client reconnecting with
+//! `Last-Event-ID` is first sent the events it missed, if they are still in the log. When
+//! events were lost, because the log no longer reaches back far enough or the client fell
+//! behind, it is sent a `lagged` event and should refetch what it shows.
+//!
+//! Event ids start at the server's start time in milliseconds, so they keep increasing across
+//! restarts and a stale `Last-Event-ID` just misses the events of the previous run.
+//!
+//! Streams end when the server shuts down; clients reconnect with `Last-Event-ID` to the next
+//! instance.
+
+use crate::appstate::HandlerAppState;
+use crate::shutdown::Shutdown;
+use crate::types::answer::Answer;
+use crate::types::question::Question;
+use crate::*;
+
+use axum::extract::Query;
+use axum::response::sse::{self, KeepAlive, Sse};
+use chrono::Utc;
+use futures::stream::{self, Stream, StreamExt};
+use http::HeaderMap;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::{IntoParams, ToSchema};
+
+/// Number of past events kept for `Last-Event-ID` resumption.
+pub const EVENT_LOG_CAPACITY: usize = 1024;
+
+/// Header a reconnecting `EventSource` sends with the id of the last event it received.
+const LAST_EVENT_ID: &str = "last-event-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    QuestionCreated,
+    QuestionUpdated,
+    QuestionDeleted,
+    AnswerCreated,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 4] = [
+        EventKind::QuestionCreated,
+        EventKind::QuestionUpdated,
+        EventKind::QuestionDeleted,
+        EventKind::AnswerCreated,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::QuestionCreated => "question_created",
+            EventKind::QuestionUpdated => "question_updated",
+            EventKind::QuestionDeleted => "question_deleted",
+            EventKind::AnswerCreated => "answer_created",
+        }
+    }
+}
+
+/// One change, sent as the `data` of an SSE event named after its `kind`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Event {
+    #[schema(example = 1718000000000u64)]
+    pub id: u64,
+    pub kind: EventKind,
+    #[schema(example = "1")]
+    pub question_id: String,
+    /// Tags of the question, or of the question answered.
+    #[schema(example = json!(["general"]))]
+    pub tags: Vec<String>,
+    /// The question as stored, for created and updated questions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub question: Option<Question>,
+    /// The answer, for created answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<Answer>,
+}
+
+struct Log {
+    next_id: u64,
+    events: VecDeque<Arc<Event>>,
+}
+
+/// Bounded log of recent events plus a broadcast channel for live subscribers.
+pub struct EventLog {
+    log: Mutex<Log>,
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl std::fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventLog").finish_non_exhaustive()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        let start: u64 = u64::try_from(Utc::now().timestamp_millis()).unwrap_or(0);
+        let (sender, _) = broadcast::channel(EVENT_LOG_CAPACITY);
+        Self {
+            log: Mutex::new(Log {
+                next_id: start,
+                events: VecDeque::with_capacity(EVENT_LOG_CAPACITY),
+            }),
+            sender,
+        }
+    }
+}
+
+impl Event {
+    /// The event for a created or updated `question`; the id is given when it is published.
+    pub fn question(kind: EventKind, question: &Question) -> Self {
+        let mut tags: Vec<String> = question.tags.iter().flatten().cloned().collect();
+        tags.sort();
+        Self {
+            id: 0,
+            kind,
+            question_id: question.id.0.clone(),
+            tags,
+            question: Some(question.clone()),
+            answer: None,
+        }
+    }
+
+    /// `tags` are the ones the question had, sorted.
+    pub fn question_deleted(question_id: &str, tags: Vec<String>) -> Self {
+        Self {
+            id: 0,
+            kind: EventKind::QuestionDeleted,
+            question_id: question_id.to_string(),
+            tags,
+            question: None,
+            answer: None,
+        }
+    }
+
+    /// `tags` are the answered question's, sorted.
+    pub fn answer_created(answer: &Answer, tags: Vec<String>) -> Self {
+        Self {
+            id: 0,
+            kind: EventKind::AnswerCreated,
+            question_id: answer.question_id.0.clone(),
+            tags,
+            question: None,
+            answer: Some(answer.clone()),
+        }
+    }
+}
+
+impl EventLog {
+    /// Give `event` the next id, keep it and send it to the live subscribers.
+    pub fn publish(&self, mut event: Event) {
+        let mut log = self.log.lock().unwrap();
+        event.id = log.next_id;
+        log.next_id += 1;
+        let event: Arc<Event> = Arc::new(event);
+        if log.events.len() == EVENT_LOG_CAPACITY {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+        // Sending under the lock keeps the live order the same as the log's. Having no
+        // subscribers isn't an error.
+        let _ = self.sender.send(event);
+    }
+
+    /// Events after `last_id` still in the log, whether some were already dropped from it, and
+    /// a receiver for everything published from now on.
+    pub fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (Vec<Arc<Event>>, bool, broadcast::Receiver<Arc<Event>>) {
+        let log = self.log.lock().unwrap();
+        let receiver: broadcast::Receiver<Arc<Event>> = self.sender.subscribe();
+        let Some(last_id) = last_id else {
+            return (Vec::new(), false, receiver);
+        };
+        let backlog: Vec<Arc<Event>> = log
+            .events
+            .iter()
+            .filter(|event| event.id > last_id)
+            .cloned()
+            .collect();
+        let oldest: u64 = log.events.front().map_or(log.next_id, |event| event.id);
+        // Saturating, as the id is whatever the client sent.
+        let next_wanted: u64 = last_id.saturating_add(1);
+        let lost: bool = next_wanted < oldest && next_wanted < log.next_id;
+        (backlog, lost, receiver)
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct EventFilter {
+    /// Only events about questions with this tag.
+    pub tag: Option<String>,
+    /// Only events about this question.
+    pub question_id: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        self.question_id
+            .as_ref()
+            .is_none_or(|id| *id == event.question_id)
+            && self.tag.as_ref().is_none_or(|tag| event.tags.contains(tag))
+    }
+}
+
+enum Delivery {
+    Event(Arc<Event>),
+    Lagged,
+}
+
+fn to_sse(delivery: Delivery) -> Result<sse::Event, Infallible> {
+    Ok(match delivery {
+        Delivery::Event(event) => sse::Event::default()
+            .id(event.id.to_string())
+            .event(event.kind.name())
+            .json_data(&*event)
+            .unwrap_or_else(|_| sse::Event::default().comment("unencodable event")),
+        Delivery::Lagged => sse::Event::default()
+            .event("lagged")
+            .data("events were missed"),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    tag = "events",
+    params(
+        EventFilter,
+        ("Last-Event-ID" = Option<u64>, Header, description = "Resume after this event"),
+    ),
+    responses(
+        (status = 200, description = "Stream of `question_created`, `question_updated`, \
+            `question_deleted`, `answer_created` and `lagged` events", body = Event,
+            content_type = "text/event-stream"),
+    )
+)]
+pub async fn events(
+    State(appstate): HandlerAppState,
+    Query(filter): Query<EventFilter>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let last_id: Option<u64> = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    let (log, shutdown): (Arc<EventLog>, Shutdown) = {
+        let appstate = appstate.read().await;
+        (appstate.store.events.clone(), appstate.shutdown.clone())
+    };
+    let (backlog, lost, receiver) = log.subscribe(last_id);
+
+    let lagged = stream::iter(lost.then_some(Delivery::Lagged));
+    let backlog = stream::iter(backlog.into_iter().map(Delivery::Event));
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((Delivery::Event(event), receiver)),
+            Err(RecvError::Lagged(_)) => Some((Delivery::Lagged, receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    });
+
+    let stream = lagged
+        .chain(backlog)
+        .chain(live)
+        .filter(move |delivery| {
+            futures::future::ready(match delivery {
+                Delivery::Event(event) => filter.matches(event),
+                Delivery::Lagged => true,
+            })
+        })
+        .map(to_sse)
+        .take_until(async move { shutdown.cancelled().await });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::question::QuestionId;
+
+    /// Publish `count` events and return their ids.
+    fn publish(log: &EventLog, count: usize) -> Vec<u64> {
+        let (_, _, mut receiver) = log.subscribe(None);
+        (0..count)
+            .map(|n| {
+                log.publish(Event::question_deleted(&n.to_string(), Vec::new()));
+                receiver.try_recv().unwrap().id
+            })
+            .collect()
+    }
+
+    fn ids(backlog: &[Arc<Event>]) -> Vec<u64> {
+        backlog.iter().map(|event| event.id).collect()
+    }
+
+    #[test]
+    fn subscribe_resumes_after_last_id() {
+        let log: EventLog = EventLog::default();
+        let published: Vec<u64> = publish(&log, 3);
+        let (first, last): (u64, u64) = (published[0], published[2]);
+        assert_eq!(published, vec![first, first + 1, first + 2]);
+
+        // Last-Event-ID, then the ids sent and whether a `lagged` comes first.
+        let cases: [(Option<u64>, Vec<u64>, bool); 8] = [
+            (None, vec![], false),
+            (Some(first - 1), published.clone(), false),
+            (Some(first), published[1..].to_vec(), false),
+            (Some(last), vec![], false),
+            (Some(last + 10), vec![], false),
+            (Some(u64::MAX), vec![], false),
+            (Some(0), published.clone(), true),
+            // From an earlier run, whose events are gone.
+            (Some(first - 100), published.clone(), true),
+        ];
+        for (last_id, expected, lost) in cases {
+            let (backlog, lagged, _) = log.subscribe(last_id);
+            assert_eq!((ids(&backlog), lagged), (expected, lost), "{:?}", last_id);
+        }
+    }
+
+    #[test]
+    fn subscribe_reports_events_dropped_from_the_log() {
+        let log: EventLog = EventLog::default();
+        let published: Vec<u64> = publish(&log, EVENT_LOG_CAPACITY + 2);
+        let (backlog, lost, _) = log.subscribe(Some(published[0]));
+        assert!(lost);
+        assert_eq!(ids(&backlog), published[2..].to_vec());
+        let (backlog, lost, _) = log.subscribe(Some(published[1]));
+        assert!(!lost);
+        assert_eq!(ids(&backlog), published[2..].to_vec());
+    }
+
+    #[test]
+    fn subscribe_receives_later_events() {
+        let log: EventLog = EventLog::default();
+        let published: Vec<u64> = publish(&log, 1);
+        let (backlog, _, mut receiver) = log.subscribe(Some(published[0]));
+        assert!(backlog.is_empty());
+        log.publish(Event::question_deleted("7", Vec::new()));
+        let event: Arc<Event> = receiver.try_recv().unwrap();
+        assert_eq!(
+            (event.id, event.question_id.as_str()),
+            (published[0] + 1, "7")
+        );
+    }
+
+    #[sqlx::test]
+    async fn answers_and_deletions_carry_the_question_tags(pool: PgPool) {
+        let log: Arc<EventLog> = Arc::default();
+        let mut store: Store = Store {
+            connection: pool,
+            events: log.clone(),
+            notify: false,
+        };
+        let question: Question = store
+            .add_question(
+                Question {
+                    id: QuestionId(String::new()),
+                    title: "How?".to_string(),
+                    content: "Please help!".to_string(),
+                    tags: Some(HashSet::from(["rust".to_string(), "async".to_string()])),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        let (_, _, mut receiver) = log.subscribe(None);
+        store.add_answer(&question.id, "Like so.").await.unwrap();
+        store.delete_question(&question.id.0).await.unwrap();
+
+        let filter: EventFilter = EventFilter {
+            tag: Some("rust".to_string()),
+            question_id: None,
+        };
+        for kind in [EventKind::AnswerCreated, EventKind::QuestionDeleted] {
+            let event: Arc<Event> = receiver.try_recv().unwrap();
+            assert_eq!(
+                (event.kind, event.tags.clone()),
+                (kind, vec!["async".to_string(), "rust".to_string()])
+            );
+            assert!(filter.matches(&event), "{:?}", kind);
+        }
+    }
+}
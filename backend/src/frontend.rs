@@ -0,0 +1,159 @@
+//! # Frontend
+//!
+//! Serves the trunk-built Yew bundle (`trunk build --release` in `frontend/`) from
+//! `--frontend-dir`, and `/favicon.ico` from `--favicon`. Any other path that isn't a file in
+//! the bundle gets `index.html`, so the frontend's router can handle it, unless it looks like
+//! a file itself: missing assets are a 404, not a page.
+//!
+//! * Fingerprinted assets (`frontend-<hash>.js`, `index-<hash>.css`, ...) found in the bundle
+//!   are cached for a year as `immutable`; everything else, `index.html` in particular, is
+//!   revalidated.
+//! * A `.br` or `.gz` file next to an asset is served instead when the client accepts it.
+
+use crate::error::Problem;
+use crate::*;
+
+use axum::extract::Request;
+use axum::middleware::{self, Next};
+use core::convert::Infallible;
+use std::path::PathBuf;
+use tower::service_fn;
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Length of the hex hash trunk puts in asset file names.
+const FINGERPRINT_LEN: usize = 16;
+
+const CACHE_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const CACHE_REVALIDATE: &str = "no-cache";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct FrontendArgs {
+    /// Directory holding the built frontend.
+    #[clap(long, env = "FRONTEND_DIR", default_value = "../frontend/dist")]
+    pub frontend_dir: PathBuf,
+    /// Icon served as `/favicon.ico`.
+    #[clap(long, env = "FAVICON", default_value = "assets/static/favicon.ico")]
+    pub favicon: PathBuf,
+}
+
+/// Router serving the bundle, meant as the application's fallback service.
+pub fn router(args: &FrontendArgs) -> Router {
+    let index: ServeFile = ServeFile::new(args.frontend_dir.join("index.html"))
+        .precompressed_br()
+        .precompressed_gzip();
+    let fallback = service_fn(move |request: Request| {
+        let index: ServeFile = index.clone();
+        async move { Ok::<Response, Infallible>(index_fallback(index, request).await) }
+    });
+    let assets = ServeDir::new(&args.frontend_dir)
+        .precompressed_br()
+        .precompressed_gzip()
+        .fallback(fallback);
+    let favicon: ServeFile =
+        ServeFile::new_with_mime(&args.favicon, &"image/vnd.microsoft.icon".parse().unwrap());
+    Router::new()
+        .route_service("/favicon.ico", favicon)
+        .fallback_service(assets)
+        .layer(middleware::from_fn(cache_headers))
+}
+
+/// `index.html` for a path the frontend's router may know, or a 404 for a missing file.
+async fn index_fallback(index: ServeFile, request: Request) -> Response {
+    if is_file(request.uri().path()) {
+        return Problem::from_status(StatusCode::NOT_FOUND).into_response();
+    }
+    match index.oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(never) => match never {},
+    }
+}
+
+/// Whether the last path segment names a file, with an extension or a trunk content hash,
+/// rather than a page of the frontend. Fingerprinted paths are always files, so `index.html`
+/// is never served as `immutable`.
+fn is_file(path: &str) -> bool {
+    let name: &str = path.rsplit('/').next().unwrap_or_default();
+    name.contains('.') || is_fingerprinted(path)
+}
+
+/// Whether the last path segment carries a trunk content hash, as in
+/// `frontend-c05c9a599ff8e3ea_bg.wasm`.
+fn is_fingerprinted(path: &str) -> bool {
+    let name: &str = path.rsplit('/').next().unwrap_or_default();
+    name.rsplit_once('-').is_some_and(|(_, rest)| {
+        rest.bytes().take_while(u8::is_ascii_hexdigit).count() == FINGERPRINT_LEN
+    })
+}
+
+async fn cache_headers(request: Request, next: Next) -> Response {
+    let fingerprinted: bool = is_fingerprinted(request.uri().path());
+    let mut response: Response = next.run(request).await;
+    if response.status().is_success() {
+        let value: &'static str = if fingerprinted {
+            CACHE_IMMUTABLE
+        } else {
+            CACHE_REVALIDATE
+        };
+        response
+            .headers_mut()
+            .insert(http::header::CACHE_CONTROL, HeaderValue::from_static(value));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    #[test]
+    fn is_file() {
+        for (path, file) in [
+            ("/", false),
+            ("/questions/7", false),
+            ("/frontend-c05c9a599ff8e3ea_bg.wasm", true),
+            ("/index-a0ba8ab7eb937935.css", true),
+            ("/frontend-c05c9a599ff8e3ea", true),
+            ("/robots.txt", true),
+            ("/ask-a-question", false),
+        ] {
+            assert_eq!(super::is_file(path), file, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_files_pages_and_404s() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("frontend-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("app-0123456789abcdef.js"), "").unwrap();
+        std::fs::write(dir.join("favicon.ico"), "").unwrap();
+        let app: Router = router(&FrontendArgs {
+            frontend_dir: dir.clone(),
+            favicon: dir.join("favicon.ico"),
+        });
+
+        for (path, status, cache) in [
+            ("/", StatusCode::OK, Some(CACHE_REVALIDATE)),
+            ("/questions/7", StatusCode::OK, Some(CACHE_REVALIDATE)),
+            (
+                "/app-0123456789abcdef.js",
+                StatusCode::OK,
+                Some(CACHE_IMMUTABLE),
+            ),
+            ("/app-fedcba9876543210.js", StatusCode::NOT_FOUND, None),
+            ("/missing.png", StatusCode::NOT_FOUND, None),
+            ("/favicon.ico", StatusCode::OK, Some(CACHE_REVALIDATE)),
+        ] {
+            let request: Request = Request::get(path).body(Body::empty()).unwrap();
+            let response: Response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+            let cache_control: Option<&str> = response
+                .headers()
+                .get(http::header::CACHE_CONTROL)
+                .and_then(|v| v.to_str().ok());
+            assert_eq!(cache_control, cache, "{}", path);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,635 @@
+//! # GraphQL
+//!
+//! `POST /graphql` runs GraphQL queries and mutations over questions, their tags, answers and
+//! authors, so a client can fetch a page of questions with everything it shows in one round
+//! trip. `GET /graphql` serves the GraphiQL playground, which also shows the schema.
+//!
+//! Tags, answers and authors are resolved through per-request [`DataLoader`]s, so a page of
+//! questions costs one query for each of them rather than one per question. Mutations go through
+//! the [`Store`] like the REST API, so they publish the same events, and need the same bearer
+//! token; queries don't. Queries are limited to a depth of [`MAX_DEPTH`] and a complexity of
+//! [`MAX_COMPLEXITY`].
+
+use crate::appstate::{HandlerAppState, SharedAppState};
+use crate::auth::{AuthError, Claims};
+use crate::error::{Problem, StoreErr};
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::question::{Question, QuestionId};
+use crate::validation::field_errors;
+use crate::*;
+use question_api::QuestionResource;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{
+    ComplexObject, Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema,
+    SimpleObject, ID,
+};
+use axum::response::Html;
+use validator::Validate;
+
+/// Deepest selection a query may make.
+pub const MAX_DEPTH: usize = 10;
+
+/// Highest complexity a query may have, counting one per field.
+pub const MAX_COMPLEXITY: usize = 1000;
+
+pub type QuestionSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+pub fn schema() -> QuestionSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// A GraphQL error carrying the problem's status, type and field errors as extensions.
+fn problem_error(problem: Problem) -> async_graphql::Error {
+    let message: String = problem.detail.clone().unwrap_or(problem.title.clone());
+    async_graphql::Error::new(message).extend_with(|_, extensions| {
+        extensions.set("status", problem.status);
+        extensions.set("type", problem.kind.as_str());
+        if let Some(errors) = &problem.errors {
+            let errors: serde_json::Value = serde_json::to_value(errors).unwrap_or_default();
+            if let Ok(errors) = async_graphql::Value::from_json(errors) {
+                extensions.set("errors", errors);
+            }
+        }
+    })
+}
+
+fn store_error(e: impl Into<StoreErr>) -> async_graphql::Error {
+    problem_error(Problem::from(e.into()))
+}
+
+/// Data loaders share their error between the fields waiting on them, so it can't be turned
+/// into a [`Problem`]; it is logged and reported as an internal error.
+fn load_error(e: Arc<StoreErr>) -> async_graphql::Error {
+    tracing::error!("graphql: loading: {}", e);
+    problem_error(Problem::from_status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// The caller's claims, or an error for mutations made without a valid token.
+fn claims<'c>(ctx: &'c Context<'_>) -> async_graphql::Result<&'c Claims> {
+    ctx.data_unchecked::<Result<Claims, AuthError>>()
+        .as_ref()
+        .map_err(|e| {
+            problem_error(
+                Problem::new(StatusCode::UNAUTHORIZED, "unauthorized", "Unauthorized")
+                    .with_detail(e.to_string()),
+            )
+        })
+}
+
+fn store<'c>(ctx: &'c Context<'_>) -> &'c Store {
+    ctx.data_unchecked::<Store>()
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(name = "Question", complex)]
+pub struct QuestionNode {
+    #[graphql(skip)]
+    id: String,
+    title: String,
+    content: String,
+}
+
+#[ComplexObject]
+impl QuestionNode {
+    async fn id(&self) -> ID {
+        ID(self.id.clone())
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<String>> {
+        let loader: &DataLoader<TagLoader> = ctx.data_unchecked();
+        let tags: Option<Vec<String>> =
+            loader.load_one(self.id.clone()).await.map_err(load_error)?;
+        Ok(tags.unwrap_or_default())
+    }
+
+    async fn answers(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<AnswerNode>> {
+        let loader: &DataLoader<AnswerLoader> = ctx.data_unchecked();
+        let answers: Option<Vec<AnswerNode>> =
+            loader.load_one(self.id.clone()).await.map_err(load_error)?;
+        Ok(answers.unwrap_or_default())
+    }
+
+    /// Who asked the question, if known.
+    async fn author(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Author>> {
+        let loader: &DataLoader<AuthorLoader> = ctx.data_unchecked();
+        loader.load_one(self.id.clone()).await.map_err(load_error)
+    }
+}
+
+#[derive(Debug, Clone, SimpleObject)]
+#[graphql(name = "Answer", complex)]
+pub struct AnswerNode {
+    #[graphql(skip)]
+    id: String,
+    content: String,
+    #[graphql(skip)]
+    question_id: String,
+}
+
+#[ComplexObject]
+impl AnswerNode {
+    async fn id(&self) -> ID {
+        ID(self.id.clone())
+    }
+
+    async fn question_id(&self) -> ID {
+        ID(self.question_id.clone())
+    }
+}
+
+/// The user who asked a question. Only the name is public.
+#[derive(Debug, Clone, SimpleObject)]
+pub struct Author {
+    name: String,
+}
+
+/// A page of questions matching a filter.
+#[derive(Debug, SimpleObject)]
+pub struct QuestionPage {
+    nodes: Vec<QuestionNode>,
+    /// Questions matching the filter, on all pages.
+    total_count: i64,
+    has_next_page: bool,
+}
+
+/// Tags of questions, by question id.
+pub struct TagLoader(Store);
+
+impl Loader<String> for TagLoader {
+    type Value = Vec<String>;
+    type Error = Arc<StoreErr>;
+
+    async fn load(&self, ids: &[String]) -> Result<HashMap<String, Vec<String>>, Self::Error> {
+        Ok(self.0.tags_by_question(ids).await?)
+    }
+}
+
+/// Answers to questions, by question id.
+pub struct AnswerLoader(Store);
+
+impl Loader<String> for AnswerLoader {
+    type Value = Vec<AnswerNode>;
+    type Error = Arc<StoreErr>;
+
+    async fn load(&self, ids: &[String]) -> Result<HashMap<String, Vec<AnswerNode>>, Self::Error> {
+        let mut answers: HashMap<String, Vec<AnswerNode>> = HashMap::new();
+        for answer in self.0.answers_by_question(ids).await? {
+            answers
+                .entry(answer.question_id.0.clone())
+                .or_default()
+                .push(answer_node(answer));
+        }
+        Ok(answers)
+    }
+}
+
+/// Authors of questions, by question id.
+pub struct AuthorLoader(Store);
+
+impl Loader<String> for AuthorLoader {
+    type Value = Author;
+    type Error = Arc<StoreErr>;
+
+    async fn load(&self, ids: &[String]) -> Result<HashMap<String, Author>, Self::Error> {
+        let authors: HashMap<String, String> = self.0.question_authors(ids).await?;
+        Ok(authors
+            .into_iter()
+            .map(|(id, name)| (id, Author { name }))
+            .collect())
+    }
+}
+
+fn resource_node(question: QuestionResource) -> QuestionNode {
+    QuestionNode {
+        id: question.id.to_string(),
+        title: question.title,
+        content: question.content,
+    }
+}
+
+fn answer_node(answer: Answer) -> AnswerNode {
+    AnswerNode {
+        id: answer.id.0,
+        content: answer.content,
+        question_id: answer.question_id.0,
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn question(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+    ) -> async_graphql::Result<Option<QuestionNode>> {
+        let Ok(id) = id.parse::<i64>() else {
+            return Ok(None);
+        };
+        match store(ctx).question_resource(id).await {
+            Ok(question) => Ok(Some(resource_node(question))),
+            Err(StoreErr::QuestionNotFound(_)) => Ok(None),
+            Err(e) => Err(store_error(e)),
+        }
+    }
+
+    /// Questions in id order, optionally only those with `tag` or containing `search` in
+    /// their title or content.
+    async fn questions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 20, validator(minimum = 1, maximum = 100))] limit: i64,
+        #[graphql(default = 0, validator(minimum = 0))] offset: i64,
+        tag: Option<String>,
+        search: Option<String>,
+    ) -> async_graphql::Result<QuestionPage> {
+        let store: &Store = store(ctx);
+        let (tag, search): (Option<&str>, Option<&str>) = (tag.as_deref(), search.as_deref());
+        let total_count: i64 = store
+            .count_questions(tag, search)
+            .await
+            .map_err(store_error)?;
+        let nodes: Vec<QuestionNode> = store
+            .list_questions(tag, search, limit, offset)
+            .await
+            .map_err(store_error)?
+            .into_iter()
+            .map(resource_node)
+            .collect();
+        Ok(QuestionPage {
+            has_next_page: offset + (nodes.len() as i64) < total_count,
+            nodes,
+            total_count,
+        })
+    }
+}
+
+/// A question as submitted; its id comes from the mutation's arguments, or from the database
+/// for a new one.
+#[derive(Debug, InputObject)]
+pub struct QuestionInput {
+    title: String,
+    content: String,
+    tags: Option<Vec<String>>,
+}
+
+impl QuestionInput {
+    /// The question to store, checked like the REST API's.
+    fn into_question(self, id: &ID) -> async_graphql::Result<Question> {
+        let question: Question = Question {
+            id: QuestionId(id.to_string()),
+            title: self.title,
+            content: self.content,
+            tags: self.tags.map(|tags| tags.into_iter().collect()),
+        };
+        question.validate().map_err(|errors| {
+            problem_error(
+                Problem::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "validation",
+                    "Invalid question",
+                )
+                .with_errors(field_errors(&errors)),
+            )
+        })?;
+        Ok(question)
+    }
+}
+
+fn node(question: &Question) -> QuestionNode {
+    QuestionNode {
+        id: question.id.0.clone(),
+        title: question.title.clone(),
+        content: question.content.clone(),
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Add a question under the id the database assigns.
+    async fn create_question(
+        &self,
+        ctx: &Context<'_>,
+        question: QuestionInput,
+    ) -> async_graphql::Result<QuestionNode> {
+        let claims: &Claims = claims(ctx)?;
+        let question: Question = question.into_question(&ID::default())?;
+        let appstate = ctx.data_unchecked::<SharedAppState>().read().await;
+        let question: Question = appstate
+            .store
+            .add_question(question, Some(&claims.sub))
+            .await
+            .map_err(store_error)?;
+        Ok(node(&question))
+    }
+
+    async fn update_question(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        question: QuestionInput,
+    ) -> async_graphql::Result<QuestionNode> {
+        claims(ctx)?;
+        let question: Question = question.into_question(&id)?;
+        let question: Question = ctx
+            .data_unchecked::<SharedAppState>()
+            .write()
+            .await
+            .store
+            .update_question(&id, question)
+            .await
+            .map_err(store_error)?;
+        Ok(node(&question))
+    }
+
+    /// Delete a question; true once it is gone.
+    async fn delete_question(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<bool> {
+        claims(ctx)?;
+        ctx.data_unchecked::<SharedAppState>()
+            .write()
+            .await
+            .store
+            .delete_question(&id)
+            .await
+            .map_err(store_error)?;
+        Ok(true)
+    }
+
+    async fn create_answer(
+        &self,
+        ctx: &Context<'_>,
+        question_id: ID,
+        content: String,
+    ) -> async_graphql::Result<AnswerNode> {
+        claims(ctx)?;
+        let answer: NewAnswer = NewAnswer { content };
+        answer.validate().map_err(|errors| {
+            problem_error(
+                Problem::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "validation",
+                    "Invalid answer",
+                )
+                .with_errors(field_errors(&errors)),
+            )
+        })?;
+        let answer = ctx
+            .data_unchecked::<SharedAppState>()
+            .read()
+            .await
+            .store
+            .add_answer(&QuestionId(question_id.to_string()), &answer.content)
+            .await
+            .map_err(store_error)?;
+        Ok(answer_node(answer))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    security((), ("bearer" = []), ("api_key" = [])),
+    request_body(
+        content = Object,
+        description = "GraphQL request: `query`, and optionally `operationName` and `variables`"
+    ),
+    responses(
+        (status = 200, description = "GraphQL response, with `data` and any `errors`",
+            body = Object),
+    )
+)]
+pub async fn graphql(
+    State(appstate): HandlerAppState,
+    claims: Result<Claims, AuthError>,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    let (schema, store): (QuestionSchema, Store) = {
+        let state = appstate.read().await;
+        (state.graphql.clone(), state.store.clone())
+    };
+    let request: async_graphql::Request = request
+        .data(appstate)
+        .data(claims)
+        .data(DataLoader::new(TagLoader(store.clone()), tokio::spawn))
+        .data(DataLoader::new(AnswerLoader(store.clone()), tokio::spawn))
+        .data(DataLoader::new(AuthorLoader(store.clone()), tokio::spawn))
+        .data(store);
+    Json(schema.execute(request).await).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses(
+        (status = 200, description = "GraphiQL playground", body = String,
+            content_type = "text/html"),
+    )
+)]
+pub async fn graphiql() -> Response {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::issue_jwt_token;
+    use crate::metrics::METRICS;
+    use crate::startup::test_router;
+    use axum::body::Body;
+    use http::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    const ANN: &str = "Ann <ann@example.org>";
+
+    /// The response to `query`, made with `token` if given.
+    async fn execute(app: &Router, token: Option<&str>, query: &str) -> Value {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("/graphql")
+            .header(http::header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body: Body = Body::from(json!({ "query": query }).to_string());
+        let response: Response = app
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "{}", query);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn token(state: &SharedAppState) -> String {
+        let appstate = state.read().await;
+        issue_jwt_token(&appstate, ANN.to_string(), false)
+            .unwrap()
+            .access_token
+    }
+
+    fn new_question(title: &str, tags: &[&str]) -> Question {
+        Question {
+            id: QuestionId(String::new()),
+            title: title.to_string(),
+            content: "Please help!".to_string(),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+        }
+    }
+
+    #[sqlx::test]
+    async fn mutations_need_a_token(pool: PgPool) {
+        let (app, state) = test_router(pool);
+        let token: String = token(&state).await;
+        let store: Store = state.read().await.store.clone();
+        let id: String = store
+            .add_question(new_question("How?", &[]), None)
+            .await
+            .unwrap()
+            .id
+            .0;
+        let unchanged = || async {
+            let question: Question = store.get(&id).await.unwrap();
+            let answers: Vec<Answer> = store
+                .answers_by_question(std::slice::from_ref(&id))
+                .await
+                .unwrap();
+            let total: i64 = store.count_questions(None, None).await.unwrap();
+            (question.title, answers.len(), total) == ("How?".to_string(), 0, 1)
+        };
+
+        let input: &str = r#"question: {title: "What?", content: "Tell me."}"#;
+        let mutations: [String; 4] = [
+            format!("mutation {{ createQuestion({}) {{ id }} }}", input),
+            format!(
+                r#"mutation {{ updateQuestion(id: "{}", {}) {{ id }} }}"#,
+                id, input
+            ),
+            format!(
+                r#"mutation {{ createAnswer(questionId: "{}", content: "Like so.") {{ id }} }}"#,
+                id
+            ),
+            format!(r#"mutation {{ deleteQuestion(id: "{}") }}"#, id),
+        ];
+        for mutation in &mutations {
+            let response: Value = execute(&app, None, mutation).await;
+            assert_eq!(response["data"], Value::Null, "{}", mutation);
+            assert_eq!(
+                response["errors"][0]["extensions"]["status"], 401,
+                "{}",
+                mutation
+            );
+            assert!(unchanged().await, "{}", mutation);
+        }
+        for mutation in &mutations {
+            let response: Value = execute(&app, Some(&token), mutation).await;
+            assert_eq!(response.get("errors"), None, "{}", mutation);
+        }
+        assert!(store.get(&id).await.is_err());
+    }
+
+    #[sqlx::test]
+    async fn a_page_costs_one_query_per_loader(pool: PgPool) {
+        let (app, state) = test_router(pool);
+        let token: String = token(&state).await;
+        for (title, tags) in [
+            ("How?", &["rust"][..]),
+            ("Why?", &["go", "rust"]),
+            ("What?", &[]),
+        ] {
+            let input: String = format!(
+                r#"{{title: "{}", content: "Please help!", tags: {:?}}}"#,
+                title, tags
+            );
+            let mutation: String = format!(
+                "mutation {{ createQuestion(question: {}) {{ id }} }}",
+                input
+            );
+            let response: Value = execute(&app, Some(&token), &mutation).await;
+            let id: &str = response["data"]["createQuestion"]["id"].as_str().unwrap();
+            let answer: String = format!(
+                r#"mutation {{ createAnswer(questionId: "{}", content: "Like so.") {{ id }} }}"#,
+                id
+            );
+            execute(&app, Some(&token), &answer).await;
+        }
+
+        let loads: [&str; 3] = [
+            "tags_by_question",
+            "answers_by_question",
+            "question_authors",
+        ];
+        let before: Vec<u64> = loads.map(|load| METRICS.store_operations(load)).to_vec();
+        let query: &str =
+            "{ questions { nodes { title tags answers { content } author { name } } } }";
+        let response: Value = execute(&app, None, query).await;
+        let after: Vec<u64> = loads.map(|load| METRICS.store_operations(load)).to_vec();
+
+        let nodes: &Value = &response["data"]["questions"]["nodes"];
+        assert_eq!(nodes.as_array().map(Vec::len), Some(3), "{}", response);
+        let answer: Value = json!([{ "content": "Like so." }]);
+        let author: Value = json!({ "name": "Ann" });
+        let expected: [(&str, Value); 3] = [
+            ("How?", json!(["rust"])),
+            ("Why?", json!(["go", "rust"])),
+            ("What?", json!([])),
+        ];
+        for (node, (title, tags)) in nodes.as_array().unwrap().iter().zip(expected) {
+            assert_eq!(node["title"], title);
+            assert_eq!(node["tags"], tags, "{}", title);
+            assert_eq!(node["answers"], answer, "{}", title);
+            assert_eq!(node["author"], author, "{}", title);
+        }
+        let calls: Vec<u64> = after.iter().zip(&before).map(|(a, b)| a - b).collect();
+        assert_eq!(calls, vec![1, 1, 1]);
+    }
+
+    #[sqlx::test]
+    async fn deep_and_complex_queries_are_refused(pool: PgPool) {
+        let (app, _) = test_router(pool);
+        // Introspection nests as deep as asked; `type` is at depth 4.
+        let nested = |depth: usize| {
+            let of_types: usize = depth - 5;
+            format!(
+                "{{ __schema {{ types {{ fields {{ type {{ {}name{} }} }} }} }} }}",
+                "ofType { ".repeat(of_types),
+                " }".repeat(of_types)
+            )
+        };
+        let aliased = |count: usize| {
+            let fields: Vec<String> = (0..count)
+                .map(|n| format!(r#"q{}: question(id: "1") {{ id }}"#, n))
+                .collect();
+            format!("{{ {} }}", fields.join(" "))
+        };
+
+        // Query, and the error it gets, if any.
+        let cases: [(String, Option<&str>); 4] = [
+            (nested(MAX_DEPTH), None),
+            (nested(MAX_DEPTH + 1), Some("Query is nested too deep.")),
+            (aliased(MAX_COMPLEXITY / 2), None),
+            (
+                aliased(MAX_COMPLEXITY / 2 + 1),
+                Some("Query is too complex."),
+            ),
+        ];
+        for (query, error) in cases {
+            let response: Value = execute(&app, None, &query).await;
+            let message: Option<&str> = response["errors"][0]["message"].as_str();
+            assert_eq!(message, error, "{}", &query[..query.len().min(80)]);
+        }
+    }
+}
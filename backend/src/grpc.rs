@@ -0,0 +1,513 @@
+//! # gRPC
+//!
+//! The question, answer and tag operations as the `questions.v1.Questions` service of
+//! `proto/questions.proto`, for internal services that speak gRPC. It is served on its own
+//! listener, `--grpc-addr`, and stops with the HTTP server on shutdown.
+//!
+//! Callers authenticate with the REST API's bearer tokens, sent as `authorization` metadata.
+//! A token that is present is checked on every call and a bad one refused with
+//! `UNAUTHENTICATED`; calls that change anything need one. Writes go through the [`Store`], so
+//! they publish the same events as the REST API.
+//!
+//! `ListQuestions` and `ListAnswers` stream their results, reading
+//! [`STREAM_BATCH`] rows at a time so long lists don't have to fit in memory.
+//!
+//! Building needs `protoc`; see `build.rs`.
+
+// Every call fails with tonic's `Status`, however large.
+#![allow(clippy::result_large_err)]
+
+use crate::appstate::SharedAppState;
+use crate::auth::{Claims, JwtKeys};
+use crate::error::{Problem, StoreErr};
+use crate::shutdown::Shutdown;
+use crate::types::answer::NewAnswer;
+use crate::types::question::{Question, QuestionId};
+use crate::validation::field_errors;
+use crate::*;
+use question_api::{AnswerResource, QuestionResource};
+
+use futures::Stream;
+use std::pin::Pin;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Status};
+use validator::Validate;
+
+pub mod proto {
+    tonic::include_proto!("questions.v1");
+}
+
+use proto::questions_server::{Questions, QuestionsServer};
+
+/// Rows read from the database at a time by the streaming calls.
+pub const STREAM_BATCH: i64 = 100;
+
+/// Results a streaming call buffers ahead of a slow client.
+const STREAM_BUFFER: usize = 16;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GrpcArgs {
+    /// Address to serve the gRPC API on, separately from the HTTP server.
+    #[clap(long, env = "GRPC_ADDR", default_value = "0.0.0.0:50051")]
+    pub grpc_addr: String,
+}
+
+/// The status for a store error, using the problem it maps to for the REST API.
+fn status(e: impl Into<StoreErr>) -> Status {
+    let problem: Problem = Problem::from(e.into());
+    let message: String = problem.detail.clone().unwrap_or(problem.title.clone());
+    match problem.status {
+        400 | 413 | 422 => Status::invalid_argument(message),
+        401 => Status::unauthenticated(message),
+        403 => Status::permission_denied(message),
+        404 => Status::not_found(message),
+        409 => Status::already_exists(message),
+        _ => {
+            tracing::error!(trace_id = %problem.trace_id, "grpc: {}", message);
+            Status::internal(problem.title)
+        }
+    }
+}
+
+/// Checks the bearer token of calls that send one and leaves its claims in the request.
+fn authenticate(
+    jwt_keys: JwtKeys,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |mut request: Request<()>| {
+        let Some(value) = request.metadata().get("authorization") else {
+            return Ok(request);
+        };
+        let token: &str = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("authorization is not a bearer token"))?;
+        let claims: Claims = jwt_keys
+            .decode_claims(token)
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+        request.extensions_mut().insert(claims);
+        Ok(request)
+    }
+}
+
+fn claims<T>(request: &Request<T>) -> Result<Claims, Status> {
+    request
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| Status::unauthenticated("missing bearer token"))
+}
+
+/// The question to store, checked like the REST API's.
+fn question(question: Option<proto::Question>) -> Result<Question, Status> {
+    let question: proto::Question =
+        question.ok_or_else(|| Status::invalid_argument("question is required"))?;
+    let question: Question = Question {
+        id: QuestionId(question.id),
+        title: question.title,
+        content: question.content,
+        tags: (!question.tags.is_empty()).then(|| question.tags.into_iter().collect()),
+    };
+    question.validate().map_err(|errors| {
+        let fields: Vec<String> = field_errors(&errors)
+            .into_iter()
+            .map(|error| format!("{}: {}", error.field, error.message))
+            .collect();
+        Status::invalid_argument(fields.join("; "))
+    })?;
+    Ok(question)
+}
+
+fn reply(question: Question) -> proto::Question {
+    let mut tags: Vec<String> = question.tags.into_iter().flatten().collect();
+    tags.sort();
+    proto::Question {
+        id: question.id.0,
+        title: question.title,
+        content: question.content,
+        tags,
+    }
+}
+
+fn resource_reply(question: QuestionResource) -> proto::Question {
+    proto::Question {
+        id: question.id.to_string(),
+        title: question.title,
+        content: question.content,
+        tags: question.tags,
+    }
+}
+
+fn answer_reply(answer: AnswerResource) -> proto::Answer {
+    proto::Answer {
+        id: answer.id.to_string(),
+        question_id: answer.question_id.to_string(),
+        content: answer.content,
+    }
+}
+
+/// The numeric id of the question `id` names; anything else names no question.
+fn question_id(id: &str) -> Result<i64, Status> {
+    id.parse()
+        .map_err(|_| status(StoreErr::QuestionNotFound(id.to_string())))
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// The stream of what `produce` sends, ending when it returns. Stops early once the client
+/// has gone.
+fn stream<T, F>(produce: impl FnOnce(mpsc::Sender<Result<T, Status>>) -> F) -> ResponseStream<T>
+where
+    T: Send + 'static,
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(produce(sender));
+    Box::pin(futures::stream::poll_fn(move |cx| receiver.poll_recv(cx)))
+}
+
+pub struct QuestionService {
+    state: SharedAppState,
+}
+
+impl QuestionService {
+    async fn store(&self) -> Store {
+        self.state.read().await.store.clone()
+    }
+}
+
+#[tonic::async_trait]
+impl Questions for QuestionService {
+    type ListQuestionsStream = ResponseStream<proto::Question>;
+    type ListAnswersStream = ResponseStream<proto::Answer>;
+
+    async fn get_question(
+        &self,
+        request: Request<proto::GetQuestionRequest>,
+    ) -> Result<tonic::Response<proto::Question>, Status> {
+        let id: i64 = question_id(&request.into_inner().id)?;
+        let question: QuestionResource = self
+            .store()
+            .await
+            .question_resource(id)
+            .await
+            .map_err(status)?;
+        Ok(tonic::Response::new(resource_reply(question)))
+    }
+
+    async fn list_questions(
+        &self,
+        request: Request<proto::ListQuestionsRequest>,
+    ) -> Result<tonic::Response<Self::ListQuestionsStream>, Status> {
+        let request: proto::ListQuestionsRequest = request.into_inner();
+        let store: Store = self.store().await;
+        let limit: Option<i64> = (request.limit > 0).then_some(i64::from(request.limit));
+        Ok(tonic::Response::new(stream(move |sender| async move {
+            let (tag, search): (Option<&str>, Option<&str>) =
+                (request.tag.as_deref(), request.search.as_deref());
+            // The offset is skipped once; later batches start after the last id sent.
+            let mut after: Option<i64> = None;
+            let mut sent: i64 = 0;
+            loop {
+                let batch: i64 =
+                    limit.map_or(STREAM_BATCH, |limit| (limit - sent).min(STREAM_BATCH));
+                if batch <= 0 {
+                    return;
+                }
+                let read = match after {
+                    None => {
+                        let offset: i64 = i64::from(request.offset);
+                        store.list_questions(tag, search, batch, offset).await
+                    }
+                    Some(after) => store.list_questions_after(tag, search, after, batch).await,
+                };
+                let questions: Vec<QuestionResource> = match read {
+                    Ok(questions) => questions,
+                    Err(e) => {
+                        let _ = sender.send(Err(status(e))).await;
+                        return;
+                    }
+                };
+                let read: i64 = questions.len() as i64;
+                after = questions.last().map(|question| question.id);
+                for question in questions {
+                    if sender.send(Ok(resource_reply(question))).await.is_err() {
+                        return;
+                    }
+                }
+                if read < batch {
+                    return;
+                }
+                sent += read;
+            }
+        })))
+    }
+
+    async fn create_question(
+        &self,
+        request: Request<proto::CreateQuestionRequest>,
+    ) -> Result<tonic::Response<proto::Question>, Status> {
+        let claims: Claims = claims(&request)?;
+        let question: Question = question(request.into_inner().question)?;
+        let appstate = self.state.read().await;
+        let question: Question = appstate
+            .store
+            .add_question(question, Some(&claims.sub))
+            .await
+            .map_err(status)?;
+        Ok(tonic::Response::new(reply(question)))
+    }
+
+    async fn update_question(
+        &self,
+        request: Request<proto::UpdateQuestionRequest>,
+    ) -> Result<tonic::Response<proto::Question>, Status> {
+        claims(&request)?;
+        let question: Question = question(request.into_inner().question)?;
+        let id: String = question.id.0.clone();
+        let question: Question = self
+            .state
+            .write()
+            .await
+            .store
+            .update_question(&id, question)
+            .await
+            .map_err(status)?;
+        Ok(tonic::Response::new(reply(question)))
+    }
+
+    async fn delete_question(
+        &self,
+        request: Request<proto::DeleteQuestionRequest>,
+    ) -> Result<tonic::Response<proto::DeleteQuestionResponse>, Status> {
+        claims(&request)?;
+        let id: String = request.into_inner().id;
+        self.state
+            .write()
+            .await
+            .store
+            .delete_question(&id)
+            .await
+            .map_err(status)?;
+        Ok(tonic::Response::new(proto::DeleteQuestionResponse {}))
+    }
+
+    async fn list_answers(
+        &self,
+        request: Request<proto::ListAnswersRequest>,
+    ) -> Result<tonic::Response<Self::ListAnswersStream>, Status> {
+        let question_id: i64 = question_id(&request.into_inner().question_id)?;
+        let store: Store = self.store().await;
+        Ok(tonic::Response::new(stream(move |sender| async move {
+            let mut after: i64 = 0;
+            loop {
+                let answers: Vec<AnswerResource> = match store
+                    .list_answers_after(question_id, after, STREAM_BATCH)
+                    .await
+                {
+                    Ok(answers) => answers,
+                    Err(e) => {
+                        let _ = sender.send(Err(status(e))).await;
+                        return;
+                    }
+                };
+                let read: i64 = answers.len() as i64;
+                after = answers.last().map_or(after, |answer| answer.id);
+                for answer in answers {
+                    if sender.send(Ok(answer_reply(answer))).await.is_err() {
+                        return;
+                    }
+                }
+                if read < STREAM_BATCH {
+                    return;
+                }
+            }
+        })))
+    }
+
+    async fn create_answer(
+        &self,
+        request: Request<proto::CreateAnswerRequest>,
+    ) -> Result<tonic::Response<proto::Answer>, Status> {
+        claims(&request)?;
+        let request: proto::CreateAnswerRequest = request.into_inner();
+        let answer: NewAnswer = NewAnswer {
+            content: request.content,
+        };
+        answer
+            .validate()
+            .map_err(|_| Status::invalid_argument("content must be 1 to 10000 characters"))?;
+        let answer = self
+            .state
+            .read()
+            .await
+            .store
+            .add_answer(&QuestionId(request.question_id), &answer.content)
+            .await
+            .map_err(status)?;
+        Ok(tonic::Response::new(proto::Answer {
+            id: answer.id.0,
+            question_id: answer.question_id.0,
+            content: answer.content,
+        }))
+    }
+
+    async fn list_tags(
+        &self,
+        _request: Request<proto::ListTagsRequest>,
+    ) -> Result<tonic::Response<proto::ListTagsResponse>, Status> {
+        let tags: Vec<(String, i64)> = self.store().await.tag_counts().await.map_err(status)?;
+        Ok(tonic::Response::new(proto::ListTagsResponse {
+            tags: tags
+                .into_iter()
+                .map(|(tag, questions)| proto::TagCount {
+                    tag,
+                    questions: u32::try_from(questions).unwrap_or(u32::MAX),
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// Serve the gRPC API on `listener` until shutdown, then wait for in-flight calls. Messages
+/// are limited to `max_message_bytes`, like the JSON API's bodies.
+pub async fn serve(
+    listener: TcpListener,
+    state: SharedAppState,
+    max_message_bytes: usize,
+) -> Result<(), tonic::transport::Error> {
+    let (jwt_keys, shutdown): (JwtKeys, Shutdown) = {
+        let appstate = state.read().await;
+        (appstate.jwt_keys.clone(), appstate.shutdown.clone())
+    };
+    let service = InterceptedService::new(
+        QuestionsServer::new(QuestionService { state })
+            .max_decoding_message_size(max_message_bytes),
+        authenticate(jwt_keys),
+    );
+    let incoming: TcpIncoming =
+        TcpIncoming::from_listener(listener, true, None).expect("wrapping a listener can't fail");
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(incoming, async move { shutdown.cancelled().await })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::appstate::AppState;
+    use futures::StreamExt;
+
+    fn service(pool: PgPool) -> QuestionService {
+        let store: Store = Store {
+            connection: pool,
+            events: Arc::default(),
+            notify: false,
+        };
+        let state: AppState = AppState::new(
+            store,
+            JwtKeys::new(b"test"),
+            String::new(),
+            None,
+            HashSet::new(),
+            Shutdown::default(),
+        );
+        QuestionService {
+            state: Arc::new(RwLock::new(state)),
+        }
+    }
+
+    #[sqlx::test]
+    async fn get_question_reads_tags(pool: PgPool) {
+        let service: QuestionService = service(pool);
+        let question: Question = service
+            .store()
+            .await
+            .add_question(
+                Question {
+                    id: QuestionId(String::new()),
+                    title: "How?".to_string(),
+                    content: "Please help!".to_string(),
+                    tags: Some(HashSet::from(["rust".to_string(), "async".to_string()])),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        let request = proto::GetQuestionRequest {
+            id: question.id.0.clone(),
+        };
+        let reply: proto::Question = service
+            .get_question(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(reply.id, question.id.0);
+        assert_eq!(reply.tags, vec!["async", "rust"]);
+
+        for id in ["0", "seven"] {
+            let request = proto::GetQuestionRequest { id: id.to_string() };
+            let error: Status = service
+                .get_question(Request::new(request))
+                .await
+                .unwrap_err();
+            assert_eq!(error.code(), tonic::Code::NotFound, "{}", id);
+        }
+    }
+
+    #[sqlx::test]
+    async fn list_questions_streams_past_a_batch(pool: PgPool) {
+        sqlx::query(
+            r#"INSERT INTO questions (title, content)
+            SELECT 'Question ' || n, 'Please help!' FROM generate_series(1, $1) n;"#,
+        )
+        .bind(2 * STREAM_BATCH as i32 + 5)
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            r#"INSERT INTO tags (id, tag) SELECT id, 'even' FROM questions WHERE id % 2 = 0;"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let ids: Vec<(i64,)> = sqlx::query_as(r#"SELECT id::BIGINT FROM questions ORDER BY id;"#)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let ids: Vec<String> = ids.iter().map(|(id,)| id.to_string()).collect();
+        let even: Vec<String> = ids.iter().skip(1).step_by(2).cloned().collect();
+        let service: QuestionService = service(pool);
+
+        let batch: usize = STREAM_BATCH as usize;
+        // Tag, offset and limit, then the ids sent.
+        let cases: [(Option<&str>, u32, u32, &[String]); 4] = [
+            (None, 0, 0, &ids),
+            (None, 3, 0, &ids[3..]),
+            (None, 1, batch as u32 + 1, &ids[1..batch + 2]),
+            (Some("even"), 2, 0, &even[2..]),
+        ];
+        for (tag, offset, limit, expected) in cases {
+            let request = proto::ListQuestionsRequest {
+                tag: tag.map(str::to_string),
+                search: None,
+                offset,
+                limit,
+            };
+            let sent: Vec<String> = service
+                .list_questions(Request::new(request))
+                .await
+                .unwrap()
+                .into_inner()
+                .map(|question| question.unwrap().id)
+                .collect()
+                .await;
+            assert_eq!(sent, expected, "{:?} {} {}", tag, offset, limit);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! # Health Checks
+//!
+//! * `GET /healthz` answers as long as the process is serving requests (liveness).
+//! * `GET /readyz` checks the service's dependencies (readiness): the database answers, every
+//!   migration shipped with this build has been applied, and the JWT keys are loaded.
+//!   It answers `503 Service Unavailable` when any component is down.
+
+use crate::appstate::HandlerAppState;
+use crate::*;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// How long the readiness probe waits for the database.
+const DB_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComponentStatus {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "pending migrations: [2]")]
+    pub detail: Option<String>,
+}
+
+impl ComponentStatus {
+    fn up() -> Self {
+        Self {
+            status: Status::Up,
+            detail: None,
+        }
+    }
+
+    fn down(detail: String) -> Self {
+        Self {
+            status: Status::Down,
+            detail: Some(detail),
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        matches!(self.status, Status::Up)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthReport {
+    pub status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<&'static str, ComponentStatus>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Service is alive", body = HealthReport),
+    )
+)]
+pub async fn healthz() -> Response {
+    let report: HealthReport = HealthReport {
+        status: Status::Up,
+        components: BTreeMap::new(),
+    };
+    Json(report).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Service is ready for traffic", body = HealthReport),
+        (status = 503, description = "A dependency is down", body = HealthReport),
+    )
+)]
+pub async fn readyz(State(appstate): HandlerAppState) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let mut components: BTreeMap<&'static str, ComponentStatus> = BTreeMap::new();
+
+    let database: ComponentStatus = match store.ping(DB_PING_TIMEOUT).await {
+        Ok(()) => ComponentStatus::up(),
+        Err(e) => ComponentStatus::down(e.to_string()),
+    };
+    let migrations: ComponentStatus = if !database.is_up() {
+        ComponentStatus::down("database unavailable".to_string())
+    } else {
+        match store.pending_migrations().await {
+            Ok(pending) if pending.is_empty() => ComponentStatus::up(),
+            Ok(pending) => ComponentStatus::down(format!("pending migrations: {:?}", pending)),
+            Err(e) => ComponentStatus::down(e.to_string()),
+        }
+    };
+    components.insert("database", database);
+    components.insert("migrations", migrations);
+    // Startup refuses to run without keys, so a running server always has them.
+    components.insert("jwt_keys", ComponentStatus::up());
+
+    let ready: bool = components.values().all(ComponentStatus::is_up);
+    let report: HealthReport = HealthReport {
+        status: if ready { Status::Up } else { Status::Down },
+        components,
+    };
+    let status: StatusCode = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report)).into_response()
+}
@@ -0,0 +1,516 @@
+//! # Jobs
+//!
+//! Work that runs outside the request path, queued durably in the `jobs` table. A job has a
+//! `kind`, naming the [`JobHandler`] that runs it, and a JSON payload. [`enqueue`] adds one,
+//! optionally to run later.
+//!
+//! `--job-workers` workers per instance claim due jobs with `FOR UPDATE SKIP LOCKED`, so
+//! instances share the queue without running a job twice. A claimed job is leased for
+//! `--job-timeout-secs`; one whose worker died is claimed again once the lease runs out,
+//! unless that was its last attempt, in which case it is marked `failed`. A failed run is
+//! retried with exponential backoff until `max_attempts`, after which the job is left `failed`
+//! for an administrator to look at through `GET /api/v1/jobs`.
+//!
+//! Recurring jobs, registered with [`Jobs::every`], run again that long after each run ends,
+//! whether it succeeded or not. A partial unique index keeps one queued run of each.
+//!
+//! On shutdown the workers stop claiming and finish the job they are running.
+
+use crate::appstate::HandlerAppState;
+use crate::auth::Admin;
+use crate::error::{Problem, StoreErr};
+use crate::shutdown::Shutdown;
+use crate::*;
+
+use axum::extract::Query;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+/// Attempts a job gets unless enqueued with another limit.
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// Longest wait before retrying a job.
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Most jobs a listing returns.
+const MAX_LIST_LIMIT: i64 = 200;
+
+/// Kind of the job deleting old finished jobs.
+pub const PURGE_JOBS: &str = "jobs.purge_finished";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct JobArgs {
+    /// Jobs run at the same time by this instance.
+    #[clap(long, env = "JOB_WORKERS", default_value_t = 2)]
+    pub job_workers: usize,
+    /// Seconds an idle worker waits before looking for due jobs again.
+    #[clap(long, env = "JOB_POLL_SECS", default_value_t = 5)]
+    pub job_poll_secs: u64,
+    /// Seconds a job may run before it is abandoned and retried.
+    #[clap(long, env = "JOB_TIMEOUT_SECS", default_value_t = 300)]
+    pub job_timeout_secs: u64,
+    /// Delay before the first retry of a failed job; each further retry waits twice as long.
+    #[clap(long, env = "JOB_RETRY_BASE_SECS", default_value_t = 30)]
+    pub job_retry_base_secs: u64,
+    /// Days finished jobs are kept before being purged.
+    #[clap(long, env = "JOB_RETENTION_DAYS", default_value_t = 7)]
+    pub job_retention_days: i32,
+}
+
+/// Runs the jobs of one kind.
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    /// Run one job. An error is recorded on the job and the job retried.
+    async fn run(&self, payload: serde_json::Value) -> Result<(), String>;
+}
+
+/// Wait before retrying after `attempts` failed attempts, doubling from `base` up to a cap,
+/// with up to a tenth added so retries spread out.
+pub fn backoff(base: Duration, attempts: i32) -> Duration {
+    let exponent: u32 = attempts.saturating_sub(1).clamp(0, 30) as u32;
+    let delay: Duration = base
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(MAX_BACKOFF);
+    delay + delay.mul_f64(rand::random::<f64>() / 10.0)
+}
+
+/// Queue a job of `kind` to run at `run_at`, or as soon as possible, and return its id.
+pub async fn enqueue<'e, E>(
+    executor: E,
+    kind: &str,
+    payload: &serde_json::Value,
+    run_at: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let (id,): (i64,) = sqlx::query_as(
+        r#"INSERT INTO jobs (kind, payload, run_at, max_attempts)
+        VALUES ($1, $2, COALESCE($3, NOW()), $4)
+        RETURNING id;"#,
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(run_at)
+    .bind(DEFAULT_MAX_ATTEMPTS)
+    .fetch_one(executor)
+    .await?;
+    Ok(id)
+}
+
+/// The handlers and recurring schedule the workers run with.
+#[derive(Default)]
+pub struct Jobs {
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+    recurring: Vec<(&'static str, Duration)>,
+}
+
+impl Jobs {
+    /// Run jobs of `kind` with `handler`.
+    pub fn handle(mut self, kind: &'static str, handler: impl JobHandler + 'static) -> Self {
+        self.handlers.insert(kind, Arc::new(handler));
+        self
+    }
+
+    /// Run `handler` every `interval` as well as for queued jobs of `kind`.
+    pub fn every(
+        self,
+        kind: &'static str,
+        interval: Duration,
+        handler: impl JobHandler + 'static,
+    ) -> Self {
+        let mut jobs: Jobs = self.handle(kind, handler);
+        jobs.recurring.push((kind, interval));
+        jobs
+    }
+
+    /// Schedule the recurring jobs not already queued and spawn the workers, which run until
+    /// `shutdown`.
+    pub async fn start(
+        self,
+        args: &JobArgs,
+        pool: PgPool,
+        shutdown: &Shutdown,
+    ) -> Result<(), sqlx::Error> {
+        for (kind, interval) in &self.recurring {
+            sqlx::query(
+                r#"INSERT INTO jobs (kind, every_secs, max_attempts)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (kind) WHERE every_secs IS NOT NULL AND status IN ('pending', 'running')
+            DO NOTHING;"#,
+            )
+            .bind(kind)
+            .bind(i32::try_from(interval.as_secs()).unwrap_or(i32::MAX))
+            .bind(DEFAULT_MAX_ATTEMPTS)
+            .execute(&pool)
+            .await?;
+        }
+        let handlers: Arc<HashMap<&'static str, Arc<dyn JobHandler>>> = Arc::new(self.handlers);
+        for worker in 0..args.job_workers {
+            shutdown.spawn(work(
+                worker,
+                args.clone(),
+                pool.clone(),
+                handlers.clone(),
+                shutdown.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A claimed job.
+#[derive(Debug, sqlx::FromRow)]
+struct Claimed {
+    id: i64,
+    kind: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    max_attempts: i32,
+    every_secs: Option<i32>,
+}
+
+/// Claim the next due job for `timeout`: a pending one, or one whose worker died with
+/// attempts left.
+async fn claim(pool: &PgPool, timeout: Duration) -> Result<Option<Claimed>, sqlx::Error> {
+    sqlx::query_as(
+        r#"UPDATE jobs
+        SET status = 'running', attempts = attempts + 1,
+            locked_until = NOW() + make_interval(secs => $1)
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE run_at <= NOW()
+                AND (status = 'pending'
+                    OR (status = 'running' AND locked_until < NOW()
+                        AND attempts < max_attempts))
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED)
+        RETURNING id, kind, payload, attempts, max_attempts, every_secs;"#,
+    )
+    .bind(timeout.as_secs_f64())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Mark failed the jobs whose worker died during their last attempt, which nothing would
+/// claim again, scheduling the next run of recurring ones as [`finish`] does.
+async fn fail_abandoned(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"WITH failed AS (
+            UPDATE jobs
+            SET status = 'failed', locked_until = NULL, finished_on = NOW(),
+                last_error = 'abandoned by its worker on the last attempt'
+            WHERE status = 'running' AND locked_until < NOW() AND attempts >= max_attempts
+            RETURNING kind, payload, every_secs, max_attempts)
+        INSERT INTO jobs (kind, payload, every_secs, max_attempts, run_at)
+        SELECT kind, payload, every_secs, max_attempts, NOW() + make_interval(secs => every_secs)
+        FROM failed
+        WHERE every_secs IS NOT NULL
+        ON CONFLICT (kind) WHERE every_secs IS NOT NULL AND status IN ('pending', 'running')
+        DO NOTHING;"#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn work(
+    worker: usize,
+    args: JobArgs,
+    pool: PgPool,
+    handlers: Arc<HashMap<&'static str, Arc<dyn JobHandler>>>,
+    shutdown: Shutdown,
+) {
+    let timeout: Duration = Duration::from_secs(args.job_timeout_secs);
+    let poll: Duration = Duration::from_secs(args.job_poll_secs);
+    while !shutdown.is_cancelled() {
+        match claim(&pool, timeout).await {
+            Ok(Some(job)) => {
+                run(&args, &pool, &handlers, job, timeout).await;
+                continue;
+            }
+            // Tidied while idle; claiming skips them anyway.
+            Ok(None) => {
+                if let Err(e) = fail_abandoned(&pool).await {
+                    tracing::error!("jobs: worker {}: failing abandoned jobs: {}", worker, e);
+                }
+            }
+            Err(e) => tracing::error!("jobs: worker {}: claiming: {}", worker, e),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(poll) => (),
+        }
+    }
+}
+
+#[tracing::instrument(name = "job.run", skip_all, fields(job.id = job.id, job.kind = %job.kind))]
+async fn run(
+    args: &JobArgs,
+    pool: &PgPool,
+    handlers: &HashMap<&'static str, Arc<dyn JobHandler>>,
+    job: Claimed,
+    timeout: Duration,
+) {
+    let outcome: Result<(), String> = match handlers.get(job.kind.as_str()) {
+        Some(handler) => {
+            match tokio::time::timeout(timeout, handler.run(job.payload.clone())).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(format!("timed out after {}s", timeout.as_secs())),
+            }
+        }
+        None => Err(format!("no handler for job kind {:?}", job.kind)),
+    };
+    if let Err(e) = &outcome {
+        tracing::warn!(
+            "job {} ({}) attempt {}: {}",
+            job.id,
+            job.kind,
+            job.attempts,
+            e
+        );
+    }
+    if let Err(e) = finish(args, pool, &job, outcome).await {
+        tracing::error!("jobs: recording job {}: {}", job.id, e);
+    }
+}
+
+async fn finish(
+    args: &JobArgs,
+    pool: &PgPool,
+    job: &Claimed,
+    outcome: Result<(), String>,
+) -> Result<(), sqlx::Error> {
+    let mut tx: sqlx::Transaction<'_, Postgres> = pool.begin().await?;
+    let retry: bool = outcome.is_err() && job.attempts < job.max_attempts;
+    if retry {
+        let delay: Duration = backoff(Duration::from_secs(args.job_retry_base_secs), job.attempts);
+        sqlx::query(
+            r#"UPDATE jobs
+        SET status = 'pending', locked_until = NULL, last_error = $2,
+            run_at = NOW() + make_interval(secs => $3)
+        WHERE id = $1;"#,
+        )
+        .bind(job.id)
+        .bind(outcome.err())
+        .bind(delay.as_secs_f64())
+        .execute(&mut *tx)
+        .await?;
+        return tx.commit().await;
+    }
+
+    let status: &str = if outcome.is_ok() { "done" } else { "failed" };
+    sqlx::query(
+        r#"UPDATE jobs
+        SET status = $2, locked_until = NULL, last_error = $3, finished_on = NOW()
+        WHERE id = $1;"#,
+    )
+    .bind(job.id)
+    .bind(status)
+    .bind(outcome.err())
+    .execute(&mut *tx)
+    .await?;
+    if let Some(every_secs) = job.every_secs {
+        sqlx::query(
+            r#"INSERT INTO jobs (kind, payload, every_secs, max_attempts, run_at)
+        VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $3))
+        ON CONFLICT (kind) WHERE every_secs IS NOT NULL AND status IN ('pending', 'running')
+        DO NOTHING;"#,
+        )
+        .bind(&job.kind)
+        .bind(&job.payload)
+        .bind(every_secs)
+        .bind(job.max_attempts)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Deletes finished jobs older than `--job-retention-days`.
+pub struct PurgeJobs {
+    pub pool: PgPool,
+    pub retention_days: i32,
+}
+
+#[async_trait]
+impl JobHandler for PurgeJobs {
+    async fn run(&self, _payload: serde_json::Value) -> Result<(), String> {
+        let purged: u64 = sqlx::query(
+            r#"DELETE FROM jobs
+        WHERE status IN ('done', 'failed')
+            AND finished_on < NOW() - make_interval(days => $1);"#,
+        )
+        .bind(self.retention_days)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+        tracing::debug!("purged {} finished jobs", purged);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Job {
+    #[schema(example = 7)]
+    pub id: i64,
+    #[schema(example = "sessions.delete_expired")]
+    pub kind: String,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    /// `pending`, `running`, `done` or `failed`.
+    #[schema(example = "failed")]
+    pub status: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// When the job is due, or was last due.
+    pub run_at: DateTime<Utc>,
+    /// Seconds between runs, for recurring jobs.
+    pub every_secs: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_on: DateTime<Utc>,
+    pub finished_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct JobFilter {
+    /// Only jobs with this status; pending and failed jobs without it.
+    pub status: Option<String>,
+    /// Only jobs of this kind.
+    pub kind: Option<String>,
+    /// Most jobs to return, newest first.
+    pub limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/jobs",
+    tag = "jobs",
+    security(("bearer" = []), ("api_key" = [])),
+    params(JobFilter),
+    responses(
+        (status = 200, description = "Jobs, newest first", body = [Job]),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn list_jobs(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Query(filter): Query<JobFilter>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let statuses: Vec<String> = match filter.status {
+        Some(status) => vec![status],
+        None => vec!["pending".to_string(), "failed".to_string()],
+    };
+    let limit: i64 = filter.limit.unwrap_or(50).clamp(1, MAX_LIST_LIMIT);
+    let jobs: Result<Vec<Job>, sqlx::Error> = sqlx::query_as(
+        r#"SELECT id, kind, payload, status, attempts, max_attempts, run_at, every_secs,
+            last_error, created_on, finished_on
+        FROM jobs
+        WHERE status = ANY($1) AND ($2::TEXT IS NULL OR kind = $2)
+        ORDER BY id DESC
+        LIMIT $3;"#,
+    )
+    .bind(&statuses)
+    .bind(&filter.kind)
+    .bind(limit)
+    .fetch_all(&pool)
+    .await;
+    match jobs {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let base: Duration = Duration::from_secs(30);
+        let cases: [(i32, Duration); 8] = [
+            (i32::MIN, base),
+            (0, base),
+            (1, base),
+            (2, base * 2),
+            (3, base * 4),
+            (10, base * 512),
+            (11, MAX_BACKOFF),
+            (i32::MAX, MAX_BACKOFF),
+        ];
+        for (attempts, delay) in cases {
+            // Jitter is random, so try a few times.
+            for _ in 0..20 {
+                let wait: Duration = super::backoff(base, attempts);
+                assert!(
+                    delay <= wait && wait <= delay.mul_f64(1.1),
+                    "{} attempts: {:?}, expected {:?}",
+                    attempts,
+                    wait,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn jobs_abandoned_on_their_last_attempt_fail(pool: PgPool) {
+        // Kind, attempts made, and whether it recurs.
+        let jobs: [(&str, i32, bool); 3] = [
+            ("retried", DEFAULT_MAX_ATTEMPTS - 1, false),
+            ("exhausted", DEFAULT_MAX_ATTEMPTS, false),
+            ("recurring", DEFAULT_MAX_ATTEMPTS, true),
+        ];
+        for (kind, attempts, recurs) in jobs {
+            sqlx::query(
+                r#"INSERT INTO jobs (kind, status, attempts, max_attempts, every_secs, run_at,
+                    locked_until)
+                VALUES ($1, 'running', $2, $3, $4, NOW() - INTERVAL '1 hour',
+                    NOW() - INTERVAL '1 minute');"#,
+            )
+            .bind(kind)
+            .bind(attempts)
+            .bind(DEFAULT_MAX_ATTEMPTS)
+            .bind(recurs.then_some(60))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let timeout: Duration = Duration::from_secs(60);
+        let claimed: Claimed = claim(&pool, timeout).await.unwrap().unwrap();
+        assert_eq!(
+            (claimed.kind.as_str(), claimed.attempts),
+            ("retried", DEFAULT_MAX_ATTEMPTS)
+        );
+        assert!(claim(&pool, timeout).await.unwrap().is_none());
+
+        fail_abandoned(&pool).await.unwrap();
+        let statuses: Vec<(String, String)> =
+            sqlx::query_as(r#"SELECT kind, status FROM jobs ORDER BY id;"#)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let statuses: Vec<(&str, &str)> = statuses
+            .iter()
+            .map(|(kind, status)| (kind.as_str(), status.as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("retried", "running"),
+                ("exhausted", "failed"),
+                ("recurring", "failed"),
+                ("recurring", "pending"),
+            ]
+        );
+    }
+}
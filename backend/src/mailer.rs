@@ -0,0 +1,193 @@
+//! # Mail
+//!
+//! Outgoing email goes through the [`Mailer`] trait, chosen by [`mailer`] from the arguments:
+//!
+//! * `--mail-dir` writes each message into a Maildir (`tmp/`, `new/`, `cur/`) for local
+//!   development and tests; any mail client or `cat` can read it.
+//! * `--smtp-host` sends through an SMTP relay. The password, if any, is read from the file
+//!   named by `SMTP_PASSWORDFILE`.
+//!
+//! Without either, no mail is sent and features needing it stay off.
+
+use crate::auth::read_secret;
+use crate::*;
+
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MessageBuilder, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Upgrade with STARTTLS, usually on port 587.
+    Starttls,
+    /// No encryption; only for relays on the same host.
+    Plain,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MailArgs {
+    /// Sender of outgoing mail.
+    #[clap(
+        long,
+        env = "MAIL_FROM",
+        default_value = "Questions <noreply@question.po8.org>"
+    )]
+    pub mail_from: String,
+    /// Write outgoing mail to this Maildir instead of sending it.
+    #[clap(long, env = "MAIL_DIR")]
+    pub mail_dir: Option<PathBuf>,
+    /// SMTP relay to send mail through.
+    #[clap(long, env = "SMTP_HOST")]
+    pub smtp_host: Option<String>,
+    /// Port of the relay; the default one for `--smtp-security` without it.
+    #[clap(long, env = "SMTP_PORT")]
+    pub smtp_port: Option<u16>,
+    #[clap(long, env = "SMTP_SECURITY", value_enum, default_value_t = SmtpSecurity::Starttls)]
+    pub smtp_security: SmtpSecurity,
+    /// User to log in to the relay as.
+    #[clap(long, env = "SMTP_USERNAME")]
+    pub smtp_username: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("bad address: {0}")]
+    Address(String),
+    #[error("building message: {0}")]
+    Build(String),
+    #[error("smtp: {0}")]
+    Smtp(String),
+    #[error("maildir: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A message with plain text and HTML versions of the same body.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: Mailbox,
+    pub subject: String,
+    /// Link turning these mails off, sent as `List-Unsubscribe` along with
+    /// `List-Unsubscribe-Post` so mail clients can use it in one click (RFC 8058).
+    pub unsubscribe_url: Option<String>,
+    pub text: String,
+    pub html: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+fn message(from: &Mailbox, email: &Email) -> Result<Message, MailError> {
+    let mut builder: MessageBuilder = Message::builder()
+        .from(from.clone())
+        .to(email.to.clone())
+        .subject(&email.subject);
+    if let Some(url) = &email.unsubscribe_url {
+        builder = builder
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{}>", url),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_string(),
+            ));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text.clone(),
+            email.html.clone(),
+        ))
+        .map_err(|e| MailError::Build(e.to_string()))
+}
+
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message: Message = message(&self.from, email)?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| MailError::Smtp(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Delivers into a Maildir: written to `tmp/`, then moved to `new/` once complete.
+pub struct MaildirMailer {
+    from: Mailbox,
+    dir: PathBuf,
+}
+
+impl MaildirMailer {
+    pub async fn new(from: Mailbox, dir: &Path) -> Result<Self, MailError> {
+        for sub in ["tmp", "new", "cur"] {
+            tokio::fs::create_dir_all(dir.join(sub)).await?;
+        }
+        Ok(Self {
+            from,
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for MaildirMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message: Message = message(&self.from, email)?;
+        let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+        let name: String = format!(
+            "{}.M{}R{:016x}.question",
+            now.timestamp(),
+            now.timestamp_subsec_micros(),
+            rand::random::<u64>()
+        );
+        let tmp: PathBuf = self.dir.join("tmp").join(&name);
+        tokio::fs::write(&tmp, message.formatted()).await?;
+        tokio::fs::rename(&tmp, self.dir.join("new").join(&name)).await?;
+        Ok(())
+    }
+}
+
+/// The mailer `args` configure, if any.
+pub async fn mailer(args: &MailArgs) -> Result<Option<Arc<dyn Mailer>>, MailError> {
+    let from: Mailbox = args
+        .mail_from
+        .parse()
+        .map_err(|_| MailError::Address(args.mail_from.clone()))?;
+    if let Some(dir) = &args.mail_dir {
+        return Ok(Some(Arc::new(MaildirMailer::new(from, dir).await?)));
+    }
+    let Some(host) = &args.smtp_host else {
+        return Ok(None);
+    };
+    let smtp = |e: lettre::transport::smtp::Error| MailError::Smtp(e.to_string());
+    let mut builder = match args.smtp_security {
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(smtp)?,
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host).map_err(smtp)?
+        }
+        SmtpSecurity::Plain => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+    };
+    if let Some(port) = args.smtp_port {
+        builder = builder.port(port);
+    }
+    if let Some(username) = &args.smtp_username {
+        let password: String = read_secret("SMTP_PASSWORDFILE").await.unwrap_or_default();
+        builder = builder.credentials(Credentials::new(username.clone(), password));
+    }
+    Ok(Some(Arc::new(SmtpMailer {
+        from,
+        transport: builder.build(),
+    })))
+}
@@ -0,0 +1,198 @@
+//! # Metrics
+//!
+//! Prometheus metrics served in the text exposition format at `GET /metrics`, either on the
+//! main listener or, with `--metrics-addr`, on a separate admin listener.
+//!
+//! * `http_requests_total` and `http_request_duration_seconds` by matched route and status
+//! * `db_pool_connections` by state (`idle`, `in_use`) and `db_pool_max_connections`
+//! * `store_operation_duration_seconds` by `Store` method
+//! * `auth_failures_total` by `AuthError` variant
+//! * `process_*` CPU, memory and file descriptor statistics (Linux)
+
+use crate::appstate::HandlerAppState;
+use crate::auth::AuthError;
+use crate::*;
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MetricsArgs {
+    /// Serve `/metrics` on this separate admin address instead of the main listener.
+    #[clap(long, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<String>,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max: IntGauge,
+    store_duration: HistogramVec,
+    auth_failures: IntCounterVec,
+}
+
+/// Process wide metrics; the store and error paths record into it directly.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry: Registry = Registry::new();
+        let http_requests: IntCounterVec = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration: HistogramVec = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_pool_connections: IntGaugeVec = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let db_pool_max: IntGauge = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum size of the database pool",
+        )
+        .unwrap();
+        let store_duration: HistogramVec = HistogramVec::new(
+            HistogramOpts::new(
+                "store_operation_duration_seconds",
+                "Duration of Store operations in seconds",
+            ),
+            &["operation"],
+        )
+        .unwrap();
+        let auth_failures: IntCounterVec = IntCounterVec::new(
+            Opts::new("auth_failures_total", "Authentication failures by reason"),
+            &["reason"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(db_pool_max.clone())).unwrap();
+        registry.register(Box::new(store_duration.clone())).unwrap();
+        registry.register(Box::new(auth_failures.clone())).unwrap();
+        #[cfg(target_os = "linux")]
+        registry
+            .register(Box::new(
+                prometheus::process_collector::ProcessCollector::for_self(),
+            ))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            db_pool_connections,
+            db_pool_max,
+            store_duration,
+            auth_failures,
+        }
+    }
+
+    /// Start timing a `Store` operation; the duration is recorded when the timer drops.
+    pub fn store_timer(&self, operation: &str) -> HistogramTimer {
+        self.store_duration
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+
+    /// How many `Store` operations named `operation` have been timed so far.
+    #[cfg(test)]
+    pub fn store_operations(&self, operation: &str) -> u64 {
+        self.store_duration
+            .with_label_values(&[operation])
+            .get_sample_count()
+    }
+
+    pub fn auth_failure(&self, error: &AuthError) {
+        self.auth_failures
+            .with_label_values(&[error.reason()])
+            .inc();
+    }
+
+    /// Sample the database pool and render every metric in the text exposition format.
+    fn render(&self, store: &Store) -> Result<String, prometheus::Error> {
+        let size: i64 = i64::from(store.connection.size());
+        let idle: i64 = i64::try_from(store.connection.num_idle()).unwrap_or(i64::MAX);
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+        self.db_pool_max
+            .set(i64::from(store.connection.options().get_max_connections()));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Middleware counting and timing requests by matched route. Requests that matched no route
+/// share one label so unknown paths can't blow up the number of series.
+pub async fn track_metrics(request: Request, next: Next) -> Response {
+    let route: String = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method: String = request.method().to_string();
+    let start: Instant = Instant::now();
+
+    let response: Response = next.run(request).await;
+
+    let status: String = response.status().as_u16().to_string();
+    let labels: [&str; 3] = [&method, &route, &status];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS
+        .http_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String,
+            content_type = "text/plain; version=0.0.4"),
+    )
+)]
+pub async fn metrics(State(appstate): HandlerAppState) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    match METRICS.render(&store) {
+        Ok(text) => (
+            [(
+                http::header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            )],
+            text,
+        )
+            .into_response(),
+        Err(e) => error::Problem::from_status(StatusCode::INTERNAL_SERVER_ERROR)
+            .with_detail(e.to_string())
+            .into_response(),
+    }
+}
@@ -0,0 +1,807 @@
+//! # Notifications
+//!
+//! Emails the people watching a question when it gets an answer. Whoever asks a question watches
+//! it, and anyone else can with `PUT /api/v1/question/{id}/watch`.
+//!
+//! Each new answer becomes a row in `notifications` per watcher who hasn't turned mail off,
+//! written by [`queue`] in the transaction adding the answer.
+//! Rows for watchers who want mail right away are sent by a [`SEND_NOTIFICATION`] job each; the
+//! others wait for the [`SEND_DIGESTS`] job, which runs every `--notify-digest-minutes` and
+//! sends each of them one mail covering everything since the last. Rows are deleted once mailed,
+//! so a failure is retried by the job queue without mailing anyone twice for the same answer,
+//! unless it fails between sending and deleting.
+//!
+//! Users choose between the two, or turn mail off, with `/api/v1/notifications/preferences`.
+//! Every mail ends with a link to `/api/v1/notifications/unsubscribe` that turns it off
+//! without logging in, signed so it only works for the address it was sent to. Opening the link
+//! only asks to confirm, as mail scanners open links too; the confirmation, or a mail client's
+//! one-click `List-Unsubscribe-Post` (RFC 8058), posts to it.
+//!
+//! All of this needs a [`Mailer`](crate::mailer::Mailer); without one, watches and preferences
+//! are still kept but nothing is queued.
+
+use crate::appstate::HandlerAppState;
+use crate::auth::{subject_email, subject_name, Claims, JwtKeys};
+use crate::error::{Problem, StoreErr};
+use crate::jobs::{enqueue, JobHandler};
+use crate::mailer::{Email, Mailer};
+use crate::types::answer::Answer;
+use crate::web::STYLESHEET;
+use crate::*;
+
+use askama::Template;
+use axum::extract::Query;
+use lettre::message::Mailbox;
+use lettre::Address;
+use utoipa::{IntoParams, ToSchema};
+
+/// Kind of the job mailing one notification.
+pub const SEND_NOTIFICATION: &str = "notifications.send";
+
+/// Kind of the recurring job mailing the digests.
+pub const SEND_DIGESTS: &str = "notifications.send_digests";
+
+/// Purpose the unsubscribe tokens are signed for.
+const UNSUBSCRIBE: &str = "unsubscribe";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct NotifyArgs {
+    /// Public address of the site, for the links in notification mails.
+    #[clap(long, env = "SITE_URL", default_value = "http://localhost:3000")]
+    pub site_url: String,
+    /// Minutes between digest mails.
+    #[clap(long, env = "NOTIFY_DIGEST_MINUTES", default_value_t = 60)]
+    pub notify_digest_minutes: u64,
+}
+
+/// What a user wants to be mailed. Users who never set them get the defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct Preferences {
+    /// Whether to mail about new answers at all.
+    pub email_enabled: bool,
+    /// Whether to collect new answers into periodic digests instead of one mail each.
+    pub digest: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            email_enabled: true,
+            digest: false,
+        }
+    }
+}
+
+/// Fields of the mail about one answer.
+struct AnswerMail {
+    name: String,
+    question_title: String,
+    question_url: String,
+    answer: String,
+    unsubscribe_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "email/answer.txt")]
+struct AnswerText<'a> {
+    mail: &'a AnswerMail,
+}
+
+#[derive(Template)]
+#[template(path = "email/answer.html")]
+struct AnswerHtml<'a> {
+    mail: &'a AnswerMail,
+}
+
+struct DigestItem {
+    question_title: String,
+    question_url: String,
+    answer: String,
+}
+
+/// Fields of a digest mail.
+struct DigestMail {
+    name: String,
+    items: Vec<DigestItem>,
+    unsubscribe_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "email/digest.txt")]
+struct DigestText<'a> {
+    mail: &'a DigestMail,
+}
+
+#[derive(Template)]
+#[template(path = "email/digest.html")]
+struct DigestHtml<'a> {
+    mail: &'a DigestMail,
+}
+
+/// A queued notification.
+#[derive(Debug, sqlx::FromRow)]
+struct Pending {
+    id: i64,
+    email: String,
+    full_name: String,
+    question_id: String,
+    answer_content: String,
+}
+
+/// Watch `question_id` as the user `sub`. `author` marks the user who asked it.
+pub async fn watch<'e, E>(
+    executor: E,
+    question_id: &str,
+    sub: &str,
+    author: bool,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    sqlx::query(
+        r#"INSERT INTO question_watchers (question_id, email, full_name, author)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (question_id, email)
+        DO UPDATE SET full_name = EXCLUDED.full_name,
+            author = question_watchers.author OR EXCLUDED.author;"#,
+    )
+    .bind(question_id)
+    .bind(subject_email(sub).to_lowercase())
+    .bind(subject_name(sub))
+    .bind(author)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Queue a notification of `answer` for each watcher of its question who hasn't turned mail
+/// off, and a job mailing it for those who don't want a digest, in the transaction adding it.
+pub async fn queue(tx: &mut PgConnection, answer: &Answer) -> Result<(), sqlx::Error> {
+    let queued: Vec<(i64, bool)> = sqlx::query_as(
+        r#"INSERT INTO notifications
+            (email, full_name, question_id, answer_id, answer_content, digest)
+        SELECT w.email, w.full_name, w.question_id, $2, $3, COALESCE(p.digest, FALSE)
+        FROM question_watchers w
+        LEFT JOIN notification_preferences p ON p.email = w.email
+        WHERE w.question_id = $1 AND COALESCE(p.email_enabled, TRUE)
+        RETURNING id, digest;"#,
+    )
+    .bind(&answer.question_id.0)
+    .bind(&answer.id.0)
+    .bind(&answer.content)
+    .fetch_all(&mut *tx)
+    .await?;
+    for (id, _) in queued.iter().filter(|(_, digest)| !digest) {
+        let payload: serde_json::Value = serde_json::json!({ "notification_id": id });
+        enqueue(&mut *tx, SEND_NOTIFICATION, &payload, None).await?;
+    }
+    Ok(())
+}
+
+/// Renders and sends notification mails; cloned into the jobs that do.
+#[derive(Clone)]
+pub struct Notifier {
+    pub store: Store,
+    pub mailer: Arc<dyn Mailer>,
+    pub jwt_keys: JwtKeys,
+    pub site_url: String,
+}
+
+impl Notifier {
+    fn question_url(&self, question_id: &str) -> String {
+        let query: String = serde_urlencoded::to_string([("id", question_id)]).unwrap_or_default();
+        format!("{}/site/?{}", self.site_url.trim_end_matches('/'), query)
+    }
+
+    fn unsubscribe_url(&self, email: &str) -> String {
+        let token: String = self.jwt_keys.sign(UNSUBSCRIBE, email);
+        let query: String =
+            serde_urlencoded::to_string([("email", email), ("token", token.as_str())])
+                .unwrap_or_default();
+        format!(
+            "{}/api/v1/notifications/unsubscribe?{}",
+            self.site_url.trim_end_matches('/'),
+            query
+        )
+    }
+
+    /// Title of the question, or a stand-in if it is gone.
+    async fn question_title(&self, question_id: &str) -> String {
+        match self.store.get(question_id).await {
+            Ok(question) => question.title,
+            Err(_) => format!("question {}", question_id),
+        }
+    }
+
+    fn recipient(pending: &Pending) -> Result<Mailbox, String> {
+        let address: Address = pending
+            .email
+            .parse()
+            .map_err(|e| format!("{}: {}", pending.email, e))?;
+        Ok(Mailbox::new(Some(pending.full_name.clone()), address))
+    }
+
+    async fn send_one(&self, pending: &Pending) -> Result<(), String> {
+        let mail: AnswerMail = AnswerMail {
+            name: pending.full_name.clone(),
+            question_title: self.question_title(&pending.question_id).await,
+            question_url: self.question_url(&pending.question_id),
+            answer: pending.answer_content.clone(),
+            unsubscribe_url: self.unsubscribe_url(&pending.email),
+        };
+        let email: Email = Email {
+            to: Self::recipient(pending)?,
+            subject: format!("New answer to \"{}\"", mail.question_title),
+            unsubscribe_url: Some(mail.unsubscribe_url.clone()),
+            text: AnswerText { mail: &mail }
+                .render()
+                .map_err(|e| e.to_string())?,
+            html: AnswerHtml { mail: &mail }
+                .render()
+                .map_err(|e| e.to_string())?,
+        };
+        self.mailer.send(&email).await.map_err(|e| e.to_string())
+    }
+
+    /// Send `email` everything waiting for its digest, in one transaction so concurrent runs
+    /// don't both send it.
+    async fn send_digest(&self, email: &str) -> Result<(), String> {
+        let pool: &PgPool = &self.store.connection;
+        let mut tx: sqlx::Transaction<'_, Postgres> =
+            pool.begin().await.map_err(|e| e.to_string())?;
+        let pending: Vec<Pending> = sqlx::query_as(
+            r#"SELECT id, email, full_name, question_id, answer_content FROM notifications
+        WHERE email = $1 AND digest
+        ORDER BY id
+        FOR UPDATE SKIP LOCKED;"#,
+        )
+        .bind(email)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        let Some(last) = pending.last() else {
+            return Ok(());
+        };
+        let mut items: Vec<DigestItem> = Vec::with_capacity(pending.len());
+        for pending in &pending {
+            items.push(DigestItem {
+                question_title: self.question_title(&pending.question_id).await,
+                question_url: self.question_url(&pending.question_id),
+                answer: pending.answer_content.clone(),
+            });
+        }
+        let mail: DigestMail = DigestMail {
+            name: last.full_name.clone(),
+            items,
+            unsubscribe_url: self.unsubscribe_url(email),
+        };
+        let message: Email = Email {
+            to: Self::recipient(last)?,
+            subject: format!("{} new answers", mail.items.len()),
+            unsubscribe_url: Some(mail.unsubscribe_url.clone()),
+            text: DigestText { mail: &mail }
+                .render()
+                .map_err(|e| e.to_string())?,
+            html: DigestHtml { mail: &mail }
+                .render()
+                .map_err(|e| e.to_string())?,
+        };
+        self.mailer
+            .send(&message)
+            .await
+            .map_err(|e| e.to_string())?;
+        let ids: Vec<i64> = pending.iter().map(|pending| pending.id).collect();
+        sqlx::query(r#"DELETE FROM notifications WHERE id = ANY($1);"#)
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+}
+
+/// Mails the notification named by the job's `notification_id`.
+pub struct SendNotification(pub Notifier);
+
+#[async_trait]
+impl JobHandler for SendNotification {
+    async fn run(&self, payload: serde_json::Value) -> Result<(), String> {
+        let id: i64 = payload["notification_id"]
+            .as_i64()
+            .ok_or("payload has no notification_id")?;
+        let pool: &PgPool = &self.0.store.connection;
+        // Users who turned mail off after the answer was queued don't get it.
+        let pending: Option<Pending> = sqlx::query_as(
+            r#"SELECT n.id, n.email, n.full_name, n.question_id, n.answer_content
+        FROM notifications n
+        LEFT JOIN notification_preferences p ON p.email = n.email
+        WHERE n.id = $1 AND COALESCE(p.email_enabled, TRUE);"#,
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        if let Some(pending) = &pending {
+            self.0.send_one(pending).await?;
+        }
+        sqlx::query(r#"DELETE FROM notifications WHERE id = $1;"#)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// Mails every user with answers waiting for their digest.
+pub struct SendDigests(pub Notifier);
+
+#[async_trait]
+impl JobHandler for SendDigests {
+    async fn run(&self, _payload: serde_json::Value) -> Result<(), String> {
+        let pool: &PgPool = &self.0.store.connection;
+        // Drop what users who turned mail off were waiting for.
+        sqlx::query(
+            r#"DELETE FROM notifications n
+        USING notification_preferences p
+        WHERE p.email = n.email AND NOT p.email_enabled;"#,
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        let emails: Vec<(String,)> =
+            sqlx::query_as(r#"SELECT DISTINCT email FROM notifications WHERE digest;"#)
+                .fetch_all(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+        let mut failed: usize = 0;
+        for (email,) in &emails {
+            if let Err(e) = self.0.send_digest(email).await {
+                tracing::warn!("notifications: digest for {}: {}", email, e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(format!("{} of {} digests failed", failed, emails.len()));
+        }
+        Ok(())
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/question/{id}/watch",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 204, description = "Watching the question"),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn watch_question(
+    claims: Claims,
+    State(appstate): HandlerAppState,
+    Path(question_id): Path<String>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    match watch(&pool, &question_id, &claims.sub, false).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/question/{id}/watch",
+    tag = "question",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 204, description = "No longer watching the question"),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn unwatch_question(
+    claims: Claims,
+    State(appstate): HandlerAppState,
+    Path(question_id): Path<String>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let unwatched =
+        sqlx::query(r#"DELETE FROM question_watchers WHERE question_id = $1 AND email = $2;"#)
+            .bind(&question_id)
+            .bind(subject_email(&claims.sub).to_lowercase())
+            .execute(&pool)
+            .await;
+    match unwatched {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/notifications/preferences",
+    tag = "notifications",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Notification preferences", body = Preferences),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_preferences(claims: Claims, State(appstate): HandlerAppState) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let preferences: Result<Option<Preferences>, sqlx::Error> = sqlx::query_as(
+        r#"SELECT email_enabled, digest FROM notification_preferences WHERE email = $1;"#,
+    )
+    .bind(subject_email(&claims.sub).to_lowercase())
+    .fetch_optional(&pool)
+    .await;
+    match preferences {
+        Ok(preferences) => Json(preferences.unwrap_or_default()).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/notifications/preferences",
+    tag = "notifications",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content = Preferences, description = "New notification preferences"),
+    responses(
+        (status = 200, description = "Updated notification preferences", body = Preferences),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn put_preferences(
+    claims: Claims,
+    State(appstate): HandlerAppState,
+    Json(preferences): Json<Preferences>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let updated = sqlx::query(
+        r#"INSERT INTO notification_preferences (email, email_enabled, digest)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (email)
+        DO UPDATE SET email_enabled = EXCLUDED.email_enabled, digest = EXCLUDED.digest;"#,
+    )
+    .bind(subject_email(&claims.sub).to_lowercase())
+    .bind(preferences.email_enabled)
+    .bind(preferences.digest)
+    .execute(&pool)
+    .await;
+    match updated {
+        Ok(_) => Json(preferences).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct Unsubscribe {
+    /// Address the mail was sent to.
+    pub email: String,
+    /// Signature of the address, from the mail's link.
+    pub token: String,
+}
+
+/// Asks to confirm turning mail off, as opening a link shouldn't do it.
+#[derive(Template)]
+#[template(path = "unsubscribe.html")]
+pub struct UnsubscribeTemplate {
+    stylesheet: &'static str,
+    error: Option<String>,
+    email: String,
+    /// Where the form posts to, with the link's parameters.
+    action: String,
+    done: bool,
+}
+
+impl UnsubscribeTemplate {
+    fn new(params: &Unsubscribe, done: bool) -> Self {
+        let query: String = serde_urlencoded::to_string([
+            ("email", params.email.as_str()),
+            ("token", params.token.as_str()),
+        ])
+        .unwrap_or_default();
+        Self {
+            stylesheet: STYLESHEET,
+            error: None,
+            email: params.email.clone(),
+            action: format!("/api/v1/notifications/unsubscribe?{}", query),
+            done,
+        }
+    }
+}
+
+fn invalid_link() -> Response {
+    Problem::new(
+        StatusCode::FORBIDDEN,
+        "forbidden",
+        "Invalid unsubscribe link",
+    )
+    .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/notifications/unsubscribe",
+    tag = "notifications",
+    params(Unsubscribe),
+    responses(
+        (status = 200, description = "Form confirming mail about new answers should stop",
+            body = String, content_type = "text/html"),
+        (status = 403, description = "Link not valid for this address", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn unsubscribe_form(
+    State(appstate): HandlerAppState,
+    Query(params): Query<Unsubscribe>,
+) -> Response {
+    let appstate = appstate.read().await;
+    if !appstate
+        .jwt_keys
+        .verify(UNSUBSCRIBE, &params.email, &params.token)
+    {
+        return invalid_link();
+    }
+    UnsubscribeTemplate::new(&params, false).into_response()
+}
+
+/// Turns mail off, from the confirmation form or a mail client's one-click `List-Unsubscribe`
+/// (RFC 8058), which posts `List-Unsubscribe=One-Click` to the link. Either way the address and
+/// token come in the query.
+#[utoipa::path(
+    post,
+    path = "/api/v1/notifications/unsubscribe",
+    tag = "notifications",
+    params(Unsubscribe),
+    responses(
+        (status = 200, description = "Mail about new answers turned off", body = String,
+            content_type = "text/html"),
+        (status = 403, description = "Link not valid for this address", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn unsubscribe(
+    State(appstate): HandlerAppState,
+    Query(params): Query<Unsubscribe>,
+) -> Response {
+    let appstate = appstate.read().await;
+    if !appstate
+        .jwt_keys
+        .verify(UNSUBSCRIBE, &params.email, &params.token)
+    {
+        return invalid_link();
+    }
+    let pool: PgPool = appstate.store.connection.clone();
+    drop(appstate);
+    let unsubscribed = sqlx::query(
+        r#"INSERT INTO notification_preferences (email, email_enabled)
+        VALUES ($1, FALSE)
+        ON CONFLICT (email) DO UPDATE SET email_enabled = FALSE;"#,
+    )
+    .bind(&params.email)
+    .execute(&pool)
+    .await;
+    match unsubscribed {
+        Ok(_) => UnsubscribeTemplate::new(&params, true).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mailer::MaildirMailer;
+    use crate::startup::test_router;
+    use crate::types::question::{Question, QuestionId};
+    use axum::body::Body;
+    use http::Request;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    const ANN: &str = "Ann <ann@example.org>";
+    const BOB: &str = "Bob <bob@example.org>";
+    const CAT: &str = "Cat <cat@example.org>";
+
+    /// A store queueing notifications, a notifier writing into a temporary Maildir and a
+    /// question asked by the first of `watchers`, watched by the others.
+    async fn setup(pool: PgPool, watchers: &[&str]) -> (Notifier, TempDir, QuestionId) {
+        let store: Store = Store {
+            connection: pool.clone(),
+            events: Arc::default(),
+            notify: true,
+        };
+        let dir: TempDir = TempDir::new().unwrap();
+        let from: Mailbox = "Questions <noreply@example.org>".parse().unwrap();
+        let notifier: Notifier = Notifier {
+            store: store.clone(),
+            mailer: Arc::new(MaildirMailer::new(from, dir.path()).await.unwrap()),
+            jwt_keys: JwtKeys::new(b"test"),
+            site_url: "https://question.example.org".to_string(),
+        };
+        let question: Question = store
+            .add_question(
+                Question {
+                    id: QuestionId(String::new()),
+                    title: "How?".to_string(),
+                    content: "Please help!".to_string(),
+                    tags: None,
+                },
+                watchers.first().copied(),
+            )
+            .await
+            .unwrap();
+        for sub in watchers.iter().skip(1) {
+            watch(&pool, &question.id.0, sub, false).await.unwrap();
+        }
+        (notifier, dir, question.id)
+    }
+
+    async fn set_preferences(pool: &PgPool, email: &str, email_enabled: bool, digest: bool) {
+        sqlx::query(
+            r#"INSERT INTO notification_preferences (email, email_enabled, digest)
+            VALUES ($1, $2, $3);"#,
+        )
+        .bind(email)
+        .bind(email_enabled)
+        .bind(digest)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    /// Run the queued `SEND_NOTIFICATION` jobs.
+    async fn send_queued(notifier: &Notifier) {
+        let payloads: Vec<(serde_json::Value,)> =
+            sqlx::query_as(r#"SELECT payload FROM jobs WHERE kind = $1 ORDER BY id;"#)
+                .bind(SEND_NOTIFICATION)
+                .fetch_all(&notifier.store.connection)
+                .await
+                .unwrap();
+        for (payload,) in payloads {
+            SendNotification(notifier.clone())
+                .run(payload)
+                .await
+                .unwrap();
+        }
+    }
+
+    /// The mails delivered into `dir`, sorted by recipient.
+    fn mails(dir: &TempDir) -> Vec<String> {
+        let mut mails: Vec<String> = std::fs::read_dir(dir.path().join("new"))
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        mails.sort_by_key(|mail| header(mail, "To").unwrap_or_default().to_string());
+        mails
+    }
+
+    fn header<'m>(mail: &'m str, name: &str) -> Option<&'m str> {
+        mail.lines()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+    }
+
+    #[sqlx::test]
+    async fn answers_are_mailed_to_watchers(pool: PgPool) {
+        let (notifier, dir, question_id) = setup(pool, &[ANN]).await;
+        notifier
+            .store
+            .add_answer(&question_id, "Like so.")
+            .await
+            .unwrap();
+        send_queued(&notifier).await;
+
+        let mails: Vec<String> = mails(&dir);
+        assert_eq!(mails.len(), 1);
+        let mail: &str = &mails[0];
+        assert_eq!(header(mail, "To"), Some(ANN));
+        assert_eq!(header(mail, "Subject"), Some(r#"New answer to "How?""#));
+        let unsubscribe: &str = header(mail, "List-Unsubscribe").unwrap();
+        assert!(
+            unsubscribe.starts_with(
+                "<https://question.example.org/api/v1/notifications/unsubscribe?email=ann"
+            ),
+            "{}",
+            unsubscribe
+        );
+        assert_eq!(
+            header(mail, "List-Unsubscribe-Post"),
+            Some("List-Unsubscribe=One-Click")
+        );
+    }
+
+    #[sqlx::test]
+    async fn preferences_are_respected(pool: PgPool) {
+        let (notifier, dir, question_id) = setup(pool.clone(), &[ANN, BOB, CAT]).await;
+        set_preferences(&pool, "bob@example.org", true, true).await;
+        set_preferences(&pool, "cat@example.org", false, false).await;
+        for answer in ["Like so.", "Or so."] {
+            notifier
+                .store
+                .add_answer(&question_id, answer)
+                .await
+                .unwrap();
+        }
+
+        send_queued(&notifier).await;
+        let to: Vec<Option<String>> = mails(&dir)
+            .iter()
+            .map(|mail| header(mail, "To").map(str::to_string))
+            .collect();
+        assert_eq!(to, vec![Some(ANN.to_string()), Some(ANN.to_string())]);
+
+        SendDigests(notifier.clone())
+            .run(serde_json::Value::Null)
+            .await
+            .unwrap();
+        let mails: Vec<String> = mails(&dir);
+        let digests: Vec<(Option<&str>, Option<&str>)> = mails
+            .iter()
+            .filter(|mail| header(mail, "To") != Some(ANN))
+            .map(|mail| (header(mail, "To"), header(mail, "Subject")))
+            .collect();
+        assert_eq!(digests, vec![(Some(BOB), Some("2 new answers"))]);
+        let (left,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM notifications;"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[sqlx::test]
+    async fn unsubscribing_needs_the_address_signature(pool: PgPool) {
+        let (app, _) = test_router(pool.clone());
+        let token: String = JwtKeys::new(b"test").sign(UNSUBSCRIBE, "ann@example.org");
+        let link = |email: &str| {
+            let query: String =
+                serde_urlencoded::to_string([("email", email), ("token", token.as_str())]).unwrap();
+            format!("/api/v1/notifications/unsubscribe?{}", query)
+        };
+
+        // Method, address, status, and whether ann's mail is off afterwards.
+        let cases: [(Method, &str, StatusCode, bool); 4] = [
+            (
+                Method::POST,
+                "bob@example.org",
+                StatusCode::FORBIDDEN,
+                false,
+            ),
+            (Method::GET, "bob@example.org", StatusCode::FORBIDDEN, false),
+            (Method::GET, "ann@example.org", StatusCode::OK, false),
+            (Method::POST, "ann@example.org", StatusCode::OK, true),
+        ];
+        for (method, email, status, off) in cases {
+            let request: Request<Body> = Request::builder()
+                .method(method.clone())
+                .uri(link(email))
+                .header(
+                    http::header::CONTENT_TYPE,
+                    "application/x-www-form-urlencoded",
+                )
+                .body(Body::from("List-Unsubscribe=One-Click"))
+                .unwrap();
+            let response: Response = app.clone().oneshot(request).await.unwrap();
+            let disabled: Option<(bool,)> = sqlx::query_as(
+                r#"SELECT NOT email_enabled FROM notification_preferences WHERE email = $1;"#,
+            )
+            .bind("ann@example.org")
+            .fetch_optional(&pool)
+            .await
+            .unwrap();
+            assert_eq!(
+                (response.status(), disabled.is_some_and(|(off,)| off)),
+                (status, off),
+                "{} {}",
+                method,
+                email
+            );
+        }
+    }
+}
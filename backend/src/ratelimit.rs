@@ -0,0 +1,469 @@
+//! # Rate Limiting
+//!
+//! Token bucket rate limiter applied as a route layer to each group of API routes.
+//! Every group (reads, writes, authentication) has its own budget, and every client has its
+//! own bucket within a group. A client is identified by the `sub` of a valid bearer token,
+//! a valid `X-Api-Key` header or its IP address, depending on `--rate-limit-by`. Behind
+//! proxies, `--rate-limit-trust-forwarded` takes the IP from `X-Forwarded-For`, counting
+//! `--rate-limit-proxy-hops` addresses from the right, since a client can put anything to the
+//! left of what the proxies append.
+//!
+//! Responses carry `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and
+//! `RateLimit-Policy` headers; rejected requests get a `429 Too Many Requests` problem with
+//! `Retry-After`.
+
+use crate::auth::{JwtKeys, API_KEY_HEADER};
+use crate::error::Problem;
+use crate::*;
+
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::Next;
+use headers::authorization::Bearer;
+use headers::{Authorization, HeaderMapExt};
+use http::HeaderMap;
+use std::time::Instant;
+
+/// Buckets are pruned once a group tracks more clients than this.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Which request property identifies a client. More specific identities fall back to
+/// less specific ones when the request doesn't carry them.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBy {
+    /// Client IP address.
+    Ip,
+    /// Valid `X-Api-Key` header, falling back to IP.
+    ApiKey,
+    /// `sub` claim of a valid bearer token, falling back to API key, then IP.
+    Subject,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RateLimitArgs {
+    /// How clients are told apart for rate limiting.
+    #[clap(long, env = "RATE_LIMIT_BY", value_enum, default_value = "ip")]
+    pub rate_limit_by: KeyBy,
+    /// Take the client IP from `X-Forwarded-For` (only behind trusted proxies).
+    #[clap(long, env = "RATE_LIMIT_TRUST_FORWARDED")]
+    pub rate_limit_trust_forwarded: bool,
+    /// Trusted proxies in front of the server, each appending to `X-Forwarded-For`. The client
+    /// IP is the address this many entries from the right.
+    #[clap(long, env = "RATE_LIMIT_PROXY_HOPS", default_value_t = 1)]
+    pub rate_limit_proxy_hops: usize,
+    /// Burst size for read routes.
+    #[clap(long, env = "RATE_LIMIT_READ_BURST", default_value_t = 60)]
+    pub rate_limit_read_burst: u32,
+    /// Sustained requests per minute for read routes.
+    #[clap(long, env = "RATE_LIMIT_READ_PER_MINUTE", default_value_t = 120)]
+    pub rate_limit_read_per_minute: u32,
+    /// Burst size for routes that add, change or delete questions.
+    #[clap(long, env = "RATE_LIMIT_WRITE_BURST", default_value_t = 10)]
+    pub rate_limit_write_burst: u32,
+    /// Sustained requests per minute for routes that add, change or delete questions.
+    #[clap(long, env = "RATE_LIMIT_WRITE_PER_MINUTE", default_value_t = 20)]
+    pub rate_limit_write_per_minute: u32,
+    /// Burst size for registration and login routes.
+    #[clap(long, env = "RATE_LIMIT_AUTH_BURST", default_value_t = 5)]
+    pub rate_limit_auth_burst: u32,
+    /// Sustained requests per minute for registration and login routes.
+    #[clap(long, env = "RATE_LIMIT_AUTH_PER_MINUTE", default_value_t = 5)]
+    pub rate_limit_auth_per_minute: u32,
+}
+
+/// Size and refill rate of one route group's buckets.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Budget {
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+
+    /// Seconds until `tokens` have grown back to `target`.
+    fn secs_until(&self, tokens: f64, target: f64) -> u64 {
+        if tokens >= target || self.per_minute == 0 {
+            return 0;
+        }
+        ((target - tokens) / self.refill_per_sec()).ceil() as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of charging one request to a bucket.
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    reset: u64,
+    retry_after: u64,
+}
+
+pub struct RateLimiter {
+    group: &'static str,
+    budget: Budget,
+    key_by: KeyBy,
+    trust_forwarded: bool,
+    proxy_hops: usize,
+    jwt_keys: JwtKeys,
+    buckets: std::sync::Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        group: &'static str,
+        budget: Budget,
+        args: &RateLimitArgs,
+        jwt_keys: JwtKeys,
+    ) -> Self {
+        Self {
+            group,
+            budget,
+            key_by: args.rate_limit_by,
+            trust_forwarded: args.rate_limit_trust_forwarded,
+            proxy_hops: args.rate_limit_proxy_hops,
+            jwt_keys,
+            buckets: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Build the read, write and auth limiters described by `args`.
+    pub fn groups(args: &RateLimitArgs, jwt_keys: &JwtKeys) -> (Arc<Self>, Arc<Self>, Arc<Self>) {
+        let read: Budget = Budget {
+            burst: args.rate_limit_read_burst,
+            per_minute: args.rate_limit_read_per_minute,
+        };
+        let write: Budget = Budget {
+            burst: args.rate_limit_write_burst,
+            per_minute: args.rate_limit_write_per_minute,
+        };
+        let auth: Budget = Budget {
+            burst: args.rate_limit_auth_burst,
+            per_minute: args.rate_limit_auth_per_minute,
+        };
+        (
+            Arc::new(Self::new("read", read, args, jwt_keys.clone())),
+            Arc::new(Self::new("write", write, args, jwt_keys.clone())),
+            Arc::new(Self::new("auth", auth, args, jwt_keys.clone())),
+        )
+    }
+
+    /// The identity a request is charged to.
+    fn client_key(&self, request: &Request) -> String {
+        let headers: &HeaderMap = request.headers();
+        if self.key_by == KeyBy::Subject {
+            if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+                if let Ok(claims) = self.jwt_keys.decode_claims(bearer.token()) {
+                    return format!("sub:{}", claims.sub);
+                }
+            }
+        }
+        if self.key_by != KeyBy::Ip {
+            if let Some(key) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) {
+                // Made up keys would otherwise each get a fresh bucket.
+                if self.jwt_keys.decode_claims(key).is_ok() {
+                    return format!("key:{}", key);
+                }
+            }
+        }
+        if self.trust_forwarded {
+            let forwarded: Option<&str> = headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| forwarded_client(v, self.proxy_hops));
+            if let Some(ip) = forwarded {
+                return format!("ip:{}", ip);
+            }
+        }
+        match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        }
+    }
+
+    /// Refill the client's bucket for the time since its last request and try to take a token.
+    fn check(&self, key: String) -> Decision {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: String, now: Instant) -> Decision {
+        let burst: f64 = f64::from(self.budget.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_TRACKED_CLIENTS {
+            let budget: Budget = self.budget;
+            buckets.retain(|_, b| {
+                let elapsed: f64 = now.duration_since(b.updated).as_secs_f64();
+                b.tokens + elapsed * budget.refill_per_sec() < burst
+            });
+        }
+        let bucket: &mut Bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed: f64 = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.budget.refill_per_sec()).min(burst);
+        bucket.updated = now;
+
+        let allowed: bool = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Decision {
+            allowed,
+            remaining: bucket.tokens.floor() as u32,
+            reset: self.budget.secs_until(bucket.tokens, burst),
+            retry_after: self.budget.secs_until(bucket.tokens, 1.0).max(1),
+        }
+    }
+
+    fn set_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+        let window: u32 = (self.budget.burst * 60)
+            .checked_div(self.budget.per_minute)
+            .unwrap_or(0);
+        headers.insert("ratelimit-limit", HeaderValue::from(self.budget.burst));
+        headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(decision.reset));
+        if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", self.budget.burst, window)) {
+            headers.insert("ratelimit-policy", policy);
+        }
+    }
+}
+
+/// The address `hops` entries from the right of an `X-Forwarded-For` value: the one the
+/// outermost trusted proxy saw. None when there are fewer entries than proxies.
+fn forwarded_client(value: &str, hops: usize) -> Option<&str> {
+    value
+        .rsplit(',')
+        .nth(hops.checked_sub(1)?)
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+}
+
+/// Middleware charging each request to its client's bucket in `limiter`'s route group.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key: String = limiter.client_key(&request);
+    let decision: Decision = limiter.check(key);
+    if !decision.allowed {
+        tracing::info!("rate limit exceeded in group {}", limiter.group);
+        let mut response: Response = Problem::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate-limited",
+            "Too Many Requests",
+        )
+        .with_detail(format!(
+            "rate limit for {} requests exceeded, retry in {} s",
+            limiter.group, decision.retry_after
+        ))
+        .into_response();
+        limiter.set_headers(response.headers_mut(), &decision);
+        response.headers_mut().insert(
+            http::header::RETRY_AFTER,
+            HeaderValue::from(decision.retry_after),
+        );
+        return response;
+    }
+    let mut response: Response = next.run(request).await;
+    limiter.set_headers(response.headers_mut(), &decision);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Claims;
+    use axum::body::Body;
+    use std::time::Duration;
+
+    const SECRET: &[u8] = b"test";
+
+    fn limiter(budget: Budget, extra: &[&str]) -> RateLimiter {
+        let args: Vec<&str> = [&["backend"], extra].concat();
+        let args: Args = Args::parse_from(args);
+        RateLimiter::new("test", budget, &args.rate_limit, JwtKeys::new(SECRET))
+    }
+
+    fn token(sub: &str) -> String {
+        use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+        let claims: Claims = Claims {
+            iss: "question.po8.org".to_string(),
+            sub: sub.to_string(),
+            exp: u64::MAX / 2,
+            email_verified: false,
+        };
+        let key: EncodingKey = EncodingKey::from_secret(SECRET);
+        encode(&Header::new(Algorithm::HS512), &claims, &key).unwrap()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut request = http::Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut request: Request = request.body(Body::empty()).unwrap();
+        let peer: SocketAddr = "10.0.0.9:4000".parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    }
+
+    #[test]
+    fn secs_until() {
+        let budget: Budget = Budget {
+            burst: 10,
+            per_minute: 30,
+        };
+        let stopped: Budget = Budget {
+            burst: 10,
+            per_minute: 0,
+        };
+        for (budget, tokens, target, secs) in [
+            (budget, 10.0, 10.0, 0),
+            (budget, 12.0, 10.0, 0),
+            (budget, 9.0, 10.0, 2),
+            (budget, 9.5, 10.0, 1),
+            (budget, 0.0, 1.0, 2),
+            (budget, 0.0, 10.0, 20),
+            (stopped, 0.0, 10.0, 0),
+        ] {
+            assert_eq!(
+                budget.secs_until(tokens, target),
+                secs,
+                "{} to {}",
+                tokens,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn check_rejects_past_the_burst_and_refills() {
+        let budget: Budget = Budget {
+            burst: 3,
+            per_minute: 60,
+        };
+        let limiter: RateLimiter = limiter(budget, &[]);
+        let start: Instant = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        for remaining in [2, 1, 0] {
+            let decision: Decision = limiter.check_at("a".to_string(), start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let decision: Decision = limiter.check_at("a".to_string(), start);
+        assert!(!decision.allowed);
+        assert_eq!((decision.retry_after, decision.reset), (1, 3));
+        // Other clients have their own buckets.
+        assert!(limiter.check_at("b".to_string(), start).allowed);
+
+        // One token a second comes back, up to the burst.
+        assert!(limiter.check_at("a".to_string(), at(1)).allowed);
+        assert!(!limiter.check_at("a".to_string(), at(1)).allowed);
+        let decision: Decision = limiter.check_at("a".to_string(), at(100));
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+    }
+
+    #[test]
+    fn forwarded_client() {
+        for (value, hops, client) in [
+            ("203.0.113.7", 1, Some("203.0.113.7")),
+            ("1.2.3.4, 203.0.113.7", 1, Some("203.0.113.7")),
+            ("1.2.3.4, 203.0.113.7, 10.0.0.2", 2, Some("203.0.113.7")),
+            ("203.0.113.7", 2, None),
+            ("203.0.113.7", 0, None),
+            ("1.2.3.4, ", 1, None),
+        ] {
+            assert_eq!(
+                super::forwarded_client(value, hops),
+                client,
+                "{} {}",
+                value,
+                hops
+            );
+        }
+    }
+
+    #[test]
+    fn client_key() {
+        let budget: Budget = Budget {
+            burst: 1,
+            per_minute: 1,
+        };
+        let valid: String = token("Jane <jane@example.org>");
+        let bearer: String = format!("Bearer {}", valid);
+        let by_ip: RateLimiter = limiter(budget, &[]);
+        let by_key: RateLimiter = limiter(budget, &["--rate-limit-by=api-key"]);
+        let by_subject: RateLimiter = limiter(budget, &["--rate-limit-by=subject"]);
+        let forwarded: RateLimiter = limiter(budget, &["--rate-limit-trust-forwarded"]);
+        let forwarded_twice: RateLimiter = limiter(
+            budget,
+            &["--rate-limit-trust-forwarded", "--rate-limit-proxy-hops=2"],
+        );
+        let xff: &str = "6.6.6.6, 203.0.113.7, 10.0.0.2";
+
+        for (limiter, headers, key) in [
+            (
+                &by_ip,
+                vec![(API_KEY_HEADER, valid.as_str())],
+                "ip:10.0.0.9".to_string(),
+            ),
+            (
+                &by_ip,
+                vec![("x-forwarded-for", xff)],
+                "ip:10.0.0.9".to_string(),
+            ),
+            (
+                &by_key,
+                vec![(API_KEY_HEADER, valid.as_str())],
+                format!("key:{}", valid),
+            ),
+            (
+                &by_key,
+                vec![(API_KEY_HEADER, "made-up")],
+                "ip:10.0.0.9".to_string(),
+            ),
+            (
+                &by_subject,
+                vec![("authorization", bearer.as_str())],
+                "sub:Jane <jane@example.org>".to_string(),
+            ),
+            (
+                &by_subject,
+                vec![("authorization", "Bearer made-up")],
+                "ip:10.0.0.9".to_string(),
+            ),
+            (
+                &by_subject,
+                vec![(API_KEY_HEADER, "made-up")],
+                "ip:10.0.0.9".to_string(),
+            ),
+            (
+                &forwarded,
+                vec![("x-forwarded-for", xff)],
+                "ip:10.0.0.2".to_string(),
+            ),
+            (
+                &forwarded_twice,
+                vec![("x-forwarded-for", xff)],
+                "ip:203.0.113.7".to_string(),
+            ),
+            (
+                &forwarded_twice,
+                vec![("x-forwarded-for", "10.0.0.2")],
+                "ip:10.0.0.9".to_string(),
+            ),
+        ] {
+            assert_eq!(limiter.client_key(&request(&headers)), key, "{:?}", headers);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! # Request IDs
+//!
+//! Every request carries an `X-Request-Id`. A well formed id sent by the client (or a proxy in
+//! front of us) is kept, otherwise a fresh one is generated. The id is
+//!
+//! * recorded on the request's tracing span, so every log line for the request carries it,
+//! * echoed in the `X-Request-Id` response header,
+//! * used as the `trace_id` of any problem document produced while handling the request.
+
+use crate::*;
+
+use axum::extract::Request;
+use axum::middleware::Next;
+use http::HeaderName;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client supplied id we accept; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled by the current task, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Random 16-byte hex id.
+pub fn generate() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Ids end up in logs verbatim, so only printable ASCII without spaces or quotes is accepted.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:/+=".contains(&b))
+}
+
+/// Middleware assigning the request id. It must wrap the trace layer so the request span can
+/// pick the id up from the request headers.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id: String = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map_or_else(generate, str::to_string);
+    // Validated or generated above, so always a legal header value.
+    let value: HeaderValue = HeaderValue::from_str(&id).unwrap();
+    request.headers_mut().insert(X_REQUEST_ID, value.clone());
+
+    let mut response: Response = REQUEST_ID.scope(id, next.run(request)).await;
+    response.headers_mut().insert(X_REQUEST_ID, value);
+    response
+}
@@ -21,7 +21,9 @@ async fn add_answer(store: Store, new_answer: Answer) -> impl IntoResponse {
         question_id: QuestionId(params.get("questionId").unwrap().to_string()),
     };*/
 
-    store.add_answer(new_answer.clone()).await;
+    store
+        .add_answer(&new_answer.question_id, &new_answer.content)
+        .await;
 
     (
         StatusCode::OK,
@@ -85,7 +85,8 @@ pub async fn update_question(
     mut store: Store,
     question: Question,
 ) -> Result<(), error::StoreErr> {
-    store.update_question("1", question, id).await
+    store.update_question(&id.to_string(), question).await?;
+    Ok(())
 }
 
 /// Deletes a specific question from the store.
@@ -132,71 +133,6 @@ pub async fn delete_question(
 /// If the question is successfully added, it returns a 200 OK response.
 pub async fn add_question(store: Store, question: axum::Json<Question>) -> impl IntoResponse {
     let question = question.0;
-    store.add_question(question, 1).await;
+    store.add_question(question, None).await;
     StatusCode::OK
 }
-
-/// The function `format_tags` takes a HashSet of strings and returns a formatted string with the tags
-/// separated by commas.
-///
-/// Arguments:
-///
-/// * `tags`: The `format_tags` function takes a reference to a `HashSet` of `String` values as input.
-/// It then converts the `HashSet` into a vector of string references and joins them together with a
-/// comma and space to create a single formatted string.
-///
-/// Returns:
-///
-/// A formatted string containing the tags from the HashSet, separated by commas.
-pub fn format_tags(tags: &HashSet<String>) -> String {
-    let taglist: Vec<&str> = tags.iter().map(String::as_ref).collect();
-    taglist.join(", ")
-}
-
-impl Question {
-    pub fn new(
-        id: QuestionId,
-        title: &str,
-        content: &str,
-        tags: &[&str],
-        source: Option<&str>,
-    ) -> Self {
-        let id: QuestionId = id;
-        let title: String = title.into();
-        let content: String = content.into();
-        let tags: Option<HashSet<String>> = if tags.is_empty() {
-            None
-        } else {
-            Some(tags.iter().copied().map(String::from).collect())
-        };
-        Self {
-            id,
-            title,
-            content,
-            tags,
-        }
-    }
-}
-
-impl From<&Question> for String {
-    fn from(question: &Question) -> Self {
-        let mut text: String = "Question:\n".into();
-        text += &format!("{}.\n", question.title);
-        text += &format!("{}\n", question.content);
-        text += "\n";
-
-        let mut annote: Vec<String> = vec![format!("id: {:?}", question.id)];
-        if let Some(tags) = &question.tags {
-            annote.push(format!("tags: {}", format_tags(tags)));
-        }
-        let annote: String = annote.join("; ");
-        text += &format!("[{}]\n", annote);
-        text
-    }
-}
-
-impl IntoResponse for &Question {
-    fn into_response(self) -> Response {
-        (StatusCode::OK, Json(&self)).into_response()
-    }
-}
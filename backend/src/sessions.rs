@@ -0,0 +1,170 @@
+//! # Sessions
+//!
+//! Cookie sessions for the server-rendered pages, kept in the `sessions` table so they survive
+//! restarts and are shared between instances. Expired rows are deleted every
+//! [`CLEANUP_INTERVAL`] by the [`DELETE_EXPIRED`] job.
+//!
+//! The pages log in with the same credentials as `/api/v1/register`; the session then holds the
+//! user's subject under [`SESSION_USER_KEY`]. The JSON API keeps using bearer tokens.
+
+use crate::jobs::JobHandler;
+use crate::*;
+
+use sqlx::types::Json;
+use sqlx::PgPool;
+use std::time::Duration;
+use tower_sessions::cookie::time::OffsetDateTime;
+use tower_sessions::cookie::SameSite;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, ExpiredDeletion, SessionStore};
+
+/// Session key holding the logged in user's subject, `Full Name <email>`.
+pub const SESSION_USER_KEY: &str = "user";
+
+/// How often expired sessions are deleted.
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Kind of the recurring job deleting expired sessions.
+pub const DELETE_EXPIRED: &str = "sessions.delete_expired";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(same_site: CookieSameSite) -> Self {
+        match same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct SessionArgs {
+    /// Mark the session cookie `Secure`. Always on when serving HTTPS.
+    #[clap(long, env = "SESSION_SECURE")]
+    pub session_secure: bool,
+    /// `SameSite` attribute of the session cookie. `none` needs `--session-secure`.
+    #[clap(long, env = "SESSION_SAME_SITE", value_enum, default_value_t = CookieSameSite::Lax)]
+    pub session_same_site: CookieSameSite,
+    /// Minutes of inactivity after which a session expires.
+    #[clap(long, env = "SESSION_IDLE_MINUTES", default_value_t = 24 * 60)]
+    pub session_idle_minutes: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+fn backend(e: sqlx::Error) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl JobHandler for PgSessionStore {
+    async fn run(&self, _payload: serde_json::Value) -> Result<(), String> {
+        self.delete_expired().await.map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        // Retry with a fresh id in the unlikely event of a collision.
+        loop {
+            let inserted: u64 = sqlx::query(
+                r#"INSERT INTO sessions (id, data, expiry_date)
+            VALUES ($1, $2, to_timestamp($3))
+            ON CONFLICT (id) DO NOTHING;"#,
+            )
+            .bind(record.id.to_string())
+            .bind(Json(&record.data))
+            .bind(record.expiry_date.unix_timestamp())
+            .execute(&self.pool)
+            .await
+            .map_err(backend)?
+            .rows_affected();
+            if inserted == 1 {
+                return Ok(());
+            }
+            record.id = Id::default();
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        sqlx::query(
+            r#"INSERT INTO sessions (id, data, expiry_date)
+        VALUES ($1, $2, to_timestamp($3))
+        ON CONFLICT (id)
+        DO UPDATE SET data = EXCLUDED.data, expiry_date = EXCLUDED.expiry_date;"#,
+        )
+        .bind(record.id.to_string())
+        .bind(Json(&record.data))
+        .bind(record.expiry_date.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let row: Option<(Json<HashMap<String, serde_json::Value>>, i64)> = sqlx::query_as(
+            r#"SELECT data, EXTRACT(EPOCH FROM expiry_date)::BIGINT
+        FROM sessions
+        WHERE id = $1 AND expiry_date > NOW();"#,
+        )
+        .bind(session_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(backend)?;
+        let Some((Json(data), expiry)) = row else {
+            return Ok(None);
+        };
+        let expiry_date: OffsetDateTime = OffsetDateTime::from_unix_timestamp(expiry)
+            .map_err(|e| session_store::Error::Decode(e.to_string()))?;
+        Ok(Some(Record {
+            id: *session_id,
+            data,
+            expiry_date,
+        }))
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        sqlx::query(r#"DELETE FROM sessions WHERE id = $1;"#)
+            .bind(session_id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for PgSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let deleted: u64 = sqlx::query(r#"DELETE FROM sessions WHERE expiry_date <= NOW();"#)
+            .execute(&self.pool)
+            .await
+            .map_err(backend)?
+            .rows_affected();
+        tracing::debug!("deleted {} expired sessions", deleted);
+        Ok(())
+    }
+}
+
+/// Subject of the user logged in to `session`, if any.
+pub async fn session_user(session: &Session) -> Option<String> {
+    session.get::<String>(SESSION_USER_KEY).await.ok().flatten()
+}
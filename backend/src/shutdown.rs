@@ -0,0 +1,165 @@
+//! # Graceful Shutdown
+//!
+//! On `SIGTERM` or `SIGINT` `startup` cancels its [`Shutdown`]. The HTTP and gRPC servers stop
+//! accepting connections and give in-flight requests up to `--shutdown-grace-secs` to finish;
+//! event streams and websockets end at once, and the job, webhook and notification workers stop
+//! after the item they are working on. Requests still running at the deadline are dropped,
+//! which rolls back any open database transaction. `startup` then waits up to the same grace
+//! period for the gRPC server and the workers, closes the database pool, waiting at most
+//! [`POOL_CLOSE_TIMEOUT`], and flushes telemetry.
+
+use crate::*;
+
+use axum_server::tls_rustls::RustlsConfig;
+use std::future::{Future, IntoFuture};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// How long to wait for the database pool's connections to close.
+pub const POOL_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ShutdownArgs {
+    /// Seconds to wait for in-flight requests, and then for background work, after a shutdown
+    /// signal.
+    #[clap(long, env = "SHUTDOWN_GRACE_SECS", default_value_t = 30)]
+    pub shutdown_grace_secs: u64,
+}
+
+/// Tells the servers, streams and background workers to stop, and tracks the tasks `startup`
+/// must wait for before closing the pool. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl Shutdown {
+    /// Start shutting down. Idempotent.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once shutdown has started.
+    pub async fn cancelled(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Run `task` in the background; [`Shutdown::drain`] waits for it.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Cancel, then wait up to `timeout` for the spawned tasks. False if some were still
+    /// running at the deadline.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.cancel();
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Resolves on the first `SIGINT` (Ctrl-C) or, on Unix, `SIGTERM`.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("installing SIGINT handler: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("installing SIGTERM handler: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received SIGINT"),
+        _ = terminate => tracing::info!("received SIGTERM"),
+    }
+}
+
+/// Serve `app` on `listener` until `shutdown` is cancelled, then drain in-flight requests for at
+/// most the configured grace period.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    args: &ShutdownArgs,
+    shutdown: Shutdown,
+) -> std::io::Result<()> {
+    let grace: Duration = Duration::from_secs(args.shutdown_grace_secs);
+    let (signalled_tx, mut signalled_rx) = watch::channel(false);
+
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown.cancelled().await;
+        tracing::info!("draining in-flight requests for up to {:?}", grace);
+        let _ = signalled_tx.send(true);
+    });
+
+    let deadline = async move {
+        if signalled_rx.wait_for(|signalled| *signalled).await.is_err() {
+            // The server is gone without a signal; its own result wins the race below.
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(grace).await;
+    };
+
+    tokio::select! {
+        result = server.into_future() => result,
+        _ = deadline => {
+            tracing::warn!("shutdown deadline passed, dropping remaining connections");
+            Ok(())
+        }
+    }
+}
+
+/// Like [`serve`], over TLS.
+pub async fn serve_tls(
+    listener: TcpListener,
+    config: RustlsConfig,
+    app: Router,
+    args: &ShutdownArgs,
+    shutdown: Shutdown,
+) -> std::io::Result<()> {
+    let grace: Duration = Duration::from_secs(args.shutdown_grace_secs);
+    let handle: axum_server::Handle = axum_server::Handle::new();
+
+    let signalled: axum_server::Handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.cancelled().await;
+        tracing::info!("draining in-flight requests for up to {:?}", grace);
+        signalled.graceful_shutdown(Some(grace));
+    });
+
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
@@ -1,9 +1,38 @@
-use crate::auth::make_jwt_keys;
 use crate::auth::read_secret;
+use crate::auth::{make_jwt_keys, JwtKeys};
+use crate::csrf::csrf_protect;
+use crate::error::{problem_details, Problem};
+use crate::events::events;
+use crate::graphql::{graphiql, graphql};
+use crate::health::{healthz, readyz};
+use crate::jobs::{list_jobs, Jobs, PurgeJobs, PURGE_JOBS};
+use crate::mailer::{mailer, Mailer};
+use crate::metrics::{metrics, track_metrics};
+use crate::notifications::{
+    get_preferences, put_preferences, unsubscribe, unsubscribe_form, unwatch_question,
+    watch_question, Notifier, SendDigests, SendNotification, SEND_DIGESTS, SEND_NOTIFICATION,
+};
+use crate::oidc::{oidc_callback, oidc_login, OidcClient};
+use crate::ratelimit::{rate_limit, RateLimiter};
+use crate::requestid::request_id;
+use crate::sessions::{PgSessionStore, CLEANUP_INTERVAL, DELETE_EXPIRED};
+use crate::shutdown::{Shutdown, POOL_CLOSE_TIMEOUT};
 use crate::store::Store;
+use crate::telemetry::make_request_span;
+use crate::v2::Deprecation;
+use crate::web::{
+    handler_add, handler_index, handler_login, handler_login_form, handler_logout,
+    handler_stylesheet, handler_tags, handler_tell, SITE_ROOT,
+};
+use crate::webhooks::{
+    create_webhook, delete_webhook, get_delivery, list_deliveries, list_webhooks, redeliver,
+    update_webhook, PurgeDeliveries, PURGE_DELIVERIES,
+};
+use crate::ws::ws;
 use crate::*;
-use appstate::AppState;
-use axum::extract::FromRequest;
+use appstate::{AppState, SharedAppState};
+use axum::extract::{DefaultBodyLimit, FromRequest};
+use axum::middleware;
 use bytes::Bytes;
 use core::convert::Infallible;
 use http::{header::USER_AGENT, HeaderValue, Request};
@@ -14,12 +43,14 @@ use serde_wasm_bindgen::Error;
 use sqlx::error;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 use tower::{Service, ServiceBuilder, ServiceExt};
 use tower_http::add_extension::{AddExtension, AddExtensionLayer};
 use tower_http::{
     classify::StatusInRangeAsFailures, decompression::DecompressionLayer,
     set_header::SetRequestHeaderLayer, trace::TraceLayer,
 };
+use tower_sessions::cookie::time;
 use utoipa::OpenApi;
 use utoipa_rapidoc::RapiDoc;
 use utoipa_redoc::Redoc;
@@ -28,94 +59,552 @@ use utoipa_swagger_ui::SwaggerUi;
 
 // Define an async handler function for Axum
 async fn handler_404() -> Response {
-    (StatusCode::NOT_FOUND, "404 Not Found").into_response()
+    Problem::from_status(StatusCode::NOT_FOUND).into_response()
 }
 
 pub const SESSION_ERROR_KEY: &str = "session_error";
 
-pub async fn startup(ip: String) {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "question=debug,info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// The HTTP routes and their middleware, except `/metrics`, which may be served on its own
+/// listener.
+pub fn router(
+    args: &Args,
+    state: SharedAppState,
+    jwt_keys: &JwtKeys,
+    session_store: PgSessionStore,
+) -> Router {
     // https://carlosmv.hashnode.dev/adding-logging-and-tracing-to-an-axum-app-rust
     let trace_layer = trace::TraceLayer::new_for_http()
-        .make_span_with(trace::DefaultMakeSpan::new().level(tracing::Level::INFO))
+        .make_span_with(make_request_span)
         .on_response(trace::DefaultOnResponse::new().level(tracing::Level::INFO));
 
-    let session_store = MemoryStore::default();
     let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false)
-        .with_expiry(Expiry::OnSessionEnd);
+        .with_secure(args.session.session_secure || args.tls.tls_cert_file.is_some())
+        .with_same_site(args.session.session_same_site.into())
+        .with_expiry(Expiry::OnInactivity(time::Duration::minutes(
+            args.session.session_idle_minutes,
+        )));
 
-    use std::env::var;
+    let (read_limiter, write_limiter, auth_limiter) =
+        RateLimiter::groups(&args.rate_limit, jwt_keys);
 
-    //let password = read_secret("PG_PASSWORDFILE").await?;
+    let cors_layer = |policy: cors::CorsPolicy| {
+        policy.layer().unwrap_or_else(|e| {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        })
+    };
+    let read_cors = cors_layer(args.cors.read());
+    let write_cors = cors_layer(args.cors.write());
+    let auth_cors = cors_layer(args.cors.auth());
 
-    let url = "localhost:5432";
+    let read_apis = Router::new()
+        .route("/questions", get(questions))
+        .route("/question", get(question))
+        .route("/question/:id", get(get_question))
+        .route("/events", get(events))
+        .route("/ws", get(ws))
+        .route("/notifications/preferences", get(get_preferences))
+        .route("/notifications/unsubscribe", get(unsubscribe_form))
+        .route_layer(middleware::from_fn_with_state(
+            read_limiter.clone(),
+            rate_limit,
+        ))
+        .route_layer(read_cors);
 
-    let jokebase: Store = Store::new(url).await;
-    /*{
-        tracing::error!("jokebase: {}", e);
-        std::process::exit(1);
-    };*/
+    let write_apis = Router::new()
+        .route("/question/add", post(post_question))
+        .route("/question/:id", delete(delete_question))
+        .route("/question/:id", put(update_question))
+        .route("/question/:id/answer", post(post_answer))
+        .route(
+            "/question/:id/watch",
+            put(watch_question).delete(unwatch_question),
+        )
+        .route("/notifications/preferences", put(put_preferences))
+        .route("/notifications/unsubscribe", post(unsubscribe))
+        .route_layer(middleware::from_fn_with_state(
+            write_limiter.clone(),
+            rate_limit,
+        ))
+        .route_layer(write_cors);
 
-    let jwt_keys = make_jwt_keys().await.unwrap_or_else(|_| {
-        tracing::error!("jwt keys");
-        std::process::exit(1);
-    });
+    let auth_apis = Router::new()
+        .route("/register", get(register).post(register))
+        .route("/oidc/login", get(oidc_login))
+        .route("/oidc/callback", get(oidc_callback))
+        .route_layer(middleware::from_fn_with_state(auth_limiter, rate_limit))
+        .route_layer(auth_cors);
 
-    let reg_key = read_secret("REG_PASSWORD").await.unwrap_or_else(|_| {
-        tracing::error!("reg password");
-        std::process::exit(1);
-    });
+    let admin_apis = Router::new()
+        .route("/jobs", get(list_jobs))
+        .route("/webhooks", get(list_webhooks).post(create_webhook))
+        .route("/webhooks/:id", put(update_webhook).delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(list_deliveries))
+        .route("/webhooks/:id/deliveries/:delivery_id", get(get_delivery))
+        .route(
+            "/webhooks/:id/deliveries/:delivery_id/redeliver",
+            post(redeliver),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            write_limiter.clone(),
+            rate_limit,
+        ))
+        .route_layer(cors_layer(args.cors.write()));
 
-    let state = Arc::new(RwLock::new(AppState::new(jokebase, jwt_keys, reg_key)));
+    // Queries and mutations share one route, so it is limited like the writes.
+    let graphql_api = Router::new()
+        .route("/graphql", get(graphiql).post(graphql))
+        .route_layer(middleware::from_fn_with_state(
+            write_limiter.clone(),
+            rate_limit,
+        ))
+        .route_layer(cors_layer(args.cors.write()));
 
-    let cors = cors::CorsLayer::new()
-        .allow_methods([Method::GET])
-        .allow_origin(cors::Any);
+    let apis = Router::new()
+        .merge(read_apis)
+        .merge(write_apis)
+        .merge(auth_apis)
+        .merge(admin_apis)
+        // Unknown API paths get a 404 rather than the frontend's `index.html`.
+        .fallback(handler_404)
+        .layer(middleware::from_fn_with_state(
+            Arc::new(Deprecation::new(&args.versions)),
+            v2::deprecated,
+        ));
 
-    let mime_type = core::str::FromStr::from_str("image/vnd.microsoft.icon").unwrap();
-    let favicon = services::ServeFile::new_with_mime("assets/static/favicon.ico", &mime_type);
+    let v2_read_apis = Router::new()
+        .route("/questions", get(v2::list_questions))
+        .route("/questions/:id", get(v2::get_question))
+        .route("/questions/:id/answers", get(v2::list_answers))
+        .route("/answers/:id", get(v2::get_answer))
+        .route_layer(middleware::from_fn_with_state(read_limiter, rate_limit))
+        .route_layer(cors_layer(args.cors.read()));
 
-    let mime_type = core::str::FromStr::from_str("text/css").unwrap();
-    let stylesheet = services::ServeFile::new_with_mime(STYLESHEET, &mime_type);
+    let v2_write_apis = Router::new()
+        .route("/questions", post(v2::create_question))
+        .route(
+            "/questions/:id",
+            put(v2::update_question).delete(v2::delete_question),
+        )
+        .route("/questions/:id/answers", post(v2::create_answer))
+        .route_layer(middleware::from_fn_with_state(write_limiter, rate_limit))
+        .route_layer(cors_layer(args.cors.write()));
 
-    let apis = Router::new()
-        .route("/questions", get(questions))
-        .route("/question", get(question))
-        .route("/question/:id", get(get_question))
-        .route("/question/add", post(post_question))
-        .route("/question/:id", delete(delete_question))
-        .route("/question/:id", put(update_question))
-        .route("/register", get(register));
+    let v2_apis = Router::new()
+        .merge(v2_read_apis)
+        .merge(v2_write_apis)
+        .fallback(handler_404);
 
     let swagger_ui = SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi());
     let redoc_ui = Redoc::with_url("/redoc", ApiDoc::openapi());
     let rapidoc_ui = RapiDoc::new("/api-docs/openapi.json").path("/rapidoc");
 
-    let app = Router::new()
-        //.route("/", get(handler_index))
-        //.route("/index.html", get(handler_index))
-        //.route("/tell", get(handler_tell))
-        //.route("/add", get(handler_add))
-        .route_service("/index.css", stylesheet)
-        //.route_service("/favicon.ico", favicon)
+    let pages = Router::new()
+        .route("/", get(handler_index))
+        .route("/tags", get(handler_tags))
+        .route("/tell", get(handler_tell))
+        .route("/add", post(handler_add))
+        .route("/login", get(handler_login_form).post(handler_login))
+        .route("/logout", post(handler_logout))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(args.csrf.clone()),
+            csrf_protect,
+        ))
+        .route("/index.css", get(handler_stylesheet));
+
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .merge(swagger_ui)
         .merge(redoc_ui)
         .merge(rapidoc_ui)
+        .merge(graphql_api)
         .nest("/api/v1", apis)
-        .fallback(handler_404)
-        .layer(cors)
+        .nest(v2::PREFIX, v2_apis)
+        .nest(SITE_ROOT, pages)
+        .fallback_service(frontend::router(&args.frontend))
+        .layer(DefaultBodyLimit::max(args.max_body_bytes))
+        .layer(middleware::from_fn(problem_details))
+        .layer(middleware::from_fn(track_metrics))
         .layer(session_layer)
         .layer(trace_layer)
-        .with_state(state);
+        .layer(middleware::from_fn(request_id))
+        .with_state(state)
+}
+
+/// The API as `startup` serves it, over `pool`, with registration password `password` and rate
+/// limits high enough that tests don't run into them.
+#[cfg(test)]
+pub fn test_router(pool: PgPool) -> (Router, SharedAppState) {
+    let args: Args = Args::parse_from([
+        "backend",
+        "--rate-limit-read-burst=1000",
+        "--rate-limit-write-burst=1000",
+        "--rate-limit-auth-burst=1000",
+    ]);
+    let jwt_keys: JwtKeys = JwtKeys::new(b"test");
+    let store: Store = Store {
+        connection: pool.clone(),
+        events: Arc::default(),
+        notify: false,
+    };
+    let state: SharedAppState = Arc::new(RwLock::new(AppState::new(
+        store,
+        jwt_keys.clone(),
+        "password".to_string(),
+        None,
+        HashSet::new(),
+        Shutdown::default(),
+    )));
+    let app: Router = router(&args, state.clone(), &jwt_keys, PgSessionStore::new(pool));
+    (app, state)
+}
+
+pub async fn startup(args: Args) {
+    let tracer_provider = telemetry::init(&args.telemetry);
+    use std::env::var;
+
+    let shutdown: Shutdown = Shutdown::default();
+    tokio::spawn({
+        let shutdown: Shutdown = shutdown.clone();
+        async move {
+            shutdown::signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    let mut jokebase: Store = Store::new(&args.database).await.unwrap_or_else(|e| {
+        tracing::error!("jokebase: {:?}", e);
+        std::process::exit(1);
+    });
+
+    let session_store: PgSessionStore = PgSessionStore::new(jokebase.connection.clone());
+
+    let jwt_keys = make_jwt_keys().await.unwrap_or_else(|_| {
+        tracing::error!("jwt keys");
+        std::process::exit(1);
+    });
+
+    let reg_key = read_secret("REG_PASSWORD").await.unwrap_or_else(|_| {
+        tracing::error!("reg password");
+        std::process::exit(1);
+    });
+
+    let oidc: Option<Arc<OidcClient>> = match &args.oidc.oidc_issuer {
+        Some(issuer) => match OidcClient::discover(&args.oidc, issuer).await {
+            Ok(client) => Some(Arc::new(client)),
+            Err(e) => {
+                tracing::error!("oidc discovery: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let pool: Pool<Postgres> = jokebase.connection.clone();
+    webhooks::spawn(
+        &args.webhooks,
+        pool.clone(),
+        jokebase.events.clone(),
+        &shutdown,
+    );
+
+    let day: Duration = Duration::from_secs(24 * 60 * 60);
+    let mut jobs: Jobs = Jobs::default()
+        .every(DELETE_EXPIRED, CLEANUP_INTERVAL, session_store.clone())
+        .every(
+            PURGE_JOBS,
+            day,
+            PurgeJobs {
+                pool: pool.clone(),
+                retention_days: args.jobs.job_retention_days,
+            },
+        )
+        .every(
+            PURGE_DELIVERIES,
+            day,
+            PurgeDeliveries {
+                pool: pool.clone(),
+                retention_days: args.webhooks.webhook_retention_days,
+            },
+        );
+
+    let mailer: Option<Arc<dyn Mailer>> = mailer(&args.mail).await.unwrap_or_else(|e| {
+        tracing::error!("mail: {}", e);
+        std::process::exit(1);
+    });
+    match mailer {
+        Some(mailer) => {
+            jokebase.notify = true;
+            let notifier: Notifier = Notifier {
+                store: jokebase.clone(),
+                mailer,
+                jwt_keys: jwt_keys.clone(),
+                site_url: args.notify.site_url.clone(),
+            };
+            jobs = jobs
+                .handle(SEND_NOTIFICATION, SendNotification(notifier.clone()))
+                .every(
+                    SEND_DIGESTS,
+                    Duration::from_secs(args.notify.notify_digest_minutes * 60),
+                    SendDigests(notifier),
+                );
+        }
+        None => tracing::info!("no --mail-dir or --smtp-host; email notifications are off"),
+    }
+    if let Err(e) = jobs.start(&args.jobs, pool.clone(), &shutdown).await {
+        tracing::error!("jobs: {}", e);
+        std::process::exit(1);
+    }
+
+    let admins: HashSet<String> = args
+        .admin_emails
+        .iter()
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+        .collect();
+    let state = Arc::new(RwLock::new(AppState::new(
+        jokebase,
+        jwt_keys.clone(),
+        reg_key,
+        oidc,
+        admins,
+        shutdown.clone(),
+    )));
+    let mut app: Router = router(&args, state.clone(), &jwt_keys, session_store);
+
+    let grpc_listener = tokio::net::TcpListener::bind(&args.grpc.grpc_addr)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("grpc listener: {}", e);
+            std::process::exit(1);
+        });
+    tracing::debug!("serving grpc on {}", grpc_listener.local_addr().unwrap());
+    let grpc_state: SharedAppState = state.clone();
+    let max_body_bytes: usize = args.max_body_bytes;
+    shutdown.spawn(async move {
+        if let Err(e) = grpc::serve(grpc_listener, grpc_state, max_body_bytes).await {
+            tracing::error!("grpc: {}", e);
+        }
+    });
+
+    match &args.metrics.metrics_addr {
+        Some(addr) => {
+            let admin: Router = Router::new()
+                .route("/metrics", get(metrics))
+                .with_state(state);
+            let admin_listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+            tracing::debug!(
+                "serving metrics on {}",
+                admin_listener.local_addr().unwrap()
+            );
+            let stop: Shutdown = shutdown.clone();
+            shutdown.spawn(async move {
+                let served = axum::serve(admin_listener, admin)
+                    .with_graceful_shutdown(async move { stop.cancelled().await })
+                    .await;
+                if let Err(e) = served {
+                    tracing::error!("metrics: {}", e);
+                }
+            });
+        }
+        None => app = app.route("/metrics", get(metrics).with_state(state)),
+    }
+
+    let listener = tokio::net::TcpListener::bind(&args.serve).await.unwrap();
+    let served = match tls::load(&args.tls).await {
+        Some(Ok(config)) => {
+            let https_port: u16 = listener.local_addr().unwrap().port();
+            if let Some(addr) = args.tls.http_redirect_addr.clone() {
+                let stop: Shutdown = shutdown.clone();
+                shutdown.spawn(async move {
+                    if let Err(e) = tls::redirect_http(addr, https_port, stop).await {
+                        tracing::error!("http redirect: {}", e);
+                    }
+                });
+            }
+            tracing::debug!("serving https on {}", listener.local_addr().unwrap());
+            let app: Router = app.layer(tls::hsts(&args.tls));
+            shutdown::serve_tls(listener, config, app, &args.shutdown, shutdown.clone()).await
+        }
+        Some(Err(e)) => {
+            tracing::error!("tls certificate: {}", e);
+            std::process::exit(1);
+        }
+        None => {
+            tracing::debug!("serving {}", listener.local_addr().unwrap());
+            shutdown::serve(listener, app, &args.shutdown, shutdown.clone()).await
+        }
+    };
+    if let Err(e) = served {
+        tracing::error!("server: {}", e);
+    }
+
+    // Also stops the rest if the server failed rather than being signalled.
+    let grace: Duration = Duration::from_secs(args.shutdown.shutdown_grace_secs);
+    if !shutdown.drain(grace).await {
+        tracing::warn!("background tasks still running after {:?}", grace);
+    }
+    match tokio::time::timeout(POOL_CLOSE_TIMEOUT, pool.close()).await {
+        Ok(()) => tracing::info!("database pool closed"),
+        Err(_) => tracing::warn!("database pool not closed after {:?}", POOL_CLOSE_TIMEOUT),
+    }
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            tracing::error!("tracer shutdown: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::extract::MatchedPath;
+    use axum::middleware::Next;
+    use serde_json::Value;
+
+    /// Leaves the route a request matched on its response, as the fallbacks match none.
+    async fn record_route(request: Request<Body>, next: Next) -> Response {
+        let route: Option<MatchedPath> = request.extensions().get::<MatchedPath>().cloned();
+        let mut response: Response = next.run(request).await;
+        if let Some(route) = route {
+            response.extensions_mut().insert(route);
+        }
+        response
+    }
+
+    /// `path` with each `{param}` replaced by `with(param)`, and the names of the params.
+    fn fill(path: &str, with: impl Fn(&str) -> String) -> (String, HashSet<String>) {
+        let mut filled: String = String::new();
+        let mut params: HashSet<String> = HashSet::new();
+        let mut rest: &str = path;
+        while let Some(start) = rest.find('{') {
+            let end: usize = rest[start..].find('}').expect("closed parameter") + start;
+            let param: &str = &rest[start + 1..end];
+            filled.push_str(&rest[..start]);
+            filled.push_str(&with(param));
+            params.insert(param.to_string());
+            rest = &rest[end + 1..];
+        }
+        filled.push_str(rest);
+        (filled, params)
+    }
+
+    /// Every `$ref` in `value`.
+    fn refs<'v>(value: &'v Value, found: &mut Vec<&'v str>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(target)) => found.push(target),
+                        _ => refs(value, found),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn spec_is_consistent() {
+        let spec: Value = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut found: Vec<&str> = Vec::new();
+        refs(&spec, &mut found);
+        for target in found {
+            let name: &str = target
+                .strip_prefix("#/components/schemas/")
+                .unwrap_or_else(|| panic!("{} isn't a schema", target));
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "{}",
+                target
+            );
+        }
+
+        let schemes: &Value = &spec["components"]["securitySchemes"];
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let security: &[Value] =
+                    operation["security"].as_array().map_or(&[], Vec::as_slice);
+                for requirement in security {
+                    for scheme in requirement.as_object().unwrap().keys() {
+                        assert!(
+                            schemes.get(scheme).is_some(),
+                            "{} {} {}",
+                            method,
+                            path,
+                            scheme
+                        );
+                    }
+                }
+                let declared: HashSet<String> = operation["parameters"]
+                    .as_array()
+                    .map_or(&[][..], Vec::as_slice)
+                    .iter()
+                    .filter(|param| param["in"] == "path")
+                    .map(|param| param["name"].as_str().unwrap().to_string())
+                    .collect();
+                assert_eq!(
+                    declared,
+                    fill(path, |param| param.to_string()).1,
+                    "{} {}",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    /// Calls each documented operation without credentials: it must reach the route the spec
+    /// names, with that method, and refuse the call if the spec says it needs a token.
+    #[sqlx::test]
+    async fn routes_every_documented_operation(pool: PgPool) {
+        let (app, state) = test_router(pool);
+        let app: Router = app
+            .route("/metrics", get(metrics).with_state(state))
+            .layer(middleware::from_fn(record_route));
+
+        let spec: Value = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let where_: String = format!("{} {}", method.to_uppercase(), path);
+                let (uri, _) = fill(path, |_| "1".to_string());
+                let mut request = Request::builder()
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .uri(uri);
+                let body: Body = if operation.get("requestBody").is_some() {
+                    request = request.header(http::header::CONTENT_TYPE, "application/json");
+                    Body::from("{}")
+                } else {
+                    Body::empty()
+                };
+                let response: Response = app
+                    .clone()
+                    .oneshot(request.body(body).unwrap())
+                    .await
+                    .unwrap();
 
-    let listener = tokio::net::TcpListener::bind(ip).await.unwrap();
-    tracing::debug!("serving {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+                let route: Option<&str> = response
+                    .extensions()
+                    .get::<MatchedPath>()
+                    .map(MatchedPath::as_str);
+                let (expected, _) = fill(path, |param| format!(":{}", param));
+                assert_eq!(route, Some(expected.as_str()), "{}", where_);
+                let status: StatusCode = response.status();
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{}", where_);
+                let security: &[Value] =
+                    operation["security"].as_array().map_or(&[], Vec::as_slice);
+                let optional: bool = security
+                    .iter()
+                    .any(|requirement| requirement == &serde_json::json!({}));
+                if security.is_empty() || optional {
+                    assert_ne!(status, StatusCode::UNAUTHORIZED, "{}", where_);
+                } else {
+                    assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", where_);
+                }
+            }
+        }
+    }
 }
@@ -13,6 +13,7 @@ use tokio::sync::RwLock;
 use crate::types::{
     answer::{Answer, AnswerId},
     question::{Question, QuestionId},
+    user::{User, UserId},
 };
 use axum_macros::debug_handler;
 use headers::ContentType;
@@ -42,13 +43,78 @@ use utoipa::{
     ToSchema,
 };
 
+use crate::auth::{read_secret, subject_name};
+use crate::events::{Event, EventKind, EventLog};
+use crate::metrics::METRICS;
 use crate::routes::question::get_questions;
+use crate::{notifications, webhooks};
+use question_api::{AnswerResource, QuestionResource};
 use sqlx::error::Error as SqlxError;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgConnectOptions;
+use std::time::Duration;
+use tracing::field::Empty;
 use tracing::{event, instrument, Level};
 
+/// Migrations in `migrations/`, embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Upper bound for the wait between database connection attempts.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Questions as `/api/v2` returns them, with their tags sorted; `q` is the question.
+const SELECT_QUESTION_RESOURCE: &str = r#"SELECT q.id::BIGINT AS id, q.title, q.content,
+            ARRAY(SELECT t.tag FROM tags t WHERE t.id = q.id ORDER BY t.tag) AS tags,
+            q.created_on AT TIME ZONE 'UTC' AS created_on
+        FROM questions q"#;
+
+/// Questions with the tag `$1`, if given, whose title or content matches the `LIKE` pattern
+/// `$2`, if given.
+const QUESTION_FILTER: &str = r#"WHERE ($1::TEXT IS NULL
+            OR EXISTS (SELECT 1 FROM tags t WHERE t.id = q.id AND t.tag = $1))
+        AND ($2::TEXT IS NULL OR q.title ILIKE $2 OR q.content ILIKE $2)"#;
+
+/// Columns of answers as `/api/v2` returns them.
+const ANSWER_RESOURCE_COLUMNS: &str = r#"id::BIGINT AS id,
+            corresponding_question::BIGINT AS question_id,
+            content, created_on AT TIME ZONE 'UTC' AS created_on"#;
+
+/// `text` as a case-insensitive `LIKE` pattern matching it anywhere.
+pub fn contains_pattern(text: &str) -> String {
+    let escaped: String = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// Record the number of rows a statement returned or touched on the current `store.*` span.
+fn record_rows(rows: usize) {
+    tracing::Span::current().record("db.rows", rows);
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DatabaseArgs {
+    /// Postgres connection URL. The password may instead come from the file named by
+    /// `PG_PASSWORDFILE`.
+    #[clap(
+        long,
+        env = "DATABASE_URL",
+        default_value = "postgres://localhost:5432/rustwebdev"
+    )]
+    pub database_url: String,
+    /// Connection attempts at startup before giving up.
+    #[clap(long, env = "DB_CONNECT_ATTEMPTS", default_value_t = 10)]
+    pub db_connect_attempts: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct Store {
     pub connection: Pool<Postgres>,
+    /// Changes committed through this store, for `/api/v1/events`.
+    pub events: Arc<EventLog>,
+    /// Whether new answers queue notification mails, which needs a mailer.
+    pub notify: bool,
 }
 
 impl Store {
@@ -59,7 +125,7 @@ impl Store {
     ) -> Result<(), sqlx::Error> {
         if let Some(tags) = tags {
             for tag in tags {
-                sqlx::query(r#"INSERT INTO tags (id, tag) VALUES ($1, $2);"#)
+                sqlx::query(r#"INSERT INTO tags (id, tag) VALUES ($1::INTEGER, $2);"#)
                     .bind(id.0.as_str())
                     .bind(tag)
                     .execute(&mut *tx)
@@ -69,24 +135,97 @@ impl Store {
         Ok(())
     }
 
-    pub async fn new(db_url: &str) -> Self {
-        let db_pool: Pool<Postgres> = match PgPoolOptions::new()
-            .max_connections(5)
-            .connect(db_url)
+    /// Tags of question `id`, sorted, for the events about it.
+    async fn select_tags(tx: &mut PgConnection, id: &str) -> Result<Vec<String>, sqlx::Error> {
+        let tags: Vec<(String,)> =
+            sqlx::query_as(r#"SELECT tag FROM tags WHERE id = $1::INTEGER ORDER BY tag;"#)
+                .bind(id)
+                .fetch_all(&mut *tx)
+                .await?;
+        Ok(tags.into_iter().map(|(tag,)| tag).collect())
+    }
+
+    /// Connect to the database, retrying with exponential backoff so the service can start
+    /// before Postgres is up. Gives up after `args.db_connect_attempts` failures.
+    pub async fn new(args: &DatabaseArgs) -> Result<Self, StoreErr> {
+        let mut options: PgConnectOptions = PgConnectOptions::from_str(&args.database_url)?;
+        if let Ok(password) = read_secret("PG_PASSWORDFILE").await {
+            options = options.password(&password);
+        }
+
+        let mut backoff: Duration = Duration::from_millis(500);
+        let mut attempt: u32 = 1;
+        loop {
+            match PgPoolOptions::new()
+                .max_connections(5)
+                .connect_with(options.clone())
+                .await
+            {
+                Ok(db_pool) => {
+                    return Ok(Store {
+                        connection: db_pool,
+                        events: Arc::default(),
+                        notify: false,
+                    })
+                }
+                Err(e) if attempt < args.db_connect_attempts => {
+                    tracing::warn!(
+                        "database connection attempt {} failed, retrying in {:?}: {}",
+                        attempt,
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Round trip to the database, bounded by `timeout`.
+    #[instrument(
+        name = "store.ping",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "ping", db.rows = Empty)
+    )]
+    pub async fn ping(&self, timeout: Duration) -> Result<(), StoreErr> {
+        match tokio::time::timeout(timeout, sqlx::query("SELECT 1;").execute(&self.connection))
             .await
         {
-            Ok(pool) => pool,
-            Err(e) => panic!("Couldn't establish DB connection:{}", e),
-        };
-
-        Store {
-            connection: db_pool,
+            Ok(result) => result.map(|_| ()).map_err(StoreErr::from),
+            Err(_) => Err(StoreErr::DatabaseQueryError(format!(
+                "no answer within {:?}",
+                timeout
+            ))),
         }
     }
 
+    /// Versions of the migrations shipped with this build that haven't been applied yet.
+    #[instrument(
+        name = "store.pending_migrations",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_applied_migrations", db.rows = Empty)
+    )]
+    pub async fn pending_migrations(&self) -> Result<Vec<i64>, StoreErr> {
+        let applied: Vec<PgRow> =
+            sqlx::query(r#"SELECT version FROM _sqlx_migrations WHERE success;"#)
+                .fetch_all(&self.connection)
+                .await?;
+        record_rows(applied.len());
+        let applied: HashSet<i64> = applied.iter().map(|row| row.get("version")).collect();
+        Ok(MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .map(|m| m.version)
+            .filter(|version| !applied.contains(version))
+            .collect())
+    }
+
     pub async fn to_question(&self, row: &PgRow) -> Result<Question, sqlx::Error> {
         let id: String = row.get("id");
-        let tags: Vec<_> = sqlx::query(r#"SELECT tag FROM tags WHERE id = $1"#)
+        let tags: Vec<_> = sqlx::query(r#"SELECT tag FROM tags WHERE id = $1::INTEGER"#)
             .bind(&id)
             .fetch_all(&self.connection)
             .await?;
@@ -100,29 +239,57 @@ impl Store {
         })
     }
 
+    #[instrument(
+        name = "store.get",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_question", db.rows = Empty)
+    )]
     pub async fn get<'a>(&self, index: &str) -> Result<Question, StoreErr> {
-        let row: PgRow = sqlx::query(r#"SELECT * FROM questions WHERE id = $1;"#)
-            .bind(index)
-            .fetch_one(&self.connection)
-            .await?;
+        let _timer = METRICS.store_timer("get");
+        let not_found = || StoreErr::QuestionNotFound(index.to_string());
+        let id: i32 = index.parse().map_err(|_| not_found())?;
+        let row: PgRow =
+            sqlx::query(r#"SELECT id::TEXT AS id, title, content FROM questions WHERE id = $1;"#)
+                .bind(id)
+                .fetch_optional(&self.connection)
+                .await?
+                .ok_or_else(not_found)?;
+        record_rows(1);
 
         let question: Question = self.to_question(&row).await?;
         Ok(question)
     }
 
+    #[instrument(
+        name = "store.get_random",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_random_question", db.rows = Empty)
+    )]
     pub async fn get_random(&self) -> Result<Question, StoreErr> {
-        let row: PgRow = sqlx::query(r#"SELECT * FROM questions ORDER BY RANDOM () LIMIT 1;"#)
-            .fetch_one(&self.connection)
-            .await?;
+        let _timer = METRICS.store_timer("get_random");
+        let row: PgRow = sqlx::query(
+            r#"SELECT id::TEXT AS id, title, content FROM questions ORDER BY RANDOM () LIMIT 1;"#,
+        )
+        .fetch_one(&self.connection)
+        .await?;
+        record_rows(1);
 
         let question: Question = self.to_question(&row).await?;
         Ok(question)
     }
 
+    #[instrument(
+        name = "store.get_questions",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_questions", db.rows = Empty)
+    )]
     pub async fn get_questions<'a>(&self) -> Result<Vec<Question>, StoreErr> {
-        let rows = sqlx::query(r#"SELECT * FROM jokes;"#)
-            .fetch_all(&self.connection)
-            .await?;
+        let _timer = METRICS.store_timer("get_questions");
+        let rows =
+            sqlx::query(r#"SELECT id::TEXT AS id, title, content FROM questions ORDER BY id;"#)
+                .fetch_all(&self.connection)
+                .await?;
+        record_rows(rows.len());
         let mut questions: Vec<Question> = Vec::with_capacity(rows.len());
         for q in rows.iter() {
             questions.push(self.to_question(q).await?);
@@ -132,46 +299,95 @@ impl Store {
 
     // Define an async handler function for Axum
 
+    #[instrument(
+        name = "store.add_question",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "insert_question", db.rows = Empty)
+    )]
+    /// Add `new_question` under the id the database assigns, ignoring the one it carries, and
+    /// return it with that id. The event is published only once the question is committed.
+    /// `author`, the subject of the user asking, if known, watches it for answers.
     pub async fn add_question(
         &self,
         new_question: Question,
-        question_id: i32,
-    ) -> Result<(), StoreErr> {
+        author: Option<&str>,
+    ) -> Result<Question, StoreErr> {
+        let _timer = METRICS.store_timer("add_question");
         let mut tx: sqlx::Transaction<'_, Postgres> = Pool::begin(&self.connection).await?;
-        sqlx::query("INSERT INTO questions (title, content, tags) VALUES ($1, $2, $3)")
-            .bind(question_id)
-            .bind(new_question.title)
-            .bind(new_question.content)
-            .execute(&mut *tx)
-            .await;
-        Self::insert_tags(&mut tx, &new_question.id, &new_question.tags).await?;
-        Ok(tx.commit().await?)
+        let (id,): (String,) = sqlx::query_as(
+            r#"INSERT INTO questions (title, content, author)
+        VALUES ($1, $2, $3)
+        RETURNING id::TEXT;"#,
+        )
+        .bind(&new_question.title)
+        .bind(&new_question.content)
+        .bind(author)
+        .fetch_one(&mut *tx)
+        .await?;
+        record_rows(1);
+        let question: Question = Question {
+            id: QuestionId(id),
+            ..new_question
+        };
+        Self::insert_tags(&mut tx, &question.id, &question.tags).await?;
+        if let Some(author) = author {
+            notifications::watch(&mut *tx, &question.id.0, author, true).await?;
+        }
+        let event: Event = Event::question(EventKind::QuestionCreated, &question);
+        webhooks::queue(&mut tx, &event).await?;
+        tx.commit().await?;
+        self.events.publish(event);
+        Ok(question)
     }
 
+    #[instrument(
+        name = "store.delete_question",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "delete_question", db.rows = Empty)
+    )]
+    /// Delete a question with its answers; its tags go with it.
     pub async fn delete_question(&mut self, index: &str) -> Result<(), StoreErr> {
+        let _timer = METRICS.store_timer("delete_question");
+        let not_found = || StoreErr::QuestionNotFound(index.to_string());
+        let id: i32 = index.parse().map_err(|_| not_found())?;
         let mut tx: sqlx::Transaction<'_, Postgres> = Pool::begin(&self.connection).await?;
-        sqlx::query(r#"DELETE FROM tags WHERE id = $1;"#)
-            .bind(index)
+        // Read before the cascade removes them, for subscribers to the question's tags.
+        let tags: Vec<String> = Self::select_tags(&mut tx, &id.to_string()).await?;
+        sqlx::query(r#"DELETE FROM answers WHERE corresponding_question = $1;"#)
+            .bind(id)
             .execute(&mut *tx)
             .await?;
         let result: Vec<PgRow> =
             sqlx::query(r#"DELETE FROM questions WHERE id = $1 RETURNING questions.id;"#)
-                .bind(index)
+                .bind(id)
                 .fetch_all(&mut *tx)
                 .await?;
-        #[allow(clippy::len_zero)]
-        if result.len() == 0 {
-            return Err(StoreErr::QuestionNotFound(index.to_string()));
+        record_rows(result.len());
+        if result.is_empty() {
+            return Err(not_found());
         }
-        Ok(tx.commit().await?)
+        let event: Event = Event::question_deleted(index, tags);
+        webhooks::queue(&mut tx, &event).await?;
+        tx.commit().await?;
+        self.events.publish(event);
+        Ok(())
     }
 
+    #[instrument(
+        name = "store.update_question",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "update_question", db.rows = Empty)
+    )]
+    /// Replace the title, content and tags of the question with id `index`, whatever id
+    /// `question` carries, and return it as stored.
     pub async fn update_question(
         &mut self,
         index: &str,
         question: Question,
-        question_id: i32,
-    ) -> Result<(), StoreErr> {
+    ) -> Result<Question, StoreErr> {
+        let _timer = METRICS.store_timer("update_question");
+        let not_found = || StoreErr::QuestionNotFound(index.to_string());
+        let question_id: i32 = index.parse().map_err(|_| not_found())?;
         let mut tx: sqlx::Transaction<'_, Postgres> = Pool::begin(&self.connection).await?;
         let q: sqlx::query::Query<Postgres, sqlx::postgres::PgArguments> = sqlx::query(
             r#"UPDATE questions
@@ -185,26 +401,391 @@ impl Store {
             .bind(&question.content)
             .fetch_all(&mut *tx)
             .await?;
+        record_rows(result.len());
         if result.is_empty() {
-            return Err(StoreErr::QuestionNotFound(index.to_string()));
+            return Err(not_found());
         }
+        let question: Question = Question {
+            id: QuestionId(question_id.to_string()),
+            ..question
+        };
         sqlx::query(r#"DELETE FROM tags WHERE id = $1;"#)
-            .bind(index)
+            .bind(question_id)
             .execute(&mut *tx)
             .await?;
         Self::insert_tags(&mut tx, &question.id, &question.tags).await?;
-        Ok(tx.commit().await?)
+        let event: Event = Event::question(EventKind::QuestionUpdated, &question);
+        webhooks::queue(&mut tx, &event).await?;
+        tx.commit().await?;
+        self.events.publish(event);
+        Ok(question)
+    }
+
+    /// Questions with `tag`, if given, and containing `search` in their title or content, if
+    /// given.
+    #[instrument(
+        name = "store.count_questions",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "count_questions", db.rows = Empty)
+    )]
+    pub async fn count_questions(
+        &self,
+        tag: Option<&str>,
+        search: Option<&str>,
+    ) -> Result<i64, StoreErr> {
+        let _timer = METRICS.store_timer("count_questions");
+        let (total,): (i64,) = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM questions q {};",
+            QUESTION_FILTER
+        ))
+        .bind(tag)
+        .bind(search.map(contains_pattern))
+        .fetch_one(&self.connection)
+        .await?;
+        record_rows(1);
+        Ok(total)
+    }
+
+    /// A page of the questions [`count_questions`](Self::count_questions) counts, in id order.
+    #[instrument(
+        name = "store.list_questions",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_questions_page", db.rows = Empty)
+    )]
+    pub async fn list_questions(
+        &self,
+        tag: Option<&str>,
+        search: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<QuestionResource>, StoreErr> {
+        let _timer = METRICS.store_timer("list_questions");
+        let questions: Vec<QuestionResource> = sqlx::query_as(&format!(
+            "{} {} ORDER BY q.id LIMIT $3 OFFSET $4;",
+            SELECT_QUESTION_RESOURCE, QUESTION_FILTER
+        ))
+        .bind(tag)
+        .bind(search.map(contains_pattern))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(questions.len());
+        Ok(questions)
+    }
+
+    /// Up to `limit` of the questions [`count_questions`](Self::count_questions) counts with an
+    /// id above `after`, in id order. Walking them this way doesn't read again, as `OFFSET`
+    /// does, every question already sent.
+    #[instrument(
+        name = "store.list_questions_after",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_questions_after", db.rows = Empty)
+    )]
+    pub async fn list_questions_after(
+        &self,
+        tag: Option<&str>,
+        search: Option<&str>,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<QuestionResource>, StoreErr> {
+        let _timer = METRICS.store_timer("list_questions_after");
+        let questions: Vec<QuestionResource> = sqlx::query_as(&format!(
+            "{} {} AND q.id > $3 ORDER BY q.id LIMIT $4;",
+            SELECT_QUESTION_RESOURCE, QUESTION_FILTER
+        ))
+        .bind(tag)
+        .bind(search.map(contains_pattern))
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(questions.len());
+        Ok(questions)
+    }
+
+    #[instrument(
+        name = "store.question_resource",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_question_resource", db.rows = Empty)
+    )]
+    pub async fn question_resource(&self, id: i64) -> Result<QuestionResource, StoreErr> {
+        let _timer = METRICS.store_timer("question_resource");
+        let question: QuestionResource =
+            sqlx::query_as(&format!("{} WHERE q.id = $1;", SELECT_QUESTION_RESOURCE))
+                .bind(id)
+                .fetch_optional(&self.connection)
+                .await?
+                .ok_or_else(|| StoreErr::QuestionNotFound(id.to_string()))?;
+        record_rows(1);
+        Ok(question)
+    }
+
+    #[instrument(
+        name = "store.count_answers",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "count_answers", db.rows = Empty)
+    )]
+    pub async fn count_answers(&self, question_id: i64) -> Result<i64, StoreErr> {
+        let _timer = METRICS.store_timer("count_answers");
+        let (total,): (i64,) =
+            sqlx::query_as(r#"SELECT COUNT(*) FROM answers WHERE corresponding_question = $1;"#)
+                .bind(question_id)
+                .fetch_one(&self.connection)
+                .await?;
+        record_rows(1);
+        Ok(total)
+    }
+
+    /// A page of a question's answers, oldest first.
+    #[instrument(
+        name = "store.list_answers",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_answers_page", db.rows = Empty)
+    )]
+    pub async fn list_answers(
+        &self,
+        question_id: i64,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<AnswerResource>, StoreErr> {
+        let _timer = METRICS.store_timer("list_answers");
+        let answers: Vec<AnswerResource> = sqlx::query_as(&format!(
+            "SELECT {} FROM answers WHERE corresponding_question = $1
+            ORDER BY id LIMIT $2 OFFSET $3;",
+            ANSWER_RESOURCE_COLUMNS
+        ))
+        .bind(question_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(answers.len());
+        Ok(answers)
+    }
+
+    /// Up to `limit` of a question's answers with an id above `after`, oldest first.
+    #[instrument(
+        name = "store.list_answers_after",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_answers_after", db.rows = Empty)
+    )]
+    pub async fn list_answers_after(
+        &self,
+        question_id: i64,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<AnswerResource>, StoreErr> {
+        let _timer = METRICS.store_timer("list_answers_after");
+        let answers: Vec<AnswerResource> = sqlx::query_as(&format!(
+            "SELECT {} FROM answers WHERE corresponding_question = $1 AND id > $2
+            ORDER BY id LIMIT $3;",
+            ANSWER_RESOURCE_COLUMNS
+        ))
+        .bind(question_id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(answers.len());
+        Ok(answers)
+    }
+
+    /// Sorted tags of the questions with `ids`, by question id.
+    #[instrument(
+        name = "store.tags_by_question",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_tags", db.rows = Empty)
+    )]
+    pub async fn tags_by_question(
+        &self,
+        ids: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, StoreErr> {
+        let _timer = METRICS.store_timer("tags_by_question");
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"SELECT id::TEXT, tag FROM tags WHERE id::TEXT = ANY($1) ORDER BY id, tag;"#,
+        )
+        .bind(ids)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(rows.len());
+        let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+        for (id, tag) in rows {
+            tags.entry(id).or_default().push(tag);
+        }
+        Ok(tags)
+    }
+
+    /// Answers to the questions with `ids`, oldest first.
+    #[instrument(
+        name = "store.answers_by_question",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_answers", db.rows = Empty)
+    )]
+    pub async fn answers_by_question(&self, ids: &[String]) -> Result<Vec<Answer>, StoreErr> {
+        let _timer = METRICS.store_timer("answers_by_question");
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            r#"SELECT id::TEXT, content, corresponding_question::TEXT
+        FROM answers
+        WHERE corresponding_question::TEXT = ANY($1)
+        ORDER BY id;"#,
+        )
+        .bind(ids)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(rows.len());
+        Ok(rows
+            .into_iter()
+            .map(|(id, content, question_id)| Answer {
+                id: AnswerId(id),
+                content,
+                question_id: QuestionId(question_id),
+            })
+            .collect())
+    }
+
+    /// Names of the authors of the questions with `ids`, by question id, for those with one.
+    #[instrument(
+        name = "store.question_authors",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_authors", db.rows = Empty)
+    )]
+    pub async fn question_authors(
+        &self,
+        ids: &[String],
+    ) -> Result<HashMap<String, String>, StoreErr> {
+        let _timer = METRICS.store_timer("question_authors");
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"SELECT id::TEXT, author FROM questions
+        WHERE author IS NOT NULL AND id::TEXT = ANY($1);"#,
+        )
+        .bind(ids)
+        .fetch_all(&self.connection)
+        .await?;
+        record_rows(rows.len());
+        Ok(rows
+            .into_iter()
+            .map(|(id, author)| (id, subject_name(&author).to_string()))
+            .collect())
+    }
+
+    /// Every tag in use, with the number of questions carrying it.
+    #[instrument(
+        name = "store.tag_counts",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "count_tags", db.rows = Empty)
+    )]
+    pub async fn tag_counts(&self) -> Result<Vec<(String, i64)>, StoreErr> {
+        let _timer = METRICS.store_timer("tag_counts");
+        let tags: Vec<(String, i64)> =
+            sqlx::query_as(r#"SELECT tag, COUNT(*) FROM tags GROUP BY tag ORDER BY tag;"#)
+                .fetch_all(&self.connection)
+                .await?;
+        record_rows(tags.len());
+        Ok(tags)
     }
 
-    pub async fn add_answer(&self, new_answer: Answer) -> Result<(), sqlx::Error> {
+    #[instrument(
+        name = "store.answer_resource",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "select_answer_resource", db.rows = Empty)
+    )]
+    pub async fn answer_resource(&self, id: i64) -> Result<AnswerResource, StoreErr> {
+        let _timer = METRICS.store_timer("answer_resource");
+        let answer: AnswerResource = sqlx::query_as(&format!(
+            "SELECT {} FROM answers WHERE id = $1;",
+            ANSWER_RESOURCE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.connection)
+        .await?
+        .ok_or_else(|| StoreErr::NotFound(format!("no answer with id {}", id)))?;
+        record_rows(1);
+        Ok(answer)
+    }
+
+    #[instrument(
+        name = "store.add_answer",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "insert_answer", db.rows = Empty)
+    )]
+    pub async fn add_answer(
+        &self,
+        question_id: &QuestionId,
+        content: &str,
+    ) -> Result<Answer, StoreErr> {
+        let _timer = METRICS.store_timer("add_answer");
         let mut tx: sqlx::Transaction<'_, Postgres> = Pool::begin(&self.connection).await?;
-        sqlx::query("INSERT INTO questions (title, content, tags) VALUES ($1, $2, $3)")
-            .bind(new_answer.question_id.0)
-            .bind(new_answer.content)
-            .bind(new_answer.id.0)
-            .execute(&mut *tx)
-            .await;
-        tx.commit().await
+        let inserted: Result<(String,), sqlx::Error> = sqlx::query_as(
+            r#"INSERT INTO answers (content, corresponding_question)
+        VALUES ($1, $2::INTEGER)
+        RETURNING id::TEXT;"#,
+        )
+        .bind(content)
+        .bind(&question_id.0)
+        .fetch_one(&mut *tx)
+        .await;
+        let (id,) = match inserted {
+            Ok(row) => row,
+            // foreign_key_violation, invalid_text_representation: no such question.
+            Err(SqlxError::Database(db))
+                if matches!(db.code().as_deref(), Some("23503") | Some("22P02")) =>
+            {
+                return Err(StoreErr::QuestionNotFound(question_id.0.clone()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        record_rows(1);
+        let answer: Answer = Answer {
+            id: AnswerId(id),
+            content: content.to_string(),
+            question_id: question_id.clone(),
+        };
+        let tags: Vec<String> = Self::select_tags(&mut tx, &question_id.0).await?;
+        if self.notify {
+            notifications::queue(&mut tx, &answer).await?;
+        }
+        let event: Event = Event::answer_created(&answer, tags);
+        webhooks::queue(&mut tx, &event).await?;
+        tx.commit().await?;
+        self.events.publish(event);
+        Ok(answer)
+    }
+
+    /// Find or create the local user for an identity asserted by an OpenID Connect
+    /// provider. The email and name are refreshed from the provider on every login.
+    #[instrument(
+        name = "store.upsert_oidc_user",
+        skip_all,
+        fields(db.system = "postgresql", db.statement.name = "upsert_oidc_user", db.rows = Empty)
+    )]
+    pub async fn upsert_oidc_user(
+        &self,
+        issuer: &str,
+        subject: &str,
+        email: &str,
+        full_name: &str,
+    ) -> Result<User, StoreErr> {
+        let _timer = METRICS.store_timer("upsert_oidc_user");
+        let row: PgRow = sqlx::query(
+            r#"INSERT INTO users (oidc_issuer, oidc_subject, email, full_name)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (oidc_issuer, oidc_subject)
+        DO UPDATE SET email = EXCLUDED.email, full_name = EXCLUDED.full_name
+        RETURNING id, email, full_name;"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(email)
+        .bind(full_name)
+        .fetch_one(&self.connection)
+        .await?;
+        record_rows(1);
+        Ok(User {
+            id: UserId(row.get("id")),
+            email: row.get("email"),
+            full_name: row.get("full_name"),
+        })
     }
 }
 
@@ -340,3 +921,63 @@ impl Store {
         }
     }
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Executor;
+
+    /// Run the up migrations with a version in `versions`.
+    async fn migrate(pool: &PgPool, versions: impl std::ops::RangeBounds<i64>) {
+        for migration in MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration() && versions.contains(&m.version))
+        {
+            pool.execute(&*migration.sql).await.unwrap();
+        }
+    }
+
+    /// 0007 keeps the rows of a tags table made before it, adding the keys it lacked.
+    #[sqlx::test(migrations = false)]
+    async fn tags_migration_keeps_an_existing_table(pool: PgPool) {
+        migrate(&pool, ..7).await;
+        pool.execute(
+            r#"CREATE TABLE tags (id integer, tag TEXT);
+            INSERT INTO questions (id, title, content, tags)
+                VALUES (1, 'How?', 'Please help!', '{async}'), (2, 'Why?', 'Tell me.', NULL);
+            INSERT INTO tags (id, tag)
+                VALUES (1, 'rust'), (1, 'rust'), (2, 'go'), (3, 'gone'), (NULL, 'none');"#,
+        )
+        .await
+        .unwrap();
+        // Again, as on a database where the table already has them.
+        for _ in 0..2 {
+            migrate(&pool, 7..=7).await;
+        }
+
+        let tags: Vec<(i32, String)> =
+            sqlx::query_as(r#"SELECT id, tag FROM tags ORDER BY id, tag;"#)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let expected: Vec<(i32, String)> = vec![
+            (1, "async".to_string()),
+            (1, "rust".to_string()),
+            (2, "go".to_string()),
+        ];
+        assert_eq!(tags, expected);
+        let duplicate = sqlx::query(r#"INSERT INTO tags (id, tag) VALUES (1, 'rust');"#)
+            .execute(&pool)
+            .await;
+        assert!(duplicate.is_err());
+        sqlx::query(r#"DELETE FROM questions WHERE id = 1;"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (left,): (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM tags;"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 1);
+    }
+}
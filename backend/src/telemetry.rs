@@ -0,0 +1,149 @@
+//! # Telemetry
+//!
+//! Sets up `tracing` for the process: log output filtered by `RUST_LOG`, as text or as one JSON
+//! object per line with `--log-format json`, and optionally export of spans to an OpenTelemetry
+//! collector over OTLP/gRPC when `--otlp-endpoint` is given.
+//!
+//! Each request's span carries its `request_id` (see [`crate::requestid`]), so
+//! `grep <request id>` over JSON logs finds every line written while handling it.
+//!
+//! Incoming requests continue the trace named in a W3C `traceparent` header, so spans from
+//! callers, this service and its database calls (see the `store.*` spans) end up in one trace.
+//! A local collector such as `docker run -p 4317:4317 -p 16686:16686 jaegertracing/all-in-one`
+//! with `--otlp-endpoint http://localhost:4317` is enough to try it out.
+
+use crate::*;
+
+use axum::body::Body;
+use http::Request;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, including the fields of the enclosing spans.
+    Json,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TelemetryArgs {
+    #[clap(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// OTLP/gRPC collector endpoint, e.g. `http://localhost:4317`. No spans are exported
+    /// when unset.
+    #[clap(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` reported with exported spans.
+    #[clap(long, env = "OTEL_SERVICE_NAME", default_value = "backend")]
+    pub otel_service_name: String,
+    /// Fraction of new traces to sample, from 0.0 to 1.0. Traces started upstream follow the
+    /// caller's sampling decision.
+    #[clap(long, env = "OTEL_TRACES_SAMPLER_ARG", default_value_t = 1.0)]
+    pub otel_sampling_ratio: f64,
+}
+
+/// Install the global `tracing` subscriber. Returns the tracer provider when spans are being
+/// exported, so it can be flushed before the process exits.
+pub fn init(args: &TelemetryArgs) -> Option<TracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let exporter = args.otlp_endpoint.as_ref().map(|endpoint| {
+        opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+    });
+    // A bad exporter leaves spans unexported; it is reported once logging is up.
+    let (provider, exporter_error): (Option<TracerProvider>, Option<_>) = match exporter {
+        Some(Ok(exporter)) => {
+            let provider: TracerProvider = TracerProvider::builder()
+                .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    args.otel_sampling_ratio,
+                ))))
+                .with_resource(Resource::new([KeyValue::new(
+                    "service.name",
+                    args.otel_service_name.clone(),
+                )]))
+                .build();
+            (Some(provider), None)
+        }
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let otel_layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("backend")));
+
+    let (text_layer, json_layer) = match args.log_format {
+        LogFormat::Text => (Some(tracing_subscriber::fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+        ),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "question=debug,info".into()),
+        )
+        .with(text_layer)
+        .with(json_layer)
+        .with(otel_layer)
+        .init();
+    if let Some(e) = exporter_error {
+        tracing::error!("otlp exporter: {}; spans are not exported", e);
+    }
+    provider
+}
+
+/// Read propagation headers straight out of a request's header map.
+struct HeaderExtractor<'a>(&'a http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(http::HeaderName::as_str).collect()
+    }
+}
+
+/// Root span for a request, tagged with its request id and parented to the caller's trace
+/// when the request carries a `traceparent` header.
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    let request_id: &str = request
+        .headers()
+        .get(&requestid::X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let span: Span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        request_id,
+        http.request.method = %request.method(),
+        url.path = %request.uri().path(),
+        version = ?request.version(),
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+    span
+}
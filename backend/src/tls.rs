@@ -0,0 +1,196 @@
+//! # TLS
+//!
+//! With `--tls-cert-file` and `--tls-key-file` the server speaks HTTPS itself, using rustls.
+//!
+//! * The PEM files are checked for changes every [`RELOAD_INTERVAL`] and reloaded in place, so
+//!   renewed certificates are picked up without a restart. A bad pair is logged and the
+//!   previous one stays in use.
+//! * `--http-redirect-addr` starts a plain HTTP listener answering every request with a
+//!   `308 Permanent Redirect` to the same path over HTTPS.
+//! * Responses carry `Strict-Transport-Security` with `--hsts-max-age`, covering subdomains
+//!   only with `--hsts-include-subdomains`.
+
+use crate::error::Problem;
+use crate::shutdown::Shutdown;
+use crate::*;
+
+use axum::extract::Request;
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// How often the certificate and key files are checked for changes.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct TlsArgs {
+    /// PEM certificate chain. Enables HTTPS together with `--tls-key-file`.
+    #[clap(long, env = "TLS_CERT_FILE", requires = "tls_key_file")]
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM private key for `--tls-cert-file`.
+    #[clap(long, env = "TLS_KEY_FILE", requires = "tls_cert_file")]
+    pub tls_key_file: Option<PathBuf>,
+    /// Plain HTTP address redirecting every request to HTTPS.
+    #[clap(long, env = "HTTP_REDIRECT_ADDR", requires = "tls_cert_file")]
+    pub http_redirect_addr: Option<String>,
+    /// `max-age` of the `Strict-Transport-Security` header sent over HTTPS, in seconds.
+    #[clap(long, env = "HSTS_MAX_AGE", default_value_t = 31_536_000)]
+    pub hsts_max_age: u64,
+    /// Add `includeSubDomains` to `Strict-Transport-Security`. Only for domains whose every
+    /// subdomain is served over HTTPS, as browsers will refuse plain HTTP on all of them.
+    #[clap(long, env = "HSTS_INCLUDE_SUBDOMAINS")]
+    pub hsts_include_subdomains: bool,
+}
+
+impl TlsArgs {
+    fn files(&self) -> Option<(&Path, &Path)> {
+        Some((
+            self.tls_cert_file.as_deref()?,
+            self.tls_key_file.as_deref()?,
+        ))
+    }
+}
+
+/// Load the configured certificate and start watching it for changes. `None` when HTTPS
+/// isn't configured.
+pub async fn load(args: &TlsArgs) -> Option<std::io::Result<RustlsConfig>> {
+    let (cert, key) = args.files()?;
+    let config: RustlsConfig = match RustlsConfig::from_pem_file(cert, key).await {
+        Ok(config) => config,
+        Err(e) => return Some(Err(e)),
+    };
+    tokio::spawn(reload(
+        config.clone(),
+        cert.to_path_buf(),
+        key.to_path_buf(),
+    ));
+    Some(Ok(config))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reload `config` whenever the certificate or key file changes.
+async fn reload(config: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    let mut seen: (Option<SystemTime>, Option<SystemTime>) = (modified(&cert), modified(&key));
+    let mut interval: tokio::time::Interval = tokio::time::interval(RELOAD_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let current: (Option<SystemTime>, Option<SystemTime>) = (modified(&cert), modified(&key));
+        if current == seen {
+            continue;
+        }
+        match config.reload_from_pem_file(&cert, &key).await {
+            Ok(()) => {
+                tracing::info!("reloaded TLS certificate from {}", cert.display());
+                seen = current;
+            }
+            // Retried on the next tick; a renewal may have written only one of the files yet.
+            Err(e) => tracing::error!("reloading TLS certificate: {}", e),
+        }
+    }
+}
+
+/// Layer adding `Strict-Transport-Security` to responses that don't set it themselves.
+pub fn hsts(args: &TlsArgs) -> SetResponseHeaderLayer<HeaderValue> {
+    let mut value: String = format!("max-age={}", args.hsts_max_age);
+    if args.hsts_include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    SetResponseHeaderLayer::if_not_present(
+        http::header::STRICT_TRANSPORT_SECURITY,
+        HeaderValue::from_str(&value).unwrap(),
+    )
+}
+
+/// `Host` header value without its port, keeping bracketed IPv6 literals intact.
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.ends_with(']') => name,
+        _ => host,
+    }
+}
+
+/// Serve redirects to `https://<host>:<https_port>` on `addr` until `shutdown`.
+pub async fn redirect_http(
+    addr: String,
+    https_port: u16,
+    shutdown: Shutdown,
+) -> std::io::Result<()> {
+    let redirect = move |request: Request| async move {
+        let host: Option<&str> = request
+            .headers()
+            .get(http::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(strip_port);
+        let Some(host) = host else {
+            return Problem::from_status(StatusCode::BAD_REQUEST)
+                .with_detail("missing Host header")
+                .into_response();
+        };
+        let path: &str = request.uri().path_and_query().map_or("/", |pq| pq.as_str());
+        let location: String = if https_port == 443 {
+            format!("https://{}{}", host, path)
+        } else {
+            format!("https://{}:{}{}", host, https_port, path)
+        };
+        Redirect::permanent(&location).into_response()
+    };
+    let app: Router = Router::new().fallback(redirect);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    tracing::debug!("redirecting {} to https", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[test]
+    fn strip_port_keeps_ipv6_literals() {
+        let cases: [(&str, &str); 6] = [
+            ("example.org", "example.org"),
+            ("example.org:8080", "example.org"),
+            ("127.0.0.1:80", "127.0.0.1"),
+            ("[::1]", "[::1]"),
+            ("[::1]:8080", "[::1]"),
+            ("[2001:db8::1]:443", "[2001:db8::1]"),
+        ];
+        for (host, stripped) in cases {
+            assert_eq!(strip_port(host), stripped, "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn hsts_includes_subdomains_only_when_asked() {
+        for (include, value) in [
+            (false, "max-age=60"),
+            (true, "max-age=60; includeSubDomains"),
+        ] {
+            let args: TlsArgs = TlsArgs {
+                tls_cert_file: None,
+                tls_key_file: None,
+                http_redirect_addr: None,
+                hsts_max_age: 60,
+                hsts_include_subdomains: include,
+            };
+            let app: Router = Router::new()
+                .route("/", get(|| async { "" }))
+                .layer(hsts(&args));
+            let request: Request = Request::get("/").body(Body::empty()).unwrap();
+            let response: Response = app.oneshot(request).await.unwrap();
+            assert_eq!(
+                response.headers()[http::header::STRICT_TRANSPORT_SECURITY],
+                value
+            );
+        }
+    }
+}
@@ -1,13 +1,3 @@
-use serde::{Deserialize, Serialize};
+//! The answer types live in the `question-api` crate, shared with the frontend.
 
-use crate::types::question::QuestionId;
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Answer {
-    pub id: AnswerId,
-    pub content: String,
-    pub question_id: QuestionId,
-}
-
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
-pub struct AnswerId(pub String);
+pub use question_api::answer::{Answer, AnswerId, NewAnswer};
@@ -1,3 +1,4 @@
 pub mod answer;
 pub mod pagination;
 pub mod question;
+pub mod user;
@@ -1,12 +1,3 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct Question {
-    pub id: QuestionId,
-    pub title: String,
-    pub content: String,
-    pub tags: Option<HashSet<String>>,
-}
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
-pub struct QuestionId(pub String);
+//! The question types live in the `question-api` crate, shared with the frontend.
 
+pub use question_api::question::{format_tags, Question, QuestionId};
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct User {
+    pub id: UserId,
+    pub email: String,
+    pub full_name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserId(pub i32);
+
+impl User {
+    /// The `sub` claim used for tokens issued to this user.
+    pub fn subject(&self) -> String {
+        format!("{} <{}>", self.full_name, self.email)
+    }
+}
@@ -0,0 +1,684 @@
+//! # API v2
+//!
+//! `/api/v2` serves the same questions and answers as `/api/v1` with stable response shapes:
+//!
+//! * Every success body is an envelope: `{"data": ...}` for one resource, and for lists also
+//!   `pagination` (`limit`, `offset`, `total`) and `links` to this, the next and the previous
+//!   page.
+//! * Ids are the numbers the database assigns, never chosen by the client, and timestamps are
+//!   RFC 3339 in UTC.
+//! * Creating or updating a resource returns it, with its URL in `Location`; deleting returns
+//!   204.
+//! * Errors are the same problem documents as v1.
+//!
+//! `/api/v1` keeps working, but its responses carry `Deprecation` and `Sunset` headers and a
+//! `Link` to its successor, from [`deprecated`].
+//!
+//! The envelopes and resources are defined in `question-api`, shared with the frontend.
+
+use crate::appstate::HandlerAppState;
+use crate::auth::Claims;
+use crate::error::{Problem, StoreErr};
+use crate::types::answer::{Answer, NewAnswer};
+use crate::types::question::{Question, QuestionId};
+use crate::validation::ValidJson;
+use crate::*;
+use question_api::page::{AnswerData, AnswerPage, QuestionData, QuestionPage};
+use question_api::{
+    AnswerResource, Data, Links, Page, Pagination, QuestionInput, QuestionResource,
+};
+
+use axum::extract::{Query, Request};
+use axum::middleware::Next;
+use chrono::{DateTime, Utc};
+use http::header::{HeaderName, LINK, LOCATION};
+use utoipa::IntoParams;
+
+/// Where this version is mounted.
+pub const PREFIX: &str = "/api/v2";
+
+/// Items a page holds unless asked for fewer.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Items a page holds at most.
+const MAX_LIMIT: i64 = 100;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct VersionArgs {
+    /// When `/api/v1` was deprecated, for its `Deprecation` header (RFC 3339).
+    #[clap(long, env = "V1_DEPRECATED", default_value = "2026-10-19T00:00:00Z")]
+    pub v1_deprecated: DateTime<Utc>,
+    /// When `/api/v1` will stop working, for its `Sunset` header (RFC 3339).
+    #[clap(long, env = "V1_SUNSET", default_value = "2027-04-19T00:00:00Z")]
+    pub v1_sunset: DateTime<Utc>,
+}
+
+/// Headers marking every `/api/v1` response as deprecated.
+#[derive(Debug, Clone)]
+pub struct Deprecation {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Deprecation {
+    pub fn new(args: &VersionArgs) -> Self {
+        let value = |value: String| HeaderValue::from_str(&value).expect("ASCII header value");
+        Self {
+            headers: vec![
+                // RFC 9745: a structured-field date, in Unix seconds.
+                (
+                    HeaderName::from_static("deprecation"),
+                    value(format!("@{}", args.v1_deprecated.timestamp())),
+                ),
+                // RFC 8594: an HTTP date.
+                (
+                    HeaderName::from_static("sunset"),
+                    value(
+                        args.v1_sunset
+                            .format("%a, %d %b %Y %H:%M:%S GMT")
+                            .to_string(),
+                    ),
+                ),
+                (
+                    LINK,
+                    value(format!("<{}>; rel=\"successor-version\"", PREFIX)),
+                ),
+            ],
+        }
+    }
+}
+
+/// Add the [`Deprecation`] headers to the response.
+pub async fn deprecated(
+    State(deprecation): State<Arc<Deprecation>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response: Response = next.run(request).await;
+    for (name, value) in &deprecation.headers {
+        response.headers_mut().append(name, value.clone());
+    }
+    response
+}
+
+/// Which part of a list to return.
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct Window {
+    /// Items to return, at most 100.
+    #[param(example = 20)]
+    pub limit: Option<i64>,
+    /// Items to skip.
+    #[param(example = 0)]
+    pub offset: Option<i64>,
+}
+
+impl Window {
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    /// This page of a list of `total` items at `path`, whose other query parameters are
+    /// `filter`.
+    fn page<T>(&self, data: Vec<T>, total: i64, path: &str, filter: &[(&str, &str)]) -> Page<T> {
+        let (limit, offset): (i64, i64) = (self.limit(), self.offset());
+        let link = |offset: i64| {
+            let mut query: Vec<(&str, String)> = filter
+                .iter()
+                .map(|(name, value)| (*name, value.to_string()))
+                .collect();
+            query.push(("limit", limit.to_string()));
+            query.push(("offset", offset.to_string()));
+            format!(
+                "{}?{}",
+                path,
+                serde_urlencoded::to_string(query).unwrap_or_default()
+            )
+        };
+        Page {
+            data,
+            pagination: Pagination {
+                limit,
+                offset,
+                total,
+            },
+            links: Links {
+                this: link(offset),
+                // Saturating, as the offset is whatever the client asked for.
+                next: (offset.saturating_add(limit) < total).then(|| link(offset + limit)),
+                prev: (offset > 0).then(|| link((offset - limit).max(0))),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct QuestionFilter {
+    /// Only questions with this tag.
+    pub tag: Option<String>,
+}
+
+/// `resource` with its URL in `Location`.
+fn located<T: Serialize>(status: StatusCode, location: String, resource: T) -> Response {
+    (
+        status,
+        [(LOCATION, location)],
+        Json(Data { data: resource }),
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/questions",
+    tag = "v2",
+    params(Window, QuestionFilter),
+    responses(
+        (status = 200, description = "A page of questions, in id order", body = QuestionPage),
+    )
+)]
+pub async fn list_questions(
+    State(appstate): HandlerAppState,
+    Query(window): Query<Window>,
+    Query(filter): Query<QuestionFilter>,
+) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let tag: Option<&str> = filter.tag.as_deref();
+    let listed: Result<(i64, Vec<QuestionResource>), StoreErr> = async {
+        let total: i64 = store.count_questions(tag, None).await?;
+        let questions: Vec<QuestionResource> = store
+            .list_questions(tag, None, window.limit(), window.offset())
+            .await?;
+        Ok((total, questions))
+    }
+    .await;
+    match listed {
+        Ok((total, questions)) => {
+            let tag: Vec<(&str, &str)> = tag.iter().map(|tag| ("tag", *tag)).collect();
+            let path: String = format!("{}/questions", PREFIX);
+            Json(window.page(questions, total, &path, &tag)).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/questions/{id}",
+    tag = "v2",
+    params(("id" = i64, Path, description = "Question id")),
+    responses(
+        (status = 200, description = "The question", body = QuestionData),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_question(State(appstate): HandlerAppState, Path(id): Path<i64>) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    match store.question_resource(id).await {
+        Ok(question) => Json(Data { data: question }).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/questions",
+    tag = "v2",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content = QuestionInput, description = "Question to add"),
+    responses(
+        (status = 201, description = "Added question; its URL is in `Location`",
+            body = QuestionData),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid question", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn create_question(
+    claims: Claims,
+    State(appstate): HandlerAppState,
+    ValidJson(input): ValidJson<QuestionInput>,
+) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let created: Result<QuestionResource, StoreErr> = async {
+        // The store assigns the id; the placeholder is ignored.
+        let question: Question = store
+            .add_question(input.question(0), Some(&claims.sub))
+            .await?;
+        store.question_resource(question.id.0.parse()?).await
+    }
+    .await;
+    match created {
+        Ok(question) => {
+            let location: String = format!("{}/questions/{}", PREFIX, question.id);
+            located(StatusCode::CREATED, location, question)
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v2/questions/{id}",
+    tag = "v2",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i64, Path, description = "Question id")),
+    request_body(content = QuestionInput, description = "New title, content and tags"),
+    responses(
+        (status = 200, description = "Updated question; its URL is in `Location`",
+            body = QuestionData),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid question", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_question(
+    _claims: Claims,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i64>,
+    ValidJson(input): ValidJson<QuestionInput>,
+) -> Response {
+    let mut store: Store = appstate.read().await.store.clone();
+    let updated: Result<QuestionResource, StoreErr> = async {
+        store
+            .update_question(&id.to_string(), input.question(id))
+            .await?;
+        store.question_resource(id).await
+    }
+    .await;
+    match updated {
+        Ok(question) => {
+            let location: String = format!("{}/questions/{}", PREFIX, id);
+            located(StatusCode::OK, location, question)
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v2/questions/{id}",
+    tag = "v2",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i64, Path, description = "Question id")),
+    responses(
+        (status = 204, description = "Deleted question and its answers"),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn delete_question(
+    _claims: Claims,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i64>,
+) -> Response {
+    let mut store: Store = appstate.read().await.store.clone();
+    match store.delete_question(&id.to_string()).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/questions/{id}/answers",
+    tag = "v2",
+    params(("id" = i64, Path, description = "Question id"), Window),
+    responses(
+        (status = 200, description = "A page of the question's answers, oldest first",
+            body = AnswerPage),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn list_answers(
+    State(appstate): HandlerAppState,
+    Path(id): Path<i64>,
+    Query(window): Query<Window>,
+) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let listed: Result<(i64, Vec<AnswerResource>), StoreErr> = async {
+        store.question_resource(id).await?;
+        let total: i64 = store.count_answers(id).await?;
+        let answers: Vec<AnswerResource> = store
+            .list_answers(id, window.limit(), window.offset())
+            .await?;
+        Ok((total, answers))
+    }
+    .await;
+    match listed {
+        Ok((total, answers)) => {
+            let path: String = format!("{}/questions/{}/answers", PREFIX, id);
+            Json(window.page(answers, total, &path, &[])).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v2/questions/{id}/answers",
+    tag = "v2",
+    security(("bearer" = []), ("api_key" = [])),
+    params(("id" = i64, Path, description = "Question id")),
+    request_body(content = NewAnswer, description = "Answer to add"),
+    responses(
+        (status = 201, description = "Added answer; its URL is in `Location`",
+            body = AnswerData),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No question with this id", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 413, description = "Payload too large", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid answer", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn create_answer(
+    _claims: Claims,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i64>,
+    ValidJson(input): ValidJson<NewAnswer>,
+) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let created: Result<AnswerResource, StoreErr> = async {
+        let answer: Answer = store
+            .add_answer(&QuestionId(id.to_string()), &input.content)
+            .await?;
+        store.answer_resource(answer.id.0.parse()?).await
+    }
+    .await;
+    match created {
+        Ok(answer) => {
+            let location: String = format!("{}/answers/{}", PREFIX, answer.id);
+            located(StatusCode::CREATED, location, answer)
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/answers/{id}",
+    tag = "v2",
+    params(("id" = i64, Path, description = "Answer id")),
+    responses(
+        (status = 200, description = "The answer", body = AnswerData),
+        (status = 404, description = "No answer with this id", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_answer(State(appstate): HandlerAppState, Path(id): Path<i64>) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    match store.answer_resource(id).await {
+        Ok(answer) => Json(Data { data: answer }).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::test_router;
+    use question_api::{Client, ClientError, ListParams, Registration};
+    use std::future::IntoFuture;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn window_links() {
+        const PATH: &str = "/api/v2/questions";
+        let link = |query: &str| format!("{}?{}", PATH, query);
+        // Requested limit and offset, total and filter, then the links to this, the next and
+        // the previous page.
+        let cases = [
+            (None, None, 0, &[][..], "limit=20&offset=0", None, None),
+            (None, None, 20, &[], "limit=20&offset=0", None, None),
+            (
+                None,
+                None,
+                21,
+                &[],
+                "limit=20&offset=0",
+                Some("limit=20&offset=20"),
+                None,
+            ),
+            (
+                Some(2),
+                Some(2),
+                5,
+                &[],
+                "limit=2&offset=2",
+                Some("limit=2&offset=4"),
+                Some("limit=2&offset=0"),
+            ),
+            (
+                Some(2),
+                Some(4),
+                5,
+                &[],
+                "limit=2&offset=4",
+                None,
+                Some("limit=2&offset=2"),
+            ),
+            // Previous pages don't start before the first item.
+            (
+                Some(5),
+                Some(3),
+                5,
+                &[],
+                "limit=5&offset=3",
+                None,
+                Some("limit=5&offset=0"),
+            ),
+            // Out of range values are clamped.
+            (
+                Some(0),
+                Some(-1),
+                3,
+                &[],
+                "limit=1&offset=0",
+                Some("limit=1&offset=1"),
+                None,
+            ),
+            (Some(1000), None, 3, &[], "limit=100&offset=0", None, None),
+            (
+                None,
+                Some(i64::MAX),
+                3,
+                &[],
+                "limit=20&offset=9223372036854775807",
+                None,
+                Some("limit=20&offset=9223372036854775787"),
+            ),
+            (
+                Some(1),
+                None,
+                2,
+                &[("tag", "c++ & rust")],
+                "tag=c%2B%2B+%26+rust&limit=1&offset=0",
+                Some("tag=c%2B%2B+%26+rust&limit=1&offset=1"),
+                None,
+            ),
+        ];
+        for (limit, offset, total, filter, this, next, prev) in cases {
+            let window: Window = Window { limit, offset };
+            let page: Page<()> = window.page(Vec::new(), total, PATH, filter);
+            assert_eq!(page.links.this, link(this), "{:?}", window);
+            assert_eq!(page.links.next, next.map(link), "{:?}", window);
+            assert_eq!(page.links.prev, prev.map(link), "{:?}", window);
+        }
+    }
+
+    /// A client for the API served in-process over `pool`.
+    async fn client(pool: PgPool) -> Client {
+        let (app, _) = test_router(pool);
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url: String = format!("http://{}", listener.local_addr().unwrap());
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::serve(listener, app).into_future());
+        Client::new(url)
+    }
+
+    fn registration(password: &str) -> Registration {
+        Registration {
+            full_name: "Cordet".to_string(),
+            email: "cgula@pdx.edu".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    async fn authorized(pool: PgPool) -> Client {
+        let client: Client = client(pool).await;
+        let token: String = client
+            .register(&registration("password"))
+            .await
+            .expect("register")
+            .access_token;
+        client.with_token(token)
+    }
+
+    fn input(title: &str, tags: &[&str]) -> QuestionInput {
+        QuestionInput {
+            title: title.to_string(),
+            content: "Please help!".to_string(),
+            tags: tags.iter().copied().map(String::from).collect(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn question_lifecycle(pool: PgPool) {
+        let client: Client = authorized(pool).await;
+
+        let question: QuestionInput = input("How?", &["rust", "general"]);
+        let created = client
+            .create_question(&question)
+            .await
+            .expect("create question");
+        assert_eq!(created.title, "How?");
+        assert_eq!(
+            created.tags,
+            vec!["general".to_string(), "rust".to_string()]
+        );
+        let fetched = client.get_question(created.id).await;
+        assert_eq!(fetched.unwrap(), created);
+
+        let params: ListParams = ListParams {
+            tag: Some("rust".to_string()),
+            ..ListParams::default()
+        };
+        let page = client
+            .list_questions(&params)
+            .await
+            .expect("list questions");
+        assert_eq!(page.pagination.total, 1);
+        assert_eq!(page.data, vec![created.clone()]);
+
+        let question: QuestionInput = input("How, exactly?", &["rust"]);
+        let updated = client
+            .update_question(created.id, &question)
+            .await
+            .expect("update question");
+        assert_eq!(updated.id, created.id);
+        assert_eq!(updated.title, "How, exactly?");
+        assert_eq!(updated.tags, vec!["rust".to_string()]);
+
+        let answer: question_api::NewAnswer = question_api::NewAnswer {
+            content: "Like this.".to_string(),
+        };
+        let answer = client
+            .create_answer(created.id, &answer)
+            .await
+            .expect("create answer");
+        assert_eq!(answer.question_id, created.id);
+        let fetched = client.get_answer(answer.id).await;
+        assert_eq!(fetched.unwrap(), answer);
+        let answers = client
+            .list_answers(created.id, &ListParams::default())
+            .await
+            .expect("list answers");
+        assert_eq!(answers.data, vec![answer.clone()]);
+
+        client
+            .delete_question(created.id)
+            .await
+            .expect("delete question");
+        let gone = client.get_question(created.id).await;
+        assert_eq!(gone.unwrap_err().status(), Some(404));
+        let gone = client.get_answer(answer.id).await;
+        assert_eq!(gone.unwrap_err().status(), Some(404));
+    }
+
+    #[sqlx::test]
+    async fn paging_follows_links(pool: PgPool) {
+        let client: Client = authorized(pool).await;
+        let mut ids: Vec<i64> = Vec::new();
+        for n in 0..3 {
+            let question: QuestionInput = input(&format!("Question {}", n), &["paging"]);
+            let created = client.create_question(&question).await;
+            ids.push(created.expect("create question").id);
+        }
+
+        let params: ListParams = ListParams {
+            limit: Some(2),
+            tag: Some("paging".to_string()),
+            ..ListParams::default()
+        };
+        let first = client.list_questions(&params).await.unwrap();
+        assert_eq!(first.pagination.total, 3);
+        assert_eq!(first.data.len(), 2);
+        let next: String = first.links.next.clone().expect("a next page");
+        let second = client.follow::<QuestionResource>(&next).await.unwrap();
+        assert_eq!(second.data.len(), 1);
+        assert!(second.links.next.is_none());
+        let listed: Vec<i64> = first
+            .data
+            .iter()
+            .chain(&second.data)
+            .map(|q| q.id)
+            .collect();
+        assert_eq!(listed, ids);
+    }
+
+    #[sqlx::test]
+    async fn rejects_invalid_question(pool: PgPool) {
+        let client: Client = authorized(pool).await;
+        let question: QuestionInput = input("  ", &["Not A Tag"]);
+        let error: ClientError = client.create_question(&question).await.unwrap_err();
+        assert_eq!(error.status(), Some(422));
+        let ClientError::Problem(problem) = error else {
+            panic!("expected a problem document, got {}", error);
+        };
+        let fields: HashSet<String> = problem
+            .errors
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert!(fields.contains("title"), "{:?}", fields);
+        assert!(fields.contains("tags"), "{:?}", fields);
+    }
+
+    #[sqlx::test]
+    async fn requires_credentials(pool: PgPool) {
+        let client: Client = client(pool).await;
+        let question: QuestionInput = input("How?", &[]);
+        let error = client.create_question(&question).await;
+        assert_eq!(error.unwrap_err().status(), Some(401));
+        let error = client.register(&registration("not the password")).await;
+        assert_eq!(error.unwrap_err().status(), Some(401));
+    }
+}
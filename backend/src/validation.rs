@@ -0,0 +1,105 @@
+//! # Request Validation
+//!
+//! Payload types declare their constraints with `#[derive(Validate)]`; handlers accept them
+//! through [`ValidJson`] instead of [`Json`]. A payload that parses but breaks a constraint is
+//! rejected with `422 Unprocessable Entity` and a list of the offending fields, before the
+//! handler (and the database) ever sees it. The offending fields are listed in the `errors`
+//! member of the problem document.
+
+use crate::error::Problem;
+use crate::*;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{FromRequest, Request};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+// The constraints and the field errors they produce are shared with the frontend.
+pub use question_api::problem::FieldError;
+pub use question_api::validation::{not_blank, valid_tags, MAX_TAGS, MAX_TAG_LEN};
+
+/// Flatten validator's nested error map into a sorted list of field errors.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| FieldError {
+                field: field.to_string(),
+                code: error.code.to_string(),
+                message: match &error.message {
+                    Some(message) => message.to_string(),
+                    None => describe(error),
+                },
+            })
+        })
+        .collect();
+    fields.sort_by(|a, b| (&a.field, &a.code).cmp(&(&b.field, &b.code)));
+    fields
+}
+
+/// Human readable text for the built-in validators, which carry no message of their own.
+fn describe(error: &ValidationError) -> String {
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => {
+            format!("must be between {} and {} characters", min, max)
+        }
+        ("length", Some(min), None) => format!("must be at least {} characters", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters", max),
+        ("email", _, _) => "must be a valid email address".to_string(),
+        (code, _, _) => format!("failed {} check", code),
+    }
+}
+
+/// Why a [`ValidJson`] payload was refused.
+#[derive(Debug)]
+pub enum ValidationRejection {
+    /// The body was missing, too large, not JSON or didn't match the payload type.
+    Json(JsonRejection),
+    /// The payload parsed but broke one or more field constraints.
+    Invalid(ValidationErrors),
+}
+
+impl From<ValidationRejection> for Problem {
+    fn from(rejection: ValidationRejection) -> Self {
+        match rejection {
+            ValidationRejection::Json(rejection) => {
+                Problem::new(rejection.status(), "invalid-body", "Invalid request body")
+                    .with_detail(rejection.body_text())
+            }
+            ValidationRejection::Invalid(errors) => Problem::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation-failed",
+                "Validation failed",
+            )
+            .with_errors(field_errors(&errors)),
+        }
+    }
+}
+
+impl IntoResponse for ValidationRejection {
+    fn into_response(self) -> Response {
+        Problem::from(self).into_response()
+    }
+}
+
+/// JSON body extractor that also runs the payload's `Validate` constraints.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: serde::de::DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(ValidationRejection::Json)?;
+        value.validate().map_err(ValidationRejection::Invalid)?;
+        Ok(ValidJson(value))
+    }
+}
@@ -1,7 +1,28 @@
-use crate::routes::question::format_tags;
-use crate::types::question::Question;
+use crate::appstate::HandlerAppState;
+use crate::auth::{check_registration, Registration};
+use crate::csrf::{csrf_token, CSRF_SESSION_KEY};
+use crate::error::{Problem, StoreErr};
+use crate::metrics::METRICS;
+use crate::sessions::{session_user, SESSION_USER_KEY};
+use crate::startup::SESSION_ERROR_KEY;
+use crate::types::question::{format_tags, Question, QuestionId};
+use crate::validation::field_errors;
 use crate::*;
 use askama_axum::Template;
+use axum::extract::{Form, Query};
+use question_api::QuestionResource;
+use tower_sessions::Session;
+use validator::Validate;
+
+/// Where the server-rendered pages are mounted; the frontend bundle owns `/`.
+pub const SITE_ROOT: &str = "/site";
+
+/// The pages use the frontend's stylesheet, served by [`handler_stylesheet`].
+pub(crate) const STYLESHEET: &str = "/site/index.css";
+
+/// Most questions listed on the page for one tag.
+const TAGGED_QUESTIONS: i64 = 100;
+
 /// The IndexTemplate struct represents a template for rendering an index page with optional
 /// question, tags, stylesheet, and error information.
 /// Properties:
@@ -15,8 +36,8 @@ use askama_axum::Template;
 /// * `error`: The `error` property in the `IndexTemplate` struct is an optional field that holds a
 /// message or description of an error that may have occurred. It allows for displaying error messages
 /// to the user when rendering the template.
-//#[derive(Template)]
-//#[template(path = "index.html")]
+#[derive(Template)]
+#[template(path = "index.html")]
 pub struct IndexTemplate<'a> {
     question: Option<&'a Question>,
     tags: Option<String>,
@@ -42,7 +63,7 @@ impl<'a> IndexTemplate<'a> {
         Self {
             question: Some(question),
             tags: question.tags.as_ref().map(format_tags),
-            stylesheet: "../../frontend/index.css",
+            stylesheet: STYLESHEET,
             error: None,
         }
     }
@@ -61,7 +82,7 @@ impl<'a> IndexTemplate<'a> {
         Self {
             question: None,
             tags: None,
-            stylesheet: "/question.css",
+            stylesheet: STYLESHEET,
             error: Some(error),
         }
     }
@@ -79,47 +100,221 @@ pub struct IndexParams {
     id: Option<String>,
 }
 
-// Reference from joke-repo
+/// The `TellTemplate` struct renders the add-question form, with the error left in the session by
+/// a rejected submission.
+#[derive(Template)]
+#[template(path = "tell.html")]
+pub struct TellTemplate {
+    stylesheet: &'static str,
+    error: Option<String>,
+    user: String,
+    csrf_token: String,
+}
+
+/// The `TagsTemplate` struct renders every tag with the number of questions carrying it, or,
+/// for `?tag=`, the questions with that tag.
+#[derive(Template)]
+#[template(path = "tags.html")]
+pub struct TagsTemplate {
+    stylesheet: &'static str,
+    error: Option<String>,
+    tags: Vec<(String, i64)>,
+    tag: Option<String>,
+    questions: Vec<QuestionResource>,
+}
+
+#[derive(Deserialize)]
+pub struct TagsParams {
+    tag: Option<String>,
+}
+
+/// The `LoginTemplate` struct renders the login form, with the error left in the session by a
+/// failed attempt.
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate {
+    stylesheet: &'static str,
+    error: Option<String>,
+    csrf_token: String,
+}
+
+fn session_error(e: tower_sessions::session::Error) -> Response {
+    tracing::error!("session: {}", e);
+    error_page(Problem::from_status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+/// Take the error a previous request left in the session for this page.
+async fn take_flash(session: &Session) -> Option<String> {
+    session
+        .remove::<String>(SESSION_ERROR_KEY)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Redirect to `path` under the site root, with `error` shown on the page there.
+async fn redirect_with_error(session: &Session, path: &str, error: String) -> Response {
+    if let Err(e) = session.insert(SESSION_ERROR_KEY, error).await {
+        tracing::error!("session: {}", e);
+    }
+    Redirect::to(&format!("{}{}", SITE_ROOT, path)).into_response()
+}
+
+/// Render `problem` as an error page with its status code. The detail is already safe to show.
+fn error_page(problem: Problem) -> Response {
+    let status: StatusCode = problem.status_code();
+    let message: String = problem.detail.unwrap_or(problem.title);
+    (status, IndexTemplate::error(message)).into_response()
+}
 
-/*pub async fn handler_index(
+/// Show the question named by `?id=`, or redirect to a random one.
+pub async fn handler_index(
     State(appstate): HandlerAppState,
     Query(params): Query<IndexParams>,
 ) -> Response {
-    let appstate = appstate.read().await;
-    let jokebase = &appstate.jokebase;
+    let store: Store = appstate.read().await.store.clone();
 
-    let joke = if let Some(id) = params.id {
-        jokebase.get(&id).await
-    } else {
-        match jokebase.get_random().await {
-            Ok(joke) => return Redirect::to(&format!("/?id={}", joke.id)).into_response(),
+    let question: Result<Question, StoreErr> = if let Some(id) = params.id {
+        store.get(&id).await.map_err(|e| match e {
+            StoreErr::NotFound(_) => StoreErr::QuestionNotFound(id),
             e => e,
+        })
+    } else {
+        match store.get_random().await {
+            Ok(question) => {
+                return Redirect::to(&format!("{}/?id={}", SITE_ROOT, question.id.0))
+                    .into_response()
+            }
+            Err(StoreErr::NotFound(_)) => {
+                return IndexTemplate::error("there are no questions yet".to_string())
+                    .into_response()
+            }
+            Err(e) => Err(e),
+        }
+    };
+
+    match question {
+        Ok(question) => IndexTemplate::question(&question).into_response(),
+        Err(e) => error_page(Problem::from(e)),
+    }
+}
+
+/// List the tags, or the questions with the tag named by `?tag=`.
+pub async fn handler_tags(
+    State(appstate): HandlerAppState,
+    Query(params): Query<TagsParams>,
+) -> Response {
+    let store: Store = appstate.read().await.store.clone();
+    let page: Result<TagsTemplate, StoreErr> = async {
+        let (tags, questions) = match &params.tag {
+            Some(tag) => (
+                Vec::new(),
+                store
+                    .list_questions(Some(tag), None, TAGGED_QUESTIONS, 0)
+                    .await?,
+            ),
+            None => (store.tag_counts().await?, Vec::new()),
+        };
+        Ok(TagsTemplate {
+            stylesheet: STYLESHEET,
+            error: None,
+            tags,
+            tag: params.tag,
+            questions,
+        })
+    }
+    .await;
+    match page {
+        Ok(page) => page.into_response(),
+        Err(e) => error_page(Problem::from(e)),
+    }
+}
+
+/// Show the add-question form to a logged in user.
+pub async fn handler_tell(session: Session) -> Response {
+    let Some(user) = session_user(&session).await else {
+        return redirect_with_error(&session, "/login", "log in to add a question".to_string())
+            .await;
+    };
+    let csrf_token: String = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return session_error(e),
+    };
+    TellTemplate {
+        stylesheet: STYLESHEET,
+        error: take_flash(&session).await,
+        user,
+        csrf_token,
+    }
+    .into_response()
+}
+
+/// Show the login form.
+pub async fn handler_login_form(session: Session) -> Response {
+    let csrf_token: String = match csrf_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return session_error(e),
+    };
+    LoginTemplate {
+        stylesheet: STYLESHEET,
+        error: take_flash(&session).await,
+        csrf_token,
+    }
+    .into_response()
+}
+
+/// Log in with the registration credentials. The session id is changed on login so a session
+/// id planted before it is of no use.
+pub async fn handler_login(
+    State(appstate): HandlerAppState,
+    session: Session,
+    Form(registration): Form<Registration>,
+) -> Response {
+    if let Err(errors) = registration.validate() {
+        return redirect_with_error(&session, "/login", describe_errors(&errors)).await;
+    }
+    let user: String = match check_registration(&*appstate.read().await, &registration) {
+        Ok(user) => user,
+        Err(e) => {
+            METRICS.auth_failure(&e);
+            return redirect_with_error(&session, "/login", "wrong password".to_string()).await;
         }
     };
 
-    match joke {
-        Ok(joke) => (StatusCode::OK, IndexTemplate::joke(&joke)).into_response(),
-        Err(JokeBaseErr::JokeDoesNotExist(id)) => (
-            StatusCode::OK,
-            IndexTemplate::error(format!("cannot find joke {}", id)),
-        )
-            .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            IndexTemplate::error(e.to_string()),
-        )
-            .into_response(),
+    // The form token is replaced too, as it may have been seen before login.
+    let logged_in = async {
+        session.cycle_id().await?;
+        session.remove_value(CSRF_SESSION_KEY).await?;
+        session.insert(SESSION_USER_KEY, user).await
+    };
+    if let Err(e) = logged_in.await {
+        return session_error(e);
     }
+    Redirect::to(&format!("{}/tell", SITE_ROOT)).into_response()
 }
- */
 
-/// The `AddParams` struct represents parameters for adding a question with an ID, title, content,
-/// and optional tags.
+/// End the session.
+pub async fn handler_logout(session: Session) -> Response {
+    if let Err(e) = session.flush().await {
+        tracing::error!("session: {}", e);
+    }
+    Redirect::to(&format!("{}/", SITE_ROOT)).into_response()
+}
+
+/// Serve the frontend's stylesheet for the server-rendered pages.
+pub async fn handler_stylesheet() -> Response {
+    (
+        [(http::header::CONTENT_TYPE, "text/css")],
+        include_str!("../../frontend/index.css"),
+    )
+        .into_response()
+}
+
+/// The `AddParams` struct represents parameters for adding a question with a title, content,
+/// and optional tags. The database assigns its id.
 ///
 /// Properties:
 ///
-/// * `id`: The `id` property in the `AddParams` struct is of type `String` and is used to store an
-/// identifier for the item being added.
 /// * `title`: The `title` property in the `AddParams` struct represents the title of the item being
 /// added. It is of type `String`.
 /// * `content`: The `AddParams` struct has the following properties:
@@ -128,7 +323,6 @@ pub struct IndexParams {
 /// provided.
 #[derive(Deserialize)]
 pub struct AddParams {
-    id: String,
     title: String,
     content: String,
     tags: Option<String>,
@@ -150,7 +344,12 @@ fn parse_tags(tags: Option<String>) -> Option<HashSet<String>> {
     if tags.is_empty() {
         return None;
     }
-    let tags: HashSet<String> = tags.split(',').map(str::trim).map(str::to_string).collect();
+    let tags: HashSet<String> = tags
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect();
     if tags.is_empty() {
         None
     } else {
@@ -158,33 +357,48 @@ fn parse_tags(tags: Option<String>) -> Option<HashSet<String>> {
     }
 }
 
-// Reference from joke repo
-// * pub async fn handler_add(
-//     State(appstate): HandlerAppState,
-//     Query(params): Query<AddParams>,
-//     session: Session,
-// ) -> Response {
-//     // XXX Condition user input.
-//     let joke = Joke {
-//         id: params.id.clone(),
-//         whos_there: params.who,
-//         answer_who: params.answer,
-//         tags: parse_tags(params.tags),
-//         source: parse_source(params.source),
-//     };
-//
-//     let mut appstate = appstate.write().await;
-//
-//     match appstate.jokebase.add(joke).await {
-//         Ok(()) => Redirect::to(&format!("/?id={}", params.id)).into_response(),
-//         Err(JokeBaseErr::JokeBaseIoError(msg)) => {
-//             (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
-//         }
-//         Err(JokeBaseErr::JokeExists(id)) => {
-//             let error = Some(format!("joke {} already exists", id));
-//             let _ = session.insert(SESSION_ERROR_KEY, error).await;
-//             Redirect::to("/tell").into_response()
-//         }
-//         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-//     }
-// }
+/// One line summary of a rejected form.
+fn describe_errors(errors: &validator::ValidationErrors) -> String {
+    field_errors(errors)
+        .iter()
+        .map(|e| format!("{}: {}", e.field, e.message))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Add the question submitted by the form and show it, or return to the form with the reason
+/// it was refused.
+pub async fn handler_add(
+    State(appstate): HandlerAppState,
+    session: Session,
+    Form(params): Form<AddParams>,
+) -> Response {
+    let Some(user) = session_user(&session).await else {
+        return redirect_with_error(&session, "/login", "log in to add a question".to_string())
+            .await;
+    };
+    let question: Question = Question {
+        // Replaced by the id the database assigns.
+        id: QuestionId(String::new()),
+        title: params.title,
+        content: params.content,
+        tags: parse_tags(params.tags),
+    };
+    if let Err(errors) = question.validate() {
+        return redirect_with_error(&session, "/tell", describe_errors(&errors)).await;
+    }
+
+    let appstate = appstate.write().await;
+    match appstate.store.add_question(question, Some(&user)).await {
+        Ok(question) => {
+            Redirect::to(&format!("{}/?id={}", SITE_ROOT, question.id.0)).into_response()
+        }
+        Err(e) => {
+            let problem: Problem = Problem::from(e);
+            if problem.status_code().is_server_error() {
+                return error_page(problem);
+            }
+            redirect_with_error(&session, "/tell", problem.detail.unwrap_or(problem.title)).await
+        }
+    }
+}
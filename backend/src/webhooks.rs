@@ -0,0 +1,783 @@
+//! # Webhooks
+//!
+//! Notifies other systems when questions and answers change. Administrators (`--admin-emails`,
+//! signed in through OIDC) register URLs under `/api/v1/webhooks`, each optionally limited to
+//! some event kinds.
+//!
+//! Every [`Event`] becomes one delivery per matching active webhook, kept in
+//! `webhook_deliveries` until it succeeds or runs out of attempts. A worker posts due
+//! deliveries as the event's JSON, signed with the webhook's secret:
+//!
+//! * `Webhook-Id`: the delivery id, the same for every retry of it.
+//! * `Webhook-Event`: the event kind, such as `question_created`.
+//! * `Webhook-Timestamp`: Unix seconds at which the attempt was made.
+//! * `Webhook-Signature`: `sha256=` followed by the hex HMAC-SHA256 of `<timestamp>.<body>`.
+//!
+//! A 2xx answer completes the delivery. Anything else is retried with exponential backoff up to
+//! `--webhook-max-attempts` times, and every attempt is logged in `webhook_attempts`. Workers
+//! claim deliveries with `FOR UPDATE SKIP LOCKED`, so instances can share the queue. Finished
+//! deliveries are purged after `--webhook-retention-days` by the [`PURGE_DELIVERIES`] job.
+//!
+//! Deliveries are queued by the [`Store`] in the same transaction as the change, so every
+//! committed change is delivered even if the process dies before publishing it.
+
+use crate::appstate::HandlerAppState;
+use crate::auth::Admin;
+use crate::error::{Problem, StoreErr};
+use crate::events::{Event, EventKind, EventLog};
+use crate::jobs::{backoff, JobHandler};
+use crate::shutdown::Shutdown;
+use crate::validation::ValidJson;
+use crate::*;
+
+use axum::extract::Query;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+/// Deliveries one worker pass claims at most.
+const BATCH_SIZE: i64 = 16;
+
+/// Most deliveries a listing returns.
+const MAX_LIST_LIMIT: i64 = 200;
+
+/// Kind of the job deleting old finished deliveries.
+pub const PURGE_DELIVERIES: &str = "webhooks.purge_deliveries";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WebhookArgs {
+    /// Attempts made at a delivery before it is marked failed.
+    #[clap(long, env = "WEBHOOK_MAX_ATTEMPTS", default_value_t = 8)]
+    pub webhook_max_attempts: i32,
+    /// Delay before the first retry; each further retry waits twice as long.
+    #[clap(long, env = "WEBHOOK_RETRY_BASE_SECS", default_value_t = 30)]
+    pub webhook_retry_base_secs: u64,
+    /// Seconds a receiver has to answer.
+    #[clap(long, env = "WEBHOOK_TIMEOUT_SECS", default_value_t = 10)]
+    pub webhook_timeout_secs: u64,
+    /// Seconds between checks for due retries.
+    #[clap(long, env = "WEBHOOK_POLL_SECS", default_value_t = 5)]
+    pub webhook_poll_secs: u64,
+    /// Days finished deliveries and their attempts are kept before being purged.
+    #[clap(long, env = "WEBHOOK_RETENTION_DAYS", default_value_t = 30)]
+    pub webhook_retention_days: i32,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Webhook {
+    #[schema(example = 1)]
+    pub id: i32,
+    #[schema(example = "https://hooks.example.org/questions")]
+    pub url: String,
+    /// Event kinds delivered; empty for all of them.
+    #[schema(example = json!(["question_created", "answer_created"]))]
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_on: DateTime<Utc>,
+}
+
+/// A webhook as returned once, on creation, with the secret its deliveries are signed with.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    #[schema(example = "Q2hhbmdlIG1lIGZvciBhIHJlYWwgc2VjcmV0IQ")]
+    pub secret: String,
+}
+
+fn valid_events(events: &[String]) -> Result<(), ValidationError> {
+    if let Some(event) = events
+        .iter()
+        .find(|e| !EventKind::ALL.iter().any(|kind| kind.name() == e.as_str()))
+    {
+        let mut error: ValidationError =
+            ValidationError::new("event").with_message(Cow::from("not a known event kind"));
+        error.add_param(Cow::from("value"), event);
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn valid_scheme(url: &str) -> Result<(), ValidationError> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return Ok(());
+    }
+    Err(ValidationError::new("scheme").with_message(Cow::from("must be an http(s) URL")))
+}
+
+/// A webhook to create, or the new settings of an existing one.
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct WebhookSpec {
+    #[schema(example = "https://hooks.example.org/questions")]
+    #[validate(url, length(max = 2048), custom(function = "valid_scheme"))]
+    pub url: String,
+    #[serde(default)]
+    #[schema(example = json!(["question_created", "answer_created"]))]
+    #[validate(custom(function = "valid_events"))]
+    pub events: Vec<String>,
+    #[serde(default = "active")]
+    pub active: bool,
+}
+
+fn active() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Delivery {
+    #[schema(example = 42)]
+    pub id: i64,
+    pub webhook_id: i32,
+    #[schema(example = "question_created")]
+    pub event_kind: String,
+    /// `pending`, `delivered` or `failed`.
+    #[schema(example = "pending")]
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_on: DateTime<Utc>,
+    pub created_on: DateTime<Utc>,
+    pub delivered_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Attempt {
+    pub attempted_on: DateTime<Utc>,
+    /// Status the receiver answered with, if it answered.
+    #[schema(example = 503)]
+    pub response_status: Option<i32>,
+    #[schema(example = "HTTP 503")]
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+/// A delivery with the payload sent and the log of its attempts.
+#[derive(Debug, Serialize, ToSchema, sqlx::FromRow)]
+pub struct DeliveryLog {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub delivery: Delivery,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    #[sqlx(skip)]
+    pub log: Vec<Attempt>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DeliveryFilter {
+    /// Only deliveries with this status.
+    pub status: Option<String>,
+    /// Most deliveries to return, newest first.
+    pub limit: Option<i64>,
+}
+
+/// `sha256=<hex>` signature of `body` sent at `timestamp`.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac: Hmac<Sha256> =
+        Hmac::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Start the worker sending due deliveries, until `shutdown`. It looks for them when `log`
+/// publishes an event and every `--webhook-poll-secs`, and finishes the batch it is sending
+/// before stopping.
+pub fn spawn(args: &WebhookArgs, pool: PgPool, log: Arc<EventLog>, shutdown: &Shutdown) {
+    shutdown.spawn(work(args.clone(), pool, log, shutdown.clone()));
+}
+
+/// Queue a delivery of `event` for every active webhook that wants it, in the transaction
+/// making the change. The payload is the event without its `id`, which is only given once the
+/// change is published and only orders `/api/v1/events`.
+pub async fn queue(tx: &mut PgConnection, event: &Event) -> Result<(), sqlx::Error> {
+    let mut payload: serde_json::Value = serde_json::to_value(event).expect("events serialize");
+    if let Some(payload) = payload.as_object_mut() {
+        payload.remove("id");
+    }
+    sqlx::query(
+        r#"INSERT INTO webhook_deliveries (webhook_id, event_kind, payload)
+        SELECT id, $1, $2 FROM webhooks
+        WHERE active AND (cardinality(events) = 0 OR $1 = ANY(events));"#,
+    )
+    .bind(event.kind.name())
+    .bind(payload)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// A claimed delivery with what is needed to send it.
+#[derive(Debug, sqlx::FromRow)]
+struct Due {
+    id: i64,
+    event_kind: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+async fn work(args: WebhookArgs, pool: PgPool, log: Arc<EventLog>, shutdown: Shutdown) {
+    let timeout: Duration = Duration::from_secs(args.webhook_timeout_secs);
+    let http: reqwest::Client = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("webhook http client");
+    let poll: Duration = Duration::from_secs(args.webhook_poll_secs);
+    let (_, _, mut events) = log.subscribe(None);
+    while !shutdown.is_cancelled() {
+        // Claimed deliveries are pushed back by twice the timeout, so one whose worker died
+        // becomes due again.
+        let claimed: Result<Vec<Due>, sqlx::Error> = sqlx::query_as(
+            r#"UPDATE webhook_deliveries d
+        SET attempts = d.attempts + 1, next_attempt_on = NOW() + make_interval(secs => $2)
+        FROM webhooks w
+        WHERE w.id = d.webhook_id AND d.id IN (
+            SELECT due.id FROM webhook_deliveries due
+            JOIN webhooks hook ON hook.id = due.webhook_id
+            WHERE hook.active AND due.status = 'pending' AND due.next_attempt_on <= NOW()
+            ORDER BY due.next_attempt_on
+            LIMIT $1
+            FOR UPDATE OF due SKIP LOCKED)
+        RETURNING d.id, d.event_kind, d.payload, d.attempts, w.url, w.secret;"#,
+        )
+        .bind(BATCH_SIZE)
+        .bind(2.0 * timeout.as_secs_f64())
+        .fetch_all(&pool)
+        .await;
+        let claimed: Vec<Due> = match claimed {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                tracing::error!("webhooks: claiming deliveries: {}", e);
+                Vec::new()
+            }
+        };
+        if claimed.is_empty() {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                // Lagging only means there is more to look for.
+                received = events.recv() => if let Err(RecvError::Closed) = received {
+                    return;
+                },
+                _ = tokio::time::sleep(poll) => (),
+            }
+            continue;
+        }
+        let sends = claimed
+            .into_iter()
+            .map(|due| deliver(&args, &http, &pool, due));
+        futures::future::join_all(sends).await;
+    }
+}
+
+#[tracing::instrument(name = "webhook.deliver", skip_all, fields(delivery = due.id))]
+async fn deliver(args: &WebhookArgs, http: &reqwest::Client, pool: &PgPool, due: Due) {
+    let body: Vec<u8> = serde_json::to_vec(&due.payload).expect("payloads serialize");
+    let timestamp: i64 = Utc::now().timestamp();
+    let started: std::time::Instant = std::time::Instant::now();
+    let sent: Result<reqwest::Response, reqwest::Error> = http
+        .post(&due.url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header("webhook-id", due.id.to_string())
+        .header("webhook-event", &due.event_kind)
+        .header("webhook-timestamp", timestamp.to_string())
+        .header(
+            "webhook-signature",
+            signature(&due.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+    let duration_ms: i32 = i32::try_from(started.elapsed().as_millis()).unwrap_or(i32::MAX);
+
+    let (response_status, error): (Option<i32>, Option<String>) = match &sent {
+        Ok(response) if response.status().is_success() => {
+            (Some(i32::from(response.status().as_u16())), None)
+        }
+        Ok(response) => (
+            Some(i32::from(response.status().as_u16())),
+            Some(format!("HTTP {}", response.status().as_u16())),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let status: &str = match &error {
+        None => "delivered",
+        Some(_) if due.attempts >= args.webhook_max_attempts => "failed",
+        Some(_) => "pending",
+    };
+    if let Some(error) = &error {
+        tracing::warn!(
+            "webhook delivery {} attempt {}: {}",
+            due.id,
+            due.attempts,
+            error
+        );
+    }
+
+    let retry: Duration = backoff(
+        Duration::from_secs(args.webhook_retry_base_secs),
+        due.attempts,
+    );
+    let recorded: Result<(), sqlx::Error> = async {
+        let mut tx: sqlx::Transaction<'_, Postgres> = pool.begin().await?;
+        sqlx::query(
+            r#"INSERT INTO webhook_attempts (delivery_id, response_status, error, duration_ms)
+        VALUES ($1, $2, $3, $4);"#,
+        )
+        .bind(due.id)
+        .bind(response_status)
+        .bind(&error)
+        .bind(duration_ms)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"UPDATE webhook_deliveries
+        SET status = $2,
+            delivered_on = CASE WHEN $2 = 'delivered' THEN NOW() END,
+            next_attempt_on = NOW() + make_interval(secs => $3)
+        WHERE id = $1;"#,
+        )
+        .bind(due.id)
+        .bind(status)
+        .bind(retry.as_secs_f64())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = recorded {
+        tracing::error!("webhooks: recording delivery {}: {}", due.id, e);
+    }
+}
+
+/// Deletes delivered and failed deliveries older than `--webhook-retention-days`.
+pub struct PurgeDeliveries {
+    pub pool: PgPool,
+    pub retention_days: i32,
+}
+
+#[async_trait]
+impl JobHandler for PurgeDeliveries {
+    async fn run(&self, _payload: serde_json::Value) -> Result<(), String> {
+        let purged: u64 = sqlx::query(
+            r#"DELETE FROM webhook_deliveries
+        WHERE status IN ('delivered', 'failed')
+            AND created_on < NOW() - make_interval(days => $1);"#,
+        )
+        .bind(self.retention_days)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .rows_affected();
+        tracing::debug!("purged {} webhook deliveries", purged);
+        Ok(())
+    }
+}
+
+fn not_found(what: &str) -> Response {
+    StoreErr::NotFound(format!("no such {}", what)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Registered webhooks", body = [Webhook]),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn list_webhooks(_admin: Admin, State(appstate): HandlerAppState) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let webhooks: Result<Vec<Webhook>, sqlx::Error> =
+        sqlx::query_as(r#"SELECT id, url, events, active, created_on FROM webhooks ORDER BY id;"#)
+            .fetch_all(&pool)
+            .await;
+    match webhooks {
+        Ok(webhooks) => Json(webhooks).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content = WebhookSpec, description = "Webhook to register"),
+    responses(
+        (status = 201, description = "Registered webhook and its signing secret",
+            body = CreatedWebhook),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid webhook", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn create_webhook(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    ValidJson(spec): ValidJson<WebhookSpec>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let secret: String = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+    let webhook: Result<Webhook, sqlx::Error> = sqlx::query_as(
+        r#"INSERT INTO webhooks (url, secret, events, active)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, url, events, active, created_on;"#,
+    )
+    .bind(&spec.url)
+    .bind(&secret)
+    .bind(&spec.events)
+    .bind(spec.active)
+    .fetch_one(&pool)
+    .await;
+    match webhook {
+        Ok(webhook) => {
+            let location: String = format!("/api/v1/webhooks/{}", webhook.id);
+            (
+                StatusCode::CREATED,
+                [(http::header::LOCATION, location)],
+                Json(CreatedWebhook { webhook, secret }),
+            )
+                .into_response()
+        }
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/webhooks/{id}",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    request_body(content = WebhookSpec, description = "New settings of the webhook"),
+    responses(
+        (status = 200, description = "Updated webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No webhook with this id", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 422, description = "Invalid webhook", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn update_webhook(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i32>,
+    ValidJson(spec): ValidJson<WebhookSpec>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let webhook: Result<Option<Webhook>, sqlx::Error> = sqlx::query_as(
+        r#"UPDATE webhooks SET url = $2, events = $3, active = $4
+        WHERE id = $1
+        RETURNING id, url, events, active, created_on;"#,
+    )
+    .bind(id)
+    .bind(&spec.url)
+    .bind(&spec.events)
+    .bind(spec.active)
+    .fetch_optional(&pool)
+    .await;
+    match webhook {
+        Ok(Some(webhook)) => Json(webhook).into_response(),
+        Ok(None) => not_found("webhook"),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/webhooks/{id}",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 204, description = "Deleted the webhook and its deliveries"),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No webhook with this id", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn delete_webhook(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i32>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let deleted = sqlx::query(r#"DELETE FROM webhooks WHERE id = $1;"#)
+        .bind(id)
+        .execute(&pool)
+        .await;
+    match deleted {
+        Ok(result) if result.rows_affected() == 0 => not_found("webhook"),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    params(DeliveryFilter),
+    responses(
+        (status = 200, description = "Deliveries of the webhook, newest first",
+            body = [Delivery]),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn list_deliveries(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Path(id): Path<i32>,
+    Query(filter): Query<DeliveryFilter>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let limit: i64 = filter.limit.unwrap_or(50).clamp(1, MAX_LIST_LIMIT);
+    let deliveries: Result<Vec<Delivery>, sqlx::Error> = sqlx::query_as(
+        r#"SELECT id, webhook_id, event_kind, status, attempts, next_attempt_on, created_on,
+            delivered_on
+        FROM webhook_deliveries
+        WHERE webhook_id = $1 AND ($2::TEXT IS NULL OR status = $2)
+        ORDER BY id DESC
+        LIMIT $3;"#,
+    )
+    .bind(id)
+    .bind(&filter.status)
+    .bind(limit)
+    .fetch_all(&pool)
+    .await;
+    match deliveries {
+        Ok(deliveries) => Json(deliveries).into_response(),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/{id}/deliveries/{delivery_id}",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Delivery with its payload and attempts",
+            body = DeliveryLog),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No such delivery", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_delivery(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Path((id, delivery_id)): Path<(i32, i64)>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let delivery: Result<Option<DeliveryLog>, sqlx::Error> = sqlx::query_as(
+        r#"SELECT id, webhook_id, event_kind, status, attempts, next_attempt_on, created_on,
+            delivered_on, payload
+        FROM webhook_deliveries
+        WHERE id = $1 AND webhook_id = $2;"#,
+    )
+    .bind(delivery_id)
+    .bind(id)
+    .fetch_optional(&pool)
+    .await;
+    let mut delivery: DeliveryLog = match delivery {
+        Ok(Some(delivery)) => delivery,
+        Ok(None) => return not_found("delivery"),
+        Err(e) => return StoreErr::from(e).into_response(),
+    };
+    let log: Result<Vec<Attempt>, sqlx::Error> = sqlx::query_as(
+        r#"SELECT attempted_on, response_status, error, duration_ms
+        FROM webhook_attempts
+        WHERE delivery_id = $1
+        ORDER BY id;"#,
+    )
+    .bind(delivery_id)
+    .fetch_all(&pool)
+    .await;
+    match log {
+        Ok(log) => {
+            delivery.log = log;
+            Json(delivery).into_response()
+        }
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+    tag = "webhooks",
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 202, description = "Delivery queued again with a fresh set of attempts",
+            body = Delivery),
+        (status = 401, description = "Missing or invalid token", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 403, description = "Not an administrator", body = Problem,
+            content_type = "application/problem+json"),
+        (status = 404, description = "No such delivery", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn redeliver(
+    _admin: Admin,
+    State(appstate): HandlerAppState,
+    Path((id, delivery_id)): Path<(i32, i64)>,
+) -> Response {
+    let pool: PgPool = appstate.read().await.store.connection.clone();
+    let delivery: Result<Option<Delivery>, sqlx::Error> = sqlx::query_as(
+        r#"UPDATE webhook_deliveries
+        SET status = 'pending', attempts = 0, next_attempt_on = NOW(), delivered_on = NULL
+        WHERE id = $1 AND webhook_id = $2
+        RETURNING id, webhook_id, event_kind, status, attempts, next_attempt_on, created_on,
+            delivered_on;"#,
+    )
+    .bind(delivery_id)
+    .bind(id)
+    .fetch_optional(&pool)
+    .await;
+    match delivery {
+        Ok(Some(delivery)) => (StatusCode::ACCEPTED, Json(delivery)).into_response(),
+        Ok(None) => not_found("delivery"),
+        Err(e) => StoreErr::from(e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::question::{Question, QuestionId};
+
+    /// Against signatures computed independently, e.g. with Python's `hmac` module.
+    #[test]
+    fn signature() {
+        let body: &[u8] = br#"{"id":1}"#;
+        let cases: [(&str, i64, &[u8], &str); 4] = [
+            (
+                "secret",
+                1_700_000_000,
+                body,
+                "3dd1b9aef568d75f6790a84bd2e5dfa1f44409eef3cbdbd3f10b837376100c11",
+            ),
+            (
+                "secret",
+                1_700_000_001,
+                body,
+                "d0c79a345e51a61362e0123dd2fc00ec01a78397760f2babc7a052bbbf46c313",
+            ),
+            (
+                "other",
+                1_700_000_000,
+                body,
+                "e0cb77fc6d5b2877ec062213c262d236b5dd5a833d29fdc5a058c5fbfa287b47",
+            ),
+            (
+                "",
+                0,
+                b"",
+                "b849d5a581847b281957065739df36df2463d1977ea8d6e1e4e6cf33fadc68c3",
+            ),
+        ];
+        for (secret, timestamp, body, hex) in cases {
+            assert_eq!(
+                super::signature(secret, timestamp, body),
+                format!("sha256={}", hex),
+                "{} {}",
+                secret,
+                timestamp
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn changes_queue_deliveries_with_them(pool: PgPool) {
+        let webhooks: [(&str, &[&str]); 3] = [
+            ("https://all.example.org", &[]),
+            ("https://answers.example.org", &["answer_created"]),
+            ("https://deletions.example.org", &["question_deleted"]),
+        ];
+        for (url, events) in webhooks {
+            sqlx::query(r#"INSERT INTO webhooks (url, secret, events) VALUES ($1, 's', $2);"#)
+                .bind(url)
+                .bind(events)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let store: Store = Store {
+            connection: pool.clone(),
+            events: Arc::default(),
+            notify: false,
+        };
+        let question: Question = store
+            .add_question(
+                Question {
+                    id: QuestionId(String::new()),
+                    title: "How?".to_string(),
+                    content: "Please help!".to_string(),
+                    tags: None,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        store.add_answer(&question.id, "Like so.").await.unwrap();
+        // Rolled back, so nothing is queued for it.
+        let missing: QuestionId = QuestionId("0".to_string());
+        store.add_answer(&missing, "Lost.").await.unwrap_err();
+
+        let queued: Vec<(String, String, serde_json::Value)> = sqlx::query_as(
+            r#"SELECT w.url, d.event_kind, d.payload
+        FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+        ORDER BY d.id;"#,
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let queued: Vec<(&str, &str)> = queued
+            .iter()
+            .map(|(url, kind, payload)| {
+                assert_eq!(payload["question_id"], question.id.0.as_str());
+                assert!(payload.get("id").is_none(), "{}", payload);
+                (url.as_str(), kind.as_str())
+            })
+            .collect();
+        assert_eq!(
+            queued,
+            vec![
+                ("https://all.example.org", "question_created"),
+                ("https://all.example.org", "answer_created"),
+                ("https://answers.example.org", "answer_created"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,477 @@
+//! # WebSocket
+//!
+//! `GET /api/v1/ws` upgrades to a WebSocket for clients that pick what they hear about while
+//! connected, and that tell other readers of a question they are there or typing. Every
+//! message is a JSON object with a `type`.
+//!
+//! From the client ([`ClientMessage`]):
+//!
+//! * `subscribe` / `unsubscribe` with `question_ids` and `tags` to follow or drop.
+//! * `typing` with a `question_id`, relayed to the other subscribers of that question.
+//! * `ping`, answered with `pong`, for clients that can't see WebSocket ping frames.
+//!
+//! From the server ([`ServerMessage`]): `subscribed` with the current subscription, `event`
+//! for each [`Event`] matching it, `typing`, `presence` when a user starts or stops following a
+//! question, `lagged` when events were missed, `pong` and `error`. Subscribing to a question
+//! is also answered with a `presence` for each user already following it.
+//!
+//! The upgrade request needs an access token, as `Authorization: Bearer` or, since browsers
+//! can't set headers on WebSocket requests, the `access_token` query parameter. The server
+//! pings every [`HEARTBEAT_INTERVAL`] and drops connections silent for [`CLIENT_TIMEOUT`].
+//! Connections are closed when the server shuts down.
+
+use crate::appstate::HandlerAppState;
+use crate::auth::{subject_name, AuthError, Claims};
+use crate::error::Problem;
+use crate::events::{Event, EventLog};
+use crate::shutdown::Shutdown;
+use crate::*;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Query;
+use futures::stream::{SplitSink, StreamExt};
+use futures::SinkExt;
+use http::HeaderMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Instant;
+use utoipa::{IntoParams, ToSchema};
+
+/// How often the server pings each connection.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a connection may go without sending anything, pongs included.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Largest message accepted from a client.
+const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+
+/// Most question ids plus tags one connection may follow.
+const MAX_SUBSCRIPTIONS: usize = 256;
+
+/// Typing and presence notices waiting for slow connections before they miss some.
+const RELAY_CAPACITY: usize = 256;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe {
+        #[serde(default)]
+        question_ids: Vec<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        question_ids: Vec<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    Typing {
+        question_id: String,
+    },
+    Ping,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Subscribed {
+        question_ids: Vec<String>,
+        tags: Vec<String>,
+    },
+    Event(Box<Event>),
+    Typing {
+        question_id: String,
+        user: String,
+    },
+    Presence {
+        question_id: String,
+        user: String,
+        online: bool,
+    },
+    Lagged,
+    Pong,
+    Error {
+        message: String,
+    },
+}
+
+/// A typing or presence notice from one connection.
+#[derive(Debug, Clone)]
+struct Signal {
+    from: u64,
+    question_id: String,
+    message: ServerMessage,
+}
+
+/// Passes typing and presence notices between connections. Typing notices aren't stored
+/// anywhere; who follows each question is kept for the snapshot a new subscriber gets.
+pub struct Relay {
+    next_connection: AtomicU64,
+    sender: broadcast::Sender<Signal>,
+    /// Users following each question, by connection.
+    present: Mutex<HashMap<String, HashMap<u64, String>>>,
+}
+
+impl std::fmt::Debug for Relay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Relay").finish_non_exhaustive()
+    }
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(RELAY_CAPACITY);
+        Self {
+            next_connection: AtomicU64::new(0),
+            sender,
+            present: Mutex::default(),
+        }
+    }
+}
+
+impl Relay {
+    fn send(&self, from: u64, question_id: &str, message: ServerMessage) {
+        // Nobody listening isn't an error.
+        let _ = self.sender.send(Signal {
+            from,
+            question_id: question_id.to_string(),
+            message,
+        });
+    }
+
+    /// Mark `user` on connection `from` as following `question_id` and tell the others. Returns
+    /// the other users already following it, sorted.
+    fn join(&self, from: u64, question_id: &str, user: &str) -> Vec<String> {
+        let others: Vec<String> = {
+            let mut present = self.present.lock().unwrap();
+            let followers: &mut HashMap<u64, String> =
+                present.entry(question_id.to_string()).or_default();
+            let mut others: Vec<String> = followers
+                .iter()
+                .filter(|(connection, other)| **connection != from && *other != user)
+                .map(|(_, other)| other.clone())
+                .collect();
+            others.sort();
+            others.dedup();
+            followers.insert(from, user.to_string());
+            others
+        };
+        self.send(from, question_id, presence(question_id, user, true));
+        others
+    }
+
+    /// Mark connection `from` as no longer following `question_id` and tell the others.
+    fn leave(&self, from: u64, question_id: &str, user: &str) {
+        {
+            let mut present = self.present.lock().unwrap();
+            if let Some(followers) = present.get_mut(question_id) {
+                followers.remove(&from);
+                if followers.is_empty() {
+                    present.remove(question_id);
+                }
+            }
+        }
+        self.send(from, question_id, presence(question_id, user, false));
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct WsAuth {
+    /// Access token, for clients that can't send an `Authorization` header.
+    pub access_token: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/ws",
+    tag = "events",
+    params(WsAuth),
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol; messages are \
+            `ClientMessage` and `ServerMessage` JSON objects"),
+        (status = 401, description = "Missing or invalid access token", body = Problem,
+            content_type = "application/problem+json"),
+    )
+)]
+pub async fn ws(
+    State(appstate): HandlerAppState,
+    Query(auth): Query<WsAuth>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let bearer: Option<&str> = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let Some(token) = bearer.or(auth.access_token.as_deref()) else {
+        return AuthError::InvalidToken.into_response();
+    };
+    let appstate = appstate.read().await;
+    let claims: Claims = match appstate.jwt_keys.decode_claims(token) {
+        Ok(claims) => claims,
+        Err(_) => return AuthError::InvalidToken.into_response(),
+    };
+    let log: Arc<EventLog> = appstate.store.events.clone();
+    let relay: Arc<Relay> = appstate.relay.clone();
+    let shutdown: Shutdown = appstate.shutdown.clone();
+    drop(appstate);
+
+    // Only the name, so addresses aren't shown to other users.
+    let user: String = subject_name(&claims.sub).to_string();
+    upgrade
+        .max_message_size(MAX_MESSAGE_BYTES)
+        .on_upgrade(move |socket| connection(socket, user, log, relay, shutdown))
+}
+
+/// What one connection follows.
+#[derive(Debug, Default)]
+struct Subscription {
+    question_ids: HashSet<String>,
+    tags: HashSet<String>,
+}
+
+impl Subscription {
+    fn matches(&self, event: &Event) -> bool {
+        self.question_ids.contains(&event.question_id)
+            || event.tags.iter().any(|tag| self.tags.contains(tag))
+    }
+
+    fn current(&self) -> ServerMessage {
+        let mut question_ids: Vec<String> = self.question_ids.iter().cloned().collect();
+        let mut tags: Vec<String> = self.tags.iter().cloned().collect();
+        question_ids.sort();
+        tags.sort();
+        ServerMessage::Subscribed { question_ids, tags }
+    }
+}
+
+type Sender = SplitSink<WebSocket, Message>;
+
+async fn send(sender: &mut Sender, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text: String = serde_json::to_string(message).expect("server messages serialize");
+    sender.send(Message::Text(text)).await
+}
+
+fn error(message: &str) -> ServerMessage {
+    ServerMessage::Error {
+        message: message.to_string(),
+    }
+}
+
+async fn connection(
+    socket: WebSocket,
+    user: String,
+    log: Arc<EventLog>,
+    relay: Arc<Relay>,
+    shutdown: Shutdown,
+) {
+    let id: u64 = relay.next_connection.fetch_add(1, Ordering::Relaxed);
+    let (mut sender, mut receiver) = socket.split();
+    let (_, _, mut events) = log.subscribe(None);
+    let mut signals: broadcast::Receiver<Signal> = relay.sender.subscribe();
+    let mut subscription: Subscription = Subscription::default();
+    let mut heartbeat: tokio::time::Interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen: Instant = Instant::now();
+    tracing::debug!("websocket {} opened for {}", id, user);
+
+    loop {
+        let sent: Result<(), axum::Error> = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            frame = receiver.next() => {
+                let message: Message = match frame {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => {
+                        tracing::debug!("websocket {}: {}", id, e);
+                        break;
+                    }
+                    None => break,
+                };
+                last_seen = Instant::now();
+                match message {
+                    Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                        Ok(message) => {
+                            handle(message, id, &user, &mut subscription, &relay, &mut sender)
+                                .await
+                        }
+                        Err(e) => send(&mut sender, &error(&e.to_string())).await,
+                    },
+                    Message::Binary(_) => {
+                        send(&mut sender, &error("messages must be JSON text")).await
+                    }
+                    // Pings are answered by axum; pongs only count as activity.
+                    Message::Ping(_) | Message::Pong(_) => Ok(()),
+                    Message::Close(_) => break,
+                }
+            }
+            event = events.recv() => match event {
+                Ok(event) if subscription.matches(&event) => {
+                    let event: Box<Event> = Box::new(Event::clone(&event));
+                    let message: ServerMessage = ServerMessage::Event(event);
+                    send(&mut sender, &message).await
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(_)) => send(&mut sender, &ServerMessage::Lagged).await,
+                Err(RecvError::Closed) => break,
+            },
+            signal = signals.recv() => match signal {
+                Ok(signal)
+                    if signal.from != id
+                        && subscription.question_ids.contains(&signal.question_id) =>
+                {
+                    send(&mut sender, &signal.message).await
+                }
+                // Missed typing notices don't matter.
+                Ok(_) | Err(RecvError::Lagged(_)) => Ok(()),
+                Err(RecvError::Closed) => break,
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > CLIENT_TIMEOUT {
+                    tracing::debug!("websocket {} timed out", id);
+                    break;
+                }
+                sender.send(Message::Ping(Vec::new())).await
+            }
+        };
+        if let Err(e) = sent {
+            tracing::debug!("websocket {}: {}", id, e);
+            break;
+        }
+    }
+
+    for question_id in &subscription.question_ids {
+        relay.leave(id, question_id, &user);
+    }
+    let _ = sender.close().await;
+    tracing::debug!("websocket {} closed", id);
+}
+
+fn presence(question_id: &str, user: &str, online: bool) -> ServerMessage {
+    ServerMessage::Presence {
+        question_id: question_id.to_string(),
+        user: user.to_string(),
+        online,
+    }
+}
+
+async fn handle(
+    message: ClientMessage,
+    id: u64,
+    user: &str,
+    subscription: &mut Subscription,
+    relay: &Relay,
+    sender: &mut Sender,
+) -> Result<(), axum::Error> {
+    match message {
+        ClientMessage::Subscribe { question_ids, tags } => {
+            let total: usize = subscription.question_ids.len()
+                + subscription.tags.len()
+                + question_ids.len()
+                + tags.len();
+            if total > MAX_SUBSCRIPTIONS {
+                let message: String = format!("at most {} subscriptions", MAX_SUBSCRIPTIONS);
+                return send(sender, &error(&message)).await;
+            }
+            let mut snapshot: Vec<ServerMessage> = Vec::new();
+            for question_id in question_ids {
+                if subscription.question_ids.insert(question_id.clone()) {
+                    for other in relay.join(id, &question_id, user) {
+                        snapshot.push(presence(&question_id, &other, true));
+                    }
+                }
+            }
+            subscription.tags.extend(tags);
+            send(sender, &subscription.current()).await?;
+            for message in &snapshot {
+                send(sender, message).await?;
+            }
+            Ok(())
+        }
+        ClientMessage::Unsubscribe { question_ids, tags } => {
+            for question_id in question_ids {
+                if subscription.question_ids.remove(&question_id) {
+                    relay.leave(id, &question_id, user);
+                }
+            }
+            for tag in tags {
+                subscription.tags.remove(&tag);
+            }
+            send(sender, &subscription.current()).await
+        }
+        ClientMessage::Typing { question_id } => {
+            if !subscription.question_ids.contains(&question_id) {
+                return send(sender, &error("subscribe to the question first")).await;
+            }
+            let message: ServerMessage = ServerMessage::Typing {
+                question_id: question_id.clone(),
+                user: user.to_string(),
+            };
+            relay.send(id, &question_id, message);
+            Ok(())
+        }
+        ClientMessage::Ping => send(sender, &ServerMessage::Pong).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::types::question::{Question, QuestionId};
+
+    #[test]
+    fn join_returns_the_others_following() {
+        let relay: Relay = Relay::default();
+        assert!(relay.join(1, "7", "ann").is_empty());
+        assert!(relay.join(2, "8", "bob").is_empty());
+        assert_eq!(relay.join(3, "7", "cat"), vec!["ann"]);
+        // A user's other connections aren't news to them.
+        assert_eq!(relay.join(4, "7", "ann"), vec!["cat"]);
+        assert_eq!(relay.join(5, "7", "dan"), vec!["ann", "cat"]);
+
+        relay.leave(3, "7", "cat");
+        assert_eq!(relay.join(6, "7", "eve"), vec!["ann", "dan"]);
+        relay.leave(2, "8", "bob");
+        assert!(relay.join(7, "8", "fay").is_empty());
+    }
+
+    #[sqlx::test]
+    async fn tag_subscribers_receive_answers(pool: PgPool) {
+        let log: Arc<EventLog> = Arc::default();
+        let store: Store = Store {
+            connection: pool,
+            events: log.clone(),
+            notify: false,
+        };
+        let question: Question = store
+            .add_question(
+                Question {
+                    id: QuestionId(String::new()),
+                    title: "How?".to_string(),
+                    content: "Please help!".to_string(),
+                    tags: Some(HashSet::from(["rust".to_string()])),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        let (_, _, mut receiver) = log.subscribe(None);
+        store.add_answer(&question.id, "Like so.").await.unwrap();
+        let event: Arc<Event> = receiver.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::AnswerCreated);
+
+        // Tags followed, and whether the answer is sent.
+        let cases: [(&[&str], bool); 3] =
+            [(&["rust"], true), (&["go", "rust"], true), (&["go"], false)];
+        for (tags, sent) in cases {
+            let subscription: Subscription = Subscription {
+                question_ids: HashSet::new(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            };
+            assert_eq!(subscription.matches(&event), sent, "{:?}", tags);
+        }
+    }
+}
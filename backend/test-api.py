@@ -28,7 +28,7 @@ def curl(endpoint, data=None, method="GET", expect_error=False, use_token = True
     except HTTPError as e:
         if expect_error:
             r = json.loads(e.read())
-            return (e, r["error"])
+            return (e, r["title"])
         assert False
 
 password = open("db/reg-password.txt", "r").read().strip()
@@ -41,6 +41,7 @@ reg = {
 print("registering: ", end="")
 token_data = curl(
     "/register",
+    method = "POST",
     data = reg,
     use_token = False,
 )
@@ -52,11 +53,13 @@ reg["password"] = ""
 print("testing for registration failure: ", end="")
 e, _ = curl(
     "/register",
+    method = "POST",
     data = reg,
     expect_error = True,
     use_token = False,
 )
-assert str(e) == "HTTP Error 401: Unauthorized"
+# An empty password fails validation before it is checked.
+assert str(e) == "HTTP Error 422: Unprocessable Entity"
 print("failed successfully")
 
 question = {
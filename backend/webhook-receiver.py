@@ -0,0 +1,53 @@
+# Local webhook receiver for trying out deliveries.
+#
+# Register http://localhost:3070/ with POST /api/v1/webhooks, then run this
+# with the secret the server returned:
+#
+#   python3 webhook-receiver.py <secret> [port] [fail-every]
+#
+# Every request's signature is checked and its event printed. With fail-every
+# set to n, every nth delivery gets a 503 so retries can be watched.
+
+import hashlib, hmac, json, sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+secret = sys.argv[1].encode("utf-8")
+port = int(sys.argv[2]) if len(sys.argv) > 2 else 3070
+fail_every = int(sys.argv[3]) if len(sys.argv) > 3 else 0
+received = 0
+
+def expected_signature(timestamp, body):
+    mac = hmac.new(secret, f"{timestamp}.".encode("utf-8") + body, hashlib.sha256)
+    return "sha256=" + mac.hexdigest()
+
+class Receiver(BaseHTTPRequestHandler):
+    def do_POST(self):
+        global received
+        received += 1
+        body = self.rfile.read(int(self.headers["Content-Length"]))
+        timestamp = self.headers["Webhook-Timestamp"]
+        signature = self.headers["Webhook-Signature"]
+        if not hmac.compare_digest(signature, expected_signature(timestamp, body)):
+            print("bad signature on delivery", self.headers["Webhook-Id"])
+            self.send_response(401)
+            self.end_headers()
+            return
+        event = json.loads(body)
+        print(
+            f"delivery {self.headers['Webhook-Id']}:",
+            self.headers["Webhook-Event"],
+            "for question",
+            event["question_id"],
+        )
+        if fail_every and received % fail_every == 0:
+            print("  answering 503")
+            self.send_response(503)
+        else:
+            self.send_response(204)
+        self.end_headers()
+
+    def log_message(self, format, *args):
+        pass
+
+print(f"receiving webhooks on http://localhost:{port}/")
+HTTPServer(("localhost", port), Receiver).serve_forever()
@@ -7,11 +7,9 @@ use cookie::*;
 use finder::*;
 use question::*;
 
-use std::collections::HashSet;
-
 extern crate serde;
 // use gloo_console::log;
-use gloo_net::http;
+use question_api::{Client, ClientError};
 extern crate wasm_bindgen_futures;
 use wasm_cookies as cookies;
 use web_sys::HtmlTextAreaElement;
@@ -19,13 +17,13 @@ use yew::prelude::*;
 
 /// Represents the result of a question operation.
 /// It can either be a successful QuestionStruct or an error.
-pub type QuestionResult = Result<QuestionStruct, gloo_net::Error>;
+pub type QuestionResult = Result<QuestionStruct, ClientError>;
 
 /// Represents the main application state.
-/// It contains a cookie string and a question result.
+/// It contains a cookie string and a question result, which is `None` while loading.
 struct App {
     cookie: String,
-    question: QuestionResult,
+    question: Option<QuestionResult>,
 }
 
 /// Represents the different types of messages that can be processed by the application.
@@ -44,7 +42,7 @@ impl App {
     ///
     /// This function sends a future to the component's link, which will be resolved when the question is retrieved.
     fn refresh_question(ctx: &Context<Self>, key: Option<String>) {
-        let got_question = QuestionStruct::get_question(key);
+        let got_question = get_question(key);
         ctx.link().send_future(got_question);
     }
 }
@@ -65,9 +63,10 @@ impl Component for App {
     fn create(ctx: &Context<Self>) -> Self {
         let cookie: String = acquire_cookie();
         App::refresh_question(ctx, None);
-        let question: Result<_, gloo_net::Error> =
-            Err(gloo_net::Error::GlooError("Loading Question…".to_string()));
-        Self { cookie, question }
+        Self {
+            cookie,
+            question: None,
+        }
     }
 
     /// Handles the messages sent to the App component.
@@ -83,7 +82,7 @@ impl Component for App {
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::GotQuestion(question) => {
-                self.question = question;
+                self.question = Some(question);
                 true
             }
             Msg::GetQuestion(key) => {
@@ -105,17 +104,22 @@ impl Component for App {
     /// * `Html` - The HTML representation of the App component.
     fn view(&self, ctx: &Context<Self>) -> Html {
         let cookie: &String = &self.cookie;
-        let question: &Result<_, gloo_net::Error> = &self.question;
+        let question: &Option<QuestionResult> = &self.question;
         html! {
         <>
             <h1>{ "question" }</h1>
             if false {
                 {render_cookie(cookie)}
             }
-            if let Ok(ref question) = question {
+            if question.is_none() {
+                <div>
+                    <span>{"Loading Question…"}</span>
+                </div>
+            }
+            if let Some(Ok(ref question)) = question {
                 <Question question={question.clone()}/>
             }
-            if let Err(ref error) = question {
+            if let Some(Err(ref error)) = question {
                 <div>
                     <span class="error">{format!("Server Error: {error}")}</span>
                 </div>
@@ -1,63 +1,47 @@
 //! `question.rs`
 //!
-//! This module contains the `QuestionStruct` struct and its associated functions.
+//! This module contains the `Question` component and the function that fetches its question.
 //!
-//! The `QuestionStruct` struct represents a question with an id, title, content, and optional tags.
-//! It is derived from the Properties, Clone, PartialEq, and Deserialize traits.
+//! `QuestionStruct` is the shared `question_api::Question`: a question with an id, title,
+//! content, and optional tags, exactly as the server sends it.
 //!
-//! The `get_question` function is an asynchronous function that retrieves a question from the server.
+//! The `get_question` function is an asynchronous function that retrieves a question from the server
+//! through the typed `question_api::Client`.
 //! It takes an optional string as an argument, which may be used to get a specific question.
-//! The function sends a GET request to the server and returns a message indicating the result of the operation.
+//! The function returns a message indicating the result of the operation.
 //!
 //! This module is particularly useful for managing questions in a Q&A application.
 
 use crate::*;
 
-#[derive(Properties, Clone, PartialEq, serde::Deserialize)]
+pub use question_api::question::format_tags;
 /// Represents a question with an id, title, content, and optional tags.
-pub struct QuestionStruct {
-    pub id: String,
-    pub title: String,
-    pub content: String,
-    pub tags: Option<HashSet<String>>,
-}
+pub use question_api::Question as QuestionStruct;
 
-impl QuestionStruct {
-    /// Retrieves a question from the server.
-    ///
-    /// # Arguments
-    ///
-    /// * `key` - An optional string that may be used to get a specific question.
-    ///
-    /// # Returns
-    ///
-    /// * `Msg` - A message indicating the result of the operation.
-    pub async fn get_question(key: Option<String>) -> Msg {
-        let request: String = match &key {
-            None => "http://localhost:3000/api/v1/question".to_string(),
-            Some(ref key) => format!("http://localhost:3060/api/v1/question/{}", key,),
-        };
-        let response: Result<http::Response, gloo_net::Error> =
-            http::Request::get(&request).send().await;
-        match response {
-            Err(e) => Msg::GotQuestion(Err(e)),
-            Ok(data) => Msg::GotQuestion(data.json().await),
-        }
-    }
+/// The client for the server this page was loaded from.
+fn client() -> Client {
+    let origin: String = web_sys::window()
+        .and_then(|window| window.location().origin().ok())
+        .unwrap_or_default();
+    Client::new(origin)
 }
 
-/// Formats the tags of a question into a comma-separated string.
+/// Retrieves a question from the server.
 ///
 /// # Arguments
 ///
-/// * `tags` - A reference to a HashSet of tags.
+/// * `key` - An optional string that may be used to get a specific question.
 ///
 /// # Returns
 ///
-/// * `String` - A comma-separated string of tags.
-pub fn format_tags(tags: &HashSet<String>) -> String {
-    let taglist: Vec<&str> = tags.iter().map(String::as_ref).collect();
-    taglist.join(", ")
+/// * `Msg` - A message indicating the result of the operation.
+pub async fn get_question(key: Option<String>) -> Msg {
+    let client: Client = client();
+    let question: QuestionResult = match &key {
+        None => client.random_question().await,
+        Some(ref key) => client.question(key).await,
+    };
+    Msg::GotQuestion(question)
 }
 
 #[derive(Properties, Clone, PartialEq, serde::Deserialize)]
@@ -0,0 +1,61 @@
+//! Answers: the `/api/v1` record and the `/api/v2` resource.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::question::QuestionId;
+#[cfg(feature = "validator")]
+use crate::validation::not_blank;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "validator", derive(validator::Validate))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Answer {
+    #[cfg_attr(feature = "utoipa", schema(value_type = String, example = "1"))]
+    pub id: AnswerId,
+    #[cfg_attr(feature = "utoipa", schema(example = "Like this."))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 10000), custom(function = "not_blank"))
+    )]
+    pub content: String,
+    #[cfg_attr(feature = "utoipa", schema(value_type = String, example = "1"))]
+    pub question_id: QuestionId,
+}
+
+/// An answer as submitted; the question comes from the path.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "validator", derive(validator::Validate))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct NewAnswer {
+    #[cfg_attr(feature = "utoipa", schema(example = "Like this."))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 10000), custom(function = "not_blank"))
+    )]
+    pub content: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnswerId(pub String);
+
+impl fmt::Display for AnswerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// An answer as `/api/v2` returns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct AnswerResource {
+    #[cfg_attr(feature = "utoipa", schema(example = 3))]
+    pub id: i64,
+    #[cfg_attr(feature = "utoipa", schema(example = 7))]
+    pub question_id: i64,
+    #[cfg_attr(feature = "utoipa", schema(example = "Like this."))]
+    pub content: String,
+    pub created_on: DateTime<Utc>,
+}
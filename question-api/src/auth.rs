@@ -0,0 +1,49 @@
+//! Registration, and the access token it returns. Send the token as `Authorization: Bearer`
+//! or in the `X-API-Key` header.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "validator")]
+use crate::validation::not_blank;
+
+/// Header carrying the access token for clients that can't send `Authorization`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "validator", derive(validator::Validate))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Registration {
+    #[cfg_attr(feature = "utoipa", schema(example = "Jane Doux"))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 255), custom(function = "not_blank"))
+    )]
+    pub full_name: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "janedoux@example.org"))]
+    #[cfg_attr(feature = "validator", validate(email, length(max = 255)))]
+    pub email: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "password123"))]
+    #[cfg_attr(feature = "validator", validate(length(min = 1)))]
+    pub password: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AuthBody {
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "eyJ0eXAiOiJKV1QiLCJhbGciOiJIUzUxMiJ9.eyJzdWIiOiJKYW5lIERvdXgifQ.c2ln")
+    )]
+    pub access_token: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "Bearer"))]
+    pub token_type: String,
+}
+
+impl AuthBody {
+    pub fn new(access_token: String) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer".to_string(),
+        }
+    }
+}
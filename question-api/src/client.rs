@@ -0,0 +1,288 @@
+//! A typed async client for the question service. It runs natively (tokio) and in the
+//! browser (wasm32, over `fetch`), so the frontend and integration tests speak the API the
+//! same way.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), question_api::ClientError> {
+//! use question_api::{Client, QuestionInput, Registration};
+//!
+//! let client = Client::new("http://localhost:3000");
+//! let registration = Registration {
+//!     full_name: "Jane Doux".to_string(),
+//!     email: "janedoux@example.org".to_string(),
+//!     password: "password123".to_string(),
+//! };
+//! let token = client.register(&registration).await?.access_token;
+//! let client = client.with_token(token);
+//! let input = QuestionInput {
+//!     title: "How?".to_string(),
+//!     content: "Please help!".to_string(),
+//!     ..QuestionInput::default()
+//! };
+//! let question = client.create_question(&input).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::answer::{AnswerResource, NewAnswer};
+use crate::auth::{AuthBody, Registration};
+use crate::page::{Data, Page};
+use crate::problem::Problem;
+use crate::question::{Question, QuestionInput, QuestionResource};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The request never got a response, or the response wasn't what the API promises.
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The service refused the request.
+    #[error("{0}")]
+    Problem(Problem),
+}
+
+impl ClientError {
+    /// The status the service answered with, if it answered.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Http(e) => e.status().map(|status| status.as_u16()),
+            ClientError::Problem(problem) => Some(problem.status),
+        }
+    }
+}
+
+/// Which part of a list to fetch. Unset members take the server's defaults.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    /// Only questions with this tag; ignored for answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    token: Option<String>,
+}
+
+impl Client {
+    /// A client for the service at `base_url`, e.g. `http://localhost:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let base_url: String = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            base_url,
+            http: reqwest::Client::new(),
+            token: None,
+        }
+    }
+
+    /// This client, sending `token` as a bearer token.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request: RequestBuilder = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// The response, or the problem it reports.
+    async fn send(request: RequestBuilder) -> Result<Response, ClientError> {
+        let response: Response = request.send().await?;
+        let status: StatusCode = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let problem: Problem = response.json().await.unwrap_or_else(|_| Problem {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            trace_id: String::new(),
+            errors: None,
+        });
+        Err(ClientError::Problem(problem))
+    }
+
+    async fn fetch<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ClientError> {
+        Ok(Self::send(request).await?.json().await?)
+    }
+
+    async fn data<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ClientError> {
+        let data: Data<T> = Self::fetch(request).await?;
+        Ok(data.data)
+    }
+
+    /// `POST /api/v1/register`: a token for `registration`.
+    pub async fn register(&self, registration: &Registration) -> Result<AuthBody, ClientError> {
+        Self::fetch(
+            self.request(Method::POST, "/api/v1/register")
+                .json(registration),
+        )
+        .await
+    }
+
+    /// `GET /api/v1/question`: a random question.
+    pub async fn random_question(&self) -> Result<Question, ClientError> {
+        Self::fetch(self.request(Method::GET, "/api/v1/question")).await
+    }
+
+    /// `GET /api/v1/question/{id}`.
+    pub async fn question(&self, id: &str) -> Result<Question, ClientError> {
+        Self::fetch(self.request(Method::GET, &format!("/api/v1/question/{}", id))).await
+    }
+
+    /// `GET /api/v2/questions`: a page of questions, in id order.
+    pub async fn list_questions(
+        &self,
+        params: &ListParams,
+    ) -> Result<Page<QuestionResource>, ClientError> {
+        Self::fetch(self.request(Method::GET, "/api/v2/questions").query(params)).await
+    }
+
+    /// `GET /api/v2/questions/{id}`.
+    pub async fn get_question(&self, id: i64) -> Result<QuestionResource, ClientError> {
+        Self::data(self.request(Method::GET, &format!("/api/v2/questions/{}", id))).await
+    }
+
+    /// `POST /api/v2/questions`.
+    pub async fn create_question(
+        &self,
+        input: &QuestionInput,
+    ) -> Result<QuestionResource, ClientError> {
+        Self::data(self.request(Method::POST, "/api/v2/questions").json(input)).await
+    }
+
+    /// `PUT /api/v2/questions/{id}`.
+    pub async fn update_question(
+        &self,
+        id: i64,
+        input: &QuestionInput,
+    ) -> Result<QuestionResource, ClientError> {
+        let path: String = format!("/api/v2/questions/{}", id);
+        Self::data(self.request(Method::PUT, &path).json(input)).await
+    }
+
+    /// `DELETE /api/v2/questions/{id}`, with its answers.
+    pub async fn delete_question(&self, id: i64) -> Result<(), ClientError> {
+        let path: String = format!("/api/v2/questions/{}", id);
+        Self::send(self.request(Method::DELETE, &path)).await?;
+        Ok(())
+    }
+
+    /// `GET /api/v2/questions/{id}/answers`: a page of the question's answers, oldest first.
+    pub async fn list_answers(
+        &self,
+        question_id: i64,
+        params: &ListParams,
+    ) -> Result<Page<AnswerResource>, ClientError> {
+        let path: String = format!("/api/v2/questions/{}/answers", question_id);
+        Self::fetch(self.request(Method::GET, &path).query(params)).await
+    }
+
+    /// `POST /api/v2/questions/{id}/answers`.
+    pub async fn create_answer(
+        &self,
+        question_id: i64,
+        answer: &NewAnswer,
+    ) -> Result<AnswerResource, ClientError> {
+        let path: String = format!("/api/v2/questions/{}/answers", question_id);
+        Self::data(self.request(Method::POST, &path).json(answer)).await
+    }
+
+    /// `GET /api/v2/answers/{id}`.
+    pub async fn get_answer(&self, id: i64) -> Result<AnswerResource, ClientError> {
+        Self::data(self.request(Method::GET, &format!("/api/v2/answers/{}", id))).await
+    }
+
+    /// The page a list's `links` point to, such as `page.links.next`.
+    pub async fn follow<T: DeserializeOwned>(&self, link: &str) -> Result<Page<T>, ClientError> {
+        Self::fetch(self.request(Method::GET, link)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_urls() {
+        let filtered: ListParams = ListParams {
+            limit: Some(2),
+            offset: None,
+            tag: Some("a b&c".to_string()),
+        };
+        let unset: ListParams = ListParams::default();
+        let cases: [(&str, &str, Option<&ListParams>, &str); 5] = [
+            (
+                "http://h",
+                "/api/v2/questions",
+                None,
+                "http://h/api/v2/questions",
+            ),
+            (
+                "http://h/",
+                "/api/v2/questions",
+                None,
+                "http://h/api/v2/questions",
+            ),
+            (
+                "http://h/base/",
+                "/api/v2/questions",
+                Some(&filtered),
+                "http://h/base/api/v2/questions?limit=2&tag=a+b%26c",
+            ),
+            (
+                "http://h",
+                "/api/v2/questions",
+                Some(&unset),
+                "http://h/api/v2/questions",
+            ),
+            // A page's links already carry their query.
+            (
+                "http://h",
+                "/api/v2/questions?limit=2&offset=2",
+                None,
+                "http://h/api/v2/questions?limit=2&offset=2",
+            ),
+        ];
+        for (base_url, path, params, expected) in cases {
+            let mut request: RequestBuilder = Client::new(base_url).request(Method::GET, path);
+            if let Some(params) = params {
+                request = request.query(params);
+            }
+            assert_eq!(
+                request.build().unwrap().url().as_str(),
+                expected,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn sends_the_token() {
+        let authorization = |client: Client| {
+            let request = client.request(Method::GET, "/").build().unwrap();
+            request.headers().get("authorization").cloned()
+        };
+        assert_eq!(authorization(Client::new("http://h")), None);
+        let client: Client = Client::new("http://h").with_token("t0k");
+        assert_eq!(authorization(client).unwrap(), "Bearer t0k");
+    }
+}
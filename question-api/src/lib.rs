@@ -0,0 +1,31 @@
+//! # Question API
+//!
+//! The types the question service sends and accepts, shared by the backend, the frontend and
+//! integration tests so that each side can't drift from the other, and with the default
+//! `client` feature a typed async [`Client`] for the API.
+//!
+//! * [`question`] and [`answer`] hold the `/api/v1` records and their `/api/v2` resources.
+//! * [`page`] holds the `/api/v2` envelopes: one resource, or a page of a list.
+//! * [`problem`] holds the problem documents every error is reported as.
+//! * [`auth`] holds registration and the token it returns.
+//!
+//! The crate builds for `wasm32-unknown-unknown`; the `utoipa`, `validator` and `sqlx`
+//! features add the OpenAPI, validation and row-mapping derives the backend uses.
+
+pub mod answer;
+pub mod auth;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod page;
+pub mod problem;
+pub mod question;
+#[cfg(feature = "validator")]
+pub mod validation;
+
+pub use answer::{Answer, AnswerId, AnswerResource, NewAnswer};
+pub use auth::{AuthBody, Registration};
+#[cfg(feature = "client")]
+pub use client::{Client, ClientError, ListParams};
+pub use page::{Data, Links, Page, Pagination};
+pub use problem::{FieldError, Problem};
+pub use question::{Question, QuestionId, QuestionInput, QuestionResource};
@@ -0,0 +1,115 @@
+//! The `/api/v2` response envelopes: `{"data": ...}` for one resource, and for a list also
+//! where the page sits in it and links to its neighbours.
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "utoipa")]
+use crate::{answer::AnswerResource, question::QuestionResource};
+
+/// One resource.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "utoipa",
+    aliases(QuestionData = Data<QuestionResource>, AnswerData = Data<AnswerResource>)
+)]
+pub struct Data<T> {
+    pub data: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Pagination {
+    #[cfg_attr(feature = "utoipa", schema(example = 20))]
+    pub limit: i64,
+    #[cfg_attr(feature = "utoipa", schema(example = 0))]
+    pub offset: i64,
+    /// Items on all pages.
+    #[cfg_attr(feature = "utoipa", schema(example = 42))]
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Links {
+    #[serde(rename = "self")]
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "/api/v2/questions?limit=20&offset=20")
+    )]
+    pub this: String,
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "/api/v2/questions?limit=20&offset=40")
+    )]
+    pub next: Option<String>,
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "/api/v2/questions?limit=20&offset=0")
+    )]
+    pub prev: Option<String>,
+}
+
+/// One page of a list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(
+    feature = "utoipa",
+    aliases(QuestionPage = Page<QuestionResource>, AnswerPage = Page<AnswerResource>)
+)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub pagination: Pagination,
+    pub links: Links,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::question::QuestionResource;
+    use chrono::{TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn page_round_trips() {
+        let page: Page<QuestionResource> = Page {
+            data: vec![QuestionResource {
+                id: 7,
+                title: "How?".to_string(),
+                content: "Please help!".to_string(),
+                tags: vec!["general".to_string()],
+                created_on: Utc.with_ymd_and_hms(2026, 10, 19, 12, 0, 0).unwrap(),
+            }],
+            pagination: Pagination {
+                limit: 1,
+                offset: 1,
+                total: 3,
+            },
+            links: Links {
+                this: "/api/v2/questions?limit=1&offset=1".to_string(),
+                next: Some("/api/v2/questions?limit=1&offset=2".to_string()),
+                prev: Some("/api/v2/questions?limit=1&offset=0".to_string()),
+            },
+        };
+        let wire: Value = json!({
+            "data": [{
+                "id": 7,
+                "title": "How?",
+                "content": "Please help!",
+                "tags": ["general"],
+                "created_on": "2026-10-19T12:00:00Z",
+            }],
+            "pagination": {"limit": 1, "offset": 1, "total": 3},
+            "links": {
+                "self": "/api/v2/questions?limit=1&offset=1",
+                "next": "/api/v2/questions?limit=1&offset=2",
+                "prev": "/api/v2/questions?limit=1&offset=0",
+            },
+        });
+        assert_eq!(serde_json::to_value(&page).unwrap(), wire);
+        assert_eq!(
+            serde_json::from_value::<Page<QuestionResource>>(wire).unwrap(),
+            page
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Errors: every error the service returns is an RFC 7807 problem document with content type
+//! `application/problem+json`. The backend builds them with its own `Problem`, which renders
+//! the response; this is the document as a client reads it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An RFC 7807 problem document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    /// URI identifying the kind of problem, or `about:blank` when the status says it all.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: Option<String>,
+    /// Path of the request that failed.
+    pub instance: Option<String>,
+    /// Identifier to quote when reporting the failure; it also appears in the server logs.
+    #[serde(default)]
+    pub trace_id: String,
+    /// Per-field errors of a rejected payload.
+    pub errors: Option<Vec<FieldError>>,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.title)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// One failed constraint on one field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    #[cfg_attr(feature = "utoipa", schema(example = "title"))]
+    pub field: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "length"))]
+    pub code: String,
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "must be between 1 and 255 characters")
+    )]
+    pub message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_backends_problems() {
+        let cases: [(&str, Problem); 2] = [
+            (
+                r#"{"type": "about:blank", "title": "Not Found", "status": 404}"#,
+                Problem {
+                    kind: "about:blank".to_string(),
+                    title: "Not Found".to_string(),
+                    status: 404,
+                    detail: None,
+                    instance: None,
+                    trace_id: String::new(),
+                    errors: None,
+                },
+            ),
+            (
+                r#"{"type": "https://question.po8.org/problems/validation", "title": "Invalid",
+                    "status": 422, "instance": "/api/v2/questions", "trace_id": "9b2f",
+                    "errors": [{"field": "title", "code": "length", "message": "too long"}]}"#,
+                Problem {
+                    kind: "https://question.po8.org/problems/validation".to_string(),
+                    title: "Invalid".to_string(),
+                    status: 422,
+                    detail: None,
+                    instance: Some("/api/v2/questions".to_string()),
+                    trace_id: "9b2f".to_string(),
+                    errors: Some(vec![FieldError {
+                        field: "title".to_string(),
+                        code: "length".to_string(),
+                        message: "too long".to_string(),
+                    }]),
+                },
+            ),
+        ];
+        for (json, expected) in cases {
+            let problem: Problem = serde_json::from_str(json).unwrap();
+            assert_eq!(problem, expected);
+            let again: String = serde_json::to_string(&problem).unwrap();
+            assert_eq!(serde_json::from_str::<Problem>(&again).unwrap(), problem);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Questions: the `/api/v1` record, whose id the client picks, and the `/api/v2` resource,
+//! whose id the server assigns.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+#[cfg(feature = "validator")]
+use crate::validation::{not_blank, valid_tags};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "validator", derive(validator::Validate))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Question {
+    #[cfg_attr(feature = "utoipa", schema(value_type = String, example = "1"))]
+    pub id: QuestionId,
+    #[cfg_attr(feature = "utoipa", schema(example = "How?"))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 255), custom(function = "not_blank"))
+    )]
+    pub title: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "Please help!"))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 10000), custom(function = "not_blank"))
+    )]
+    pub content: String,
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["general"])))]
+    #[cfg_attr(feature = "validator", validate(custom(function = "valid_tags")))]
+    pub tags: Option<HashSet<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuestionId(pub String);
+
+impl fmt::Display for QuestionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Question {
+    pub fn new(id: QuestionId, title: &str, content: &str, tags: &[&str]) -> Self {
+        let tags: Option<HashSet<String>> = if tags.is_empty() {
+            None
+        } else {
+            Some(tags.iter().copied().map(String::from).collect())
+        };
+        Self {
+            id,
+            title: title.into(),
+            content: content.into(),
+            tags,
+        }
+    }
+}
+
+/// The tags joined by commas, for display.
+pub fn format_tags(tags: &HashSet<String>) -> String {
+    let taglist: Vec<&str> = tags.iter().map(String::as_ref).collect();
+    taglist.join(", ")
+}
+
+impl From<&Question> for String {
+    fn from(question: &Question) -> Self {
+        let mut text: String = "Question:\n".into();
+        text += &format!("{}.\n", question.title);
+        text += &format!("{}\n", question.content);
+        text += "\n";
+
+        let mut annote: Vec<String> = vec![format!("id: {:?}", question.id)];
+        if let Some(tags) = &question.tags {
+            annote.push(format!("tags: {}", format_tags(tags)));
+        }
+        let annote: String = annote.join("; ");
+        text += &format!("[{}]\n", annote);
+        text
+    }
+}
+
+/// A question as `/api/v2` returns it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct QuestionResource {
+    #[cfg_attr(feature = "utoipa", schema(example = 7))]
+    pub id: i64,
+    #[cfg_attr(feature = "utoipa", schema(example = "How?"))]
+    pub title: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "Please help!"))]
+    pub content: String,
+    /// Sorted tags.
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["general"])))]
+    pub tags: Vec<String>,
+    pub created_on: DateTime<Utc>,
+}
+
+/// A question as submitted to `/api/v2`; the server assigns its id.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "validator", derive(validator::Validate))]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct QuestionInput {
+    #[cfg_attr(feature = "utoipa", schema(example = "How?"))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 255), custom(function = "not_blank"))
+    )]
+    pub title: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "Please help!"))]
+    #[cfg_attr(
+        feature = "validator",
+        validate(length(min = 1, max = 10000), custom(function = "not_blank"))
+    )]
+    pub content: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["general"])))]
+    #[cfg_attr(feature = "validator", validate(custom(function = "valid_tags")))]
+    pub tags: HashSet<String>,
+}
+
+impl QuestionInput {
+    /// The `/api/v1` form of the question once it has been given `id`.
+    pub fn question(&self, id: i64) -> Question {
+        Question {
+            id: QuestionId(id.to_string()),
+            title: self.title.clone(),
+            content: self.content.clone(),
+            tags: Some(self.tags.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_without_tags() {
+        let input: QuestionInput =
+            serde_json::from_str(r#"{"title": "How?", "content": "Please help!"}"#).unwrap();
+        assert!(input.tags.is_empty());
+        let question: Question = input.question(7);
+        assert_eq!(question.id, QuestionId("7".to_string()));
+        let json: String = serde_json::to_string(&question).unwrap();
+        assert_eq!(serde_json::from_str::<Question>(&json).unwrap(), question);
+    }
+}
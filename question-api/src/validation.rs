@@ -0,0 +1,48 @@
+//! Custom constraints for the `validator` derives on the payload types.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use validator::ValidationError;
+
+/// Most tags a question may carry.
+pub const MAX_TAGS: usize = 10;
+/// Longest tag accepted.
+pub const MAX_TAG_LEN: usize = 32;
+
+/// Rejects strings that are empty once surrounding whitespace is trimmed.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message(Cow::from("must not be blank")));
+    }
+    Ok(())
+}
+
+/// Tags are limited in number and must be short lowercase words joined by dashes,
+/// e.g. `rust` or `error-handling`.
+pub fn valid_tags(tags: &HashSet<String>) -> Result<(), ValidationError> {
+    if tags.len() > MAX_TAGS {
+        let mut error: ValidationError = ValidationError::new("count")
+            .with_message(Cow::from(format!("at most {} tags are allowed", MAX_TAGS)));
+        error.add_param(Cow::from("max"), &MAX_TAGS);
+        return Err(error);
+    }
+    let well_formed = |tag: &String| {
+        !tag.is_empty()
+            && tag.len() <= MAX_TAG_LEN
+            && !tag.starts_with('-')
+            && !tag.ends_with('-')
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    if let Some(tag) = tags.iter().find(|tag| !well_formed(tag)) {
+        let mut error: ValidationError =
+            ValidationError::new("format").with_message(Cow::from(format!(
+                "tags must be 1-{} lowercase letters, digits or inner dashes",
+                MAX_TAG_LEN
+            )));
+        error.add_param(Cow::from("value"), tag);
+        return Err(error);
+    }
+    Ok(())
+}